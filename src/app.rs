@@ -1,63 +1,709 @@
-use crate::ssh_config::{SshConfigFile, SshHostEntry};
+use crate::clipboard;
+use crate::keymap::Keymap;
+use crate::known_hosts;
+use crate::fuzzy;
+use crate::probe::{self, ProbeResult, Prober};
+use crate::settings::{self, AppSettings, HostColor, LaunchMode, SavedState};
+use crate::tree::{self, ListRow};
+use crate::ssh_config::{expand_tilde, export_hosts, next_free_alias, normalize_forward, parse_port, split_host_port, validate_forward_spec, validate_pattern, MatchEntry, SshConfigFile, SshHostEntry};
 use crate::ui::UiAction;
 use anyhow::{Context, Result};
-use crossterm::event::{DisableMouseCapture, EnableMouseCapture};
+use crossterm::cursor::Show;
+use crossterm::event::{self, DisableMouseCapture, EnableMouseCapture};
 use crossterm::execute;
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen};
 use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Margin, Position, Rect};
+use ratatui::style::Color;
 use ratatui::Terminal;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
 use std::io;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::panic;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Once};
+use std::time::{Duration, Instant};
 
-pub fn run() -> Result<()> {
-    let mut ssh_cfg = SshConfigFile::load_default()?;
-    let mut state = AppState::new(ssh_cfg.list_hosts());
+/// Terminal the UI draws on: stdout, or stderr when stdout carries `--print` output.
+type Tui = Terminal<CrosstermBackend<Box<dyn io::Write>>>;
 
-    // Terminal setup
+/// Command-line choices for one run of the picker.
+pub struct RunOptions {
+    pub config_path: PathBuf,
+    /// Choosing a host prints its name instead of launching ssh.
+    pub print_only: bool,
+    /// Refuse every action that would change the ssh config.
+    pub read_only: bool,
+}
+
+/// Exit code after a host was launched (or printed) during the session.
+pub const EXIT_LAUNCHED: i32 = 0;
+/// Exit code when the picker was closed without launching anything, matching
+/// the shell's code for a SIGINT. Errors exit with 1.
+pub const EXIT_CANCELED: i32 = 130;
+
+/// Run the picker. Returns the process exit code: `EXIT_LAUNCHED` if a host was
+/// launched or printed, otherwise `EXIT_CANCELED`.
+pub fn run(options: RunOptions) -> Result<i32> {
+    let print_only = options.print_only;
+    let (settings, settings_warnings) = settings::load_or_default();
+
+    // Terminal setup, before the config is read so a slow load shows a spinner
+    let interrupted = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register(signal, Arc::clone(&interrupted))?;
+    }
+    install_panic_hook(print_only);
     enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
+    let mut out: Box<dyn io::Write> = if print_only { Box::new(io::stderr()) } else { Box::new(io::stdout()) };
+    execute!(out, EnterAlternateScreen, EnableMouseCapture)?;
+    TERMINAL_ACTIVE.store(true, Ordering::SeqCst);
+    let backend = CrosstermBackend::new(out);
     let mut terminal = Terminal::new(backend)?;
+    let mut ssh_cfg = match load_config(&mut terminal, options.config_path, &interrupted) {
+        Ok(Some(cfg)) => cfg,
+        Ok(None) => {
+            teardown_terminal(&mut terminal)?;
+            return Ok(EXIT_CANCELED);
+        }
+        Err(e) => {
+            teardown_terminal(&mut terminal)?;
+            return Err(e);
+        }
+    };
+    let mut state = AppState::new(ssh_cfg.list_hosts(), settings);
+    if !settings_warnings.is_empty() {
+        let warnings = settings_warnings.into_iter().chain(state.status_message.take());
+        state.set_status(StatusKind::Failure, warnings.collect::<Vec<_>>().join("; "));
+    }
+    state.read_only |= options.read_only;
+    state.ssh_config = ssh_cfg.ssh_flag_path().map(Path::to_path_buf);
+    ssh_cfg.option_order = state.settings.option_order;
+    let skipped = ssh_cfg.parse_warnings().len();
+    if skipped > 0 {
+        let hint = format!("{} config line(s) could not be read; press {} to list them", skipped, state.keymap.label("ToggleDiagnostics"));
+        let warnings = state.status_message.take().into_iter().chain([hint]);
+        state.status_message = Some(warnings.collect::<Vec<_>>().join("; "));
+    }
+    let diagnostics = ssh_cfg.validate_config();
+    if !diagnostics.is_empty() {
+        let merge_hint = format!("press {} to keep only the first block of each", state.keymap.label("MergeDuplicates"));
+        let warnings = state.status_message.take().into_iter().chain(diagnostics).chain([merge_hint]);
+        state.status_message = Some(warnings.collect::<Vec<_>>().join("; "));
+    }
+    state.saved = settings::load_state();
+    state.resort();
+    if state.settings.remember_last_host {
+        // A host that no longer exists leaves the selection at the top
+        if let Some(pattern) = state.saved.last_host.clone() {
+            state.select_pattern(&pattern);
+        }
+    }
+    // Restored on every way out of the loop, errors included, so a failed
+    // write never leaves the shell in raw mode on the alternate screen
+    let session = run_loop(&mut terminal, &mut state, &mut ssh_cfg, &interrupted, print_only);
+    let restored = if TERMINAL_ACTIVE.load(Ordering::SeqCst) { teardown_terminal(&mut terminal) } else { Ok(()) };
+    let (printed, mut launched) = session?;
+    restored?;
+    // The footer is gone by now, so a failed save is reported on stderr
+    if let Some(pattern) = state.selected_host().map(|h| h.pattern.clone()) {
+        if let Err(e) = remember_host(&mut state, &pattern) {
+            eprintln!("ssh-picker: could not save picker state: {:#}", e);
+        }
+    }
+    // Commands that could not reach the clipboard are handed over on stdout instead
+    // (stderr when stdout is reserved for the chosen host)
+    for line in &state.pending_output {
+        if print_only {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+    }
+    if let Some(host) = printed {
+        println!("{}", host);
+        launched = true;
+    }
+    Ok(if launched { EXIT_LAUNCHED } else { EXIT_CANCELED })
+}
+
+/// The picker's event loop, from the first draw until the user quits or
+/// chooses a host. Returns the name to print for `--print`, and whether a
+/// host was launched.
+fn run_loop(
+    terminal: &mut Tui,
+    state: &mut AppState,
+    ssh_cfg: &mut SshConfigFile,
+    interrupted: &AtomicBool,
+    print_only: bool,
+) -> Result<(Option<String>, bool)> {
+    let prober = Prober::default();
+    let mut printed = None;
+    let mut launched = false;
+
+    let mut last_auto_probe: Option<Instant> = None;
+    let mut watch = ConfigWatch::default();
 
     // Main loop
     loop {
-        if state.needs_full_redraw {
-            terminal.clear()?;
-            state.needs_full_redraw = false;
+        // SIGINT or SIGTERM (Ctrl-C itself is a key in raw mode) closes the
+        // picker the same way q does
+        if interrupted.load(Ordering::SeqCst) {
+            break;
+        }
+        pump_probes(&prober, state, &mut last_auto_probe);
+        pump_watch(&mut watch, state, ssh_cfg);
+        // Keys typed faster than a redraw are read before filtering, so a burst
+        // of them filters the list once
+        let typing_ahead = state.filter_pending && event::poll(Duration::ZERO)?;
+        if !typing_ahead {
+            state.expire_status();
+            state.flush_filter();
+            if state.needs_full_redraw {
+                terminal.clear()?;
+                state.needs_full_redraw = false;
+            }
+            terminal.draw(|f| crate::ui::draw_ui(f, state))?;
         }
-        terminal.draw(|f| crate::ui::draw_ui(f, &state))?;
 
-        match ui::read_event(&state.mode)? {
-            crate::ui::Event::Action(action) => match handle_action(action, &mut state, &mut ssh_cfg)? {
+        match ui::read_event(&state.mode, &state.keymap)? {
+            crate::ui::Event::Action(action) => match handle_action(action, state, ssh_cfg)? {
                 LoopControl::Continue => {}
                 LoopControl::Exit => break,
-                LoopControl::Launch(host) => {
-                    // Tear down TUI before launching ssh
-                    teardown_terminal(&mut terminal)?;
-                    launch_ssh(&host)?;
-                    // Re-init terminal to return to app after ssh exits
-                    reinit_terminal(&mut terminal)?;
+                LoopControl::Launch { host, .. } if print_only => {
+                    let saved = remember_host(state, &host.pattern);
+                    report_state_save(state, saved);
+                    printed = Some(host.primary_alias().to_string());
+                    break;
+                }
+                LoopControl::Launch { program, host, extra_args, command, pause } => {
+                    let saved = remember_host(state, &host.pattern);
+                    report_state_save(state, saved);
+                    launch(terminal, state, program, &host, &extra_args, command.as_deref(), pause)?;
+                    // A Ctrl-C while ssh ran in the foreground was meant for ssh
+                    interrupted.store(false, Ordering::SeqCst);
+                    launched = true;
+                    let saved = record_connection(state, &host.pattern);
+                    report_state_save(state, saved);
+                }
+                LoopControl::EditConfig => {
+                    edit_config(terminal, state, ssh_cfg)?;
+                    interrupted.store(false, Ordering::SeqCst);
                 }
             },
+            // Modals and the list are sized from the frame on every draw, so a
+            // clean redraw is all a resize needs
+            crate::ui::Event::Resize => state.needs_full_redraw = true,
             crate::ui::Event::Tick => {}
         }
     }
+    Ok((printed, launched))
+}
+
+/// Save `pattern` as the host to select on the next start.
+fn remember_host(state: &mut AppState, pattern: &str) -> Result<()> {
+    if !state.settings.remember_last_host {
+        return Ok(());
+    }
+    state.saved.last_host = Some(pattern.to_string());
+    settings::save_state(&state.saved)
+}
+
+/// Count a finished ssh session toward the host's frecency.
+fn record_connection(state: &mut AppState, pattern: &str) -> Result<()> {
+    let entry = state.saved.history.entry(pattern.to_string()).or_default();
+    entry.connections += 1;
+    entry.last_connected = settings::unix_now();
+    if state.sort_mode == SortMode::Frecency {
+        state.resort();
+    }
+    settings::save_state(&state.saved)
+}
+
+/// Put `text` on the clipboard and say so in the footer. Without a clipboard it
+/// is printed when the picker exits instead.
+fn copy_text(state: &mut AppState, text: String) {
+    state.status_message = Some(match clipboard::copy(&text) {
+        Ok(()) => format!("Copied: {}", text),
+        Err(_) => {
+            let message = format!("No clipboard available; will print on exit: {}", text);
+            state.pending_output.push(text);
+            message
+        }
+    });
+}
+
+/// Show a failed write of the state file in the footer. It only holds
+/// conveniences (last host, history), so the picker carries on.
+fn report_state_save(state: &mut AppState, saved: Result<()>) {
+    if let Err(e) = saved {
+        state.set_status(StatusKind::Failure, format!("could not save picker state: {:#}", e));
+    }
+}
+
+/// Minimum gap between automatic probe batches, so scrolling a big list
+/// doesn't open a burst of connections on every keypress.
+const AUTO_PROBE_INTERVAL: Duration = Duration::from_secs(1);
+const AUTO_PROBE_BATCH: usize = 32;
+
+/// Longest the copy-IP action waits for DNS before giving up.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Two clicks on the same row within this long count as a double-click.
+const DOUBLE_CLICK_INTERVAL: Duration = Duration::from_millis(400);
+
+/// Quiet time after the last change to a config file before it is reloaded, so
+/// an editor's burst of writes causes one reload.
+const WATCH_SETTLE: Duration = Duration::from_millis(300);
+
+/// Watches the ssh config and its includes for edits made by other programs.
+/// Their directories are watched rather than the files, since editors often
+/// replace a file instead of writing to it; events for other files are ignored.
+#[derive(Default)]
+struct ConfigWatch {
+    watcher: Option<(notify::RecommendedWatcher, mpsc::Receiver<notify::Result<notify::Event>>)>,
+    /// Source files as the config names them, when the watches were last set up.
+    sources: Vec<PathBuf>,
+    /// Those files, also resolved through symlinks: the paths events arrive for.
+    watched_files: HashSet<PathBuf>,
+    watched_dirs: HashSet<PathBuf>,
+    /// When a watched file last changed, until the change has been handled.
+    changed_at: Option<Instant>,
+    /// Watching could not be set up; said once in the footer, not retried.
+    failed: bool,
+}
+
+impl ConfigWatch {
+    /// Watch the directory of every source file of `ssh_cfg` not watched yet.
+    fn watch_sources(&mut self, ssh_cfg: &SshConfigFile) -> notify::Result<()> {
+        use notify::Watcher;
+        if self.watcher.is_none() {
+            let (tx, rx) = mpsc::channel();
+            self.watcher = Some((notify::recommended_watcher(tx)?, rx));
+        }
+        let Some((watcher, _)) = self.watcher.as_mut() else { return Ok(()) };
+        self.sources = ssh_cfg.source_paths().into_iter().map(Path::to_path_buf).collect();
+        for path in &self.sources {
+            let real = std::fs::canonicalize(path).ok();
+            for file in std::iter::once(path.clone()).chain(real) {
+                let Some(dir) = file.parent().filter(|d| d.is_dir()) else { continue };
+                if self.watched_dirs.insert(dir.to_path_buf()) {
+                    watcher.watch(dir, notify::RecursiveMode::NonRecursive)?;
+                }
+                self.watched_files.insert(file);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reload hosts after an external edit, keeping the selection on the same host.
+/// Waits while a form, prompt, or confirmation is open.
+fn pump_watch(watch: &mut ConfigWatch, state: &mut AppState, ssh_cfg: &mut SshConfigFile) {
+    if !state.settings.watch_config || watch.failed {
+        return;
+    }
+    // Includes can come and go with any reload, edit, or undo
+    if !ssh_cfg.source_paths().into_iter().eq(watch.sources.iter().map(PathBuf::as_path)) {
+        if let Err(e) = watch.watch_sources(ssh_cfg) {
+            watch.failed = true;
+            state.set_status(StatusKind::Failure, format!("not watching the ssh config for changes: {}", e));
+            return;
+        }
+    }
+    let Some((_, events)) = &watch.watcher else { return };
+    for event in events.try_iter().flatten() {
+        // Reading the files (here or in ssh) must not count as a change
+        if !matches!(event.kind, notify::EventKind::Access(_)) && event.paths.iter().any(|p| watch.watched_files.contains(p)) {
+            watch.changed_at = Some(Instant::now());
+        }
+    }
+    let idle = matches!(state.mode, Mode::Normal | Mode::Filter | Mode::Help | Mode::Resolved { .. } | Mode::Diagnostics { .. } | Mode::MatchBlocks { .. });
+    if !idle || watch.changed_at.is_none_or(|t| t.elapsed() < WATCH_SETTLE) {
+        return;
+    }
+    watch.changed_at = None;
+    // The picker's own writes show up too, and leave nothing to reload
+    if ssh_cfg.external_changes().is_none() {
+        return;
+    }
+    let selected = state.selected_host().map(|h| h.pattern.clone());
+    match ssh_cfg.reload() {
+        Ok(()) => {
+            state.hosts = ssh_cfg.list_hosts();
+            state.marked.clear();
+            state.apply_filter();
+            if let Some(pattern) = selected {
+                state.select_pattern(&pattern);
+            }
+            state.status_message = Some("ssh config changed on disk; reloaded".to_string());
+        }
+        Err(e) => state.set_status(StatusKind::Failure, format!("could not reload ssh config: {:#}", e)),
+    }
+}
+
+/// How long a load may run before the loading screen is drawn; quick loads
+/// go straight to the host list without a flash.
+const LOADING_SHOW_AFTER: Duration = Duration::from_millis(100);
+/// How long a load may run before the loading screen says what it is doing.
+const LOADING_DETAIL_AFTER: Duration = Duration::from_millis(500);
+
+/// Progress from the background config load.
+enum LoadEvent {
+    Including(PathBuf),
+    Done(Result<SshConfigFile>),
+}
+
+/// Read the ssh config on a background thread, drawing a spinner while it
+/// runs. `Ok(None)` if the user quit before it finished.
+fn load_config(terminal: &mut Tui, path: PathBuf, interrupted: &AtomicBool) -> Result<Option<SshConfigFile>> {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let progress = tx.clone();
+        let loaded = SshConfigFile::load_reporting(path, &mut |file| {
+            let _ = progress.send(LoadEvent::Including(file.to_path_buf()));
+        });
+        let _ = tx.send(LoadEvent::Done(loaded));
+    });
+    let started = Instant::now();
+    let mut detail = "Reading the ssh config…".to_string();
+    loop {
+        match rx.recv_timeout(ui::SPINNER_STEP) {
+            Ok(LoadEvent::Including(file)) => detail = format!("Expanding includes… {}", file.display()),
+            Ok(LoadEvent::Done(loaded)) => return loaded.map(Some),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => anyhow::bail!("config loader stopped unexpectedly"),
+        }
+        if interrupted.load(Ordering::SeqCst) {
+            return Ok(None);
+        }
+        while event::poll(Duration::ZERO)? {
+            if let event::Event::Key(key) = event::read()? {
+                if ui::is_quit_key(key) {
+                    return Ok(None);
+                }
+            }
+        }
+        let elapsed = started.elapsed();
+        if elapsed >= LOADING_SHOW_AFTER {
+            let detail = (elapsed >= LOADING_DETAIL_AFTER).then_some(detail.as_str());
+            terminal.draw(|f| ui::draw_loading(f, detail))?;
+        }
+    }
+}
+
+/// Collect finished probes and start new ones: everything on an explicit
+/// request, or (with `auto_probe`) listed hosts that have no result yet.
+fn pump_probes(prober: &Prober, state: &mut AppState, last_auto_probe: &mut Option<Instant>) {
+    let results = prober.drain();
+    if !results.is_empty() {
+        for (pattern, result) in results {
+            state.probing.remove(&pattern);
+            state.probe_results.insert(pattern, result);
+        }
+        if state.sort_mode == SortMode::Latency {
+            state.resort();
+        }
+    }
+    for (pattern, result) in prober.drain_lookups() {
+        let name = state.hosts.iter().find(|h| h.pattern == pattern).map_or(pattern.as_str(), |h| h.primary_alias()).to_string();
+        match result {
+            Ok(addr) => copy_text(state, addr.to_string()),
+            Err(e) => state.set_status(StatusKind::Failure, format!("{}: {:#}", name, e)),
+        }
+    }
+    for (pattern, result) in prober.drain_tests() {
+        let name = state.hosts.iter().find(|h| h.pattern == pattern).map_or(pattern.as_str(), |h| h.primary_alias());
+        match &result {
+            Ok(rtt) => state.set_status(StatusKind::Success, format!("{}: {}ms", name, rtt.as_millis())),
+            Err(e) => state.set_status(StatusKind::Failure, format!("{}: {}", name, e)),
+        }
+        let outcome = result.map_or(ProbeResult::Unreachable, ProbeResult::Reachable);
+        state.probe_results.insert(pattern, outcome);
+    }
+    if let Some(pattern) = state.lookup_requested.take() {
+        if let Some(host) = state.hosts.iter().find(|h| h.pattern == pattern) {
+            prober.start_lookup(host, LOOKUP_TIMEOUT);
+        }
+    }
+    if let Some(pattern) = state.test_requested.take() {
+        if let Some(host) = state.hosts.iter().find(|h| h.pattern == pattern) {
+            prober.start_test(host, Duration::from_secs(state.settings.test_timeout_secs.max(1)));
+        }
+    }
+    if state.sweep_requested {
+        state.probing.extend(prober.start_sweep(&state.hosts));
+        state.sweep_requested = false;
+    } else if state.settings.auto_probe && last_auto_probe.is_none_or(|t| t.elapsed() >= AUTO_PROBE_INTERVAL) {
+        let due = state
+            .filtered_hosts
+            .iter()
+            .map(|&i| &state.hosts[i])
+            .filter(|h| !state.probing.contains(&h.pattern) && !state.probe_results.contains_key(&h.pattern))
+            .take(AUTO_PROBE_BATCH);
+        let queued = prober.start_sweep(due);
+        state.probing.extend(queued);
+        *last_auto_probe = Some(Instant::now());
+    }
+}
+
+/// Start `program` for `host` in a new terminal window when `settings.spawn_terminal`
+/// is set, otherwise according to `settings.launch_mode`. Tmux modes open it beside
+/// the picker, which keeps running; outside tmux they fall back to running it here,
+/// with a note in the footer.
+fn launch(
+    terminal: &mut Tui,
+    state: &mut AppState,
+    program: Program,
+    host: &SshHostEntry,
+    extra_args: &[String],
+    command: Option<&str>,
+    pause: bool,
+) -> Result<()> {
+    // A one-shot command or a paused launch always runs here, where its output can be read
+    if command.is_some() || pause {
+        return launch_with_reconnect(terminal, state, program, host, extra_args, command, true);
+    }
+    if let Some(template) = state.settings.spawn_terminal.clone().filter(|t| !t.trim().is_empty()) {
+        match spawn_in_terminal(&template, program, host, extra_args, state.ssh_config.as_deref()) {
+            Ok(()) => {
+                let opened = format!("opened {} {} in a new terminal window", program.name(), host.primary_alias());
+                state.set_status(StatusKind::Success, opened);
+            }
+            Err(e) => state.set_status(StatusKind::Failure, format!("{:#}", e)),
+        }
+        return Ok(());
+    }
+    let mode = state.settings.launch_mode;
+    if mode == LaunchMode::Replace {
+        return launch_with_reconnect(terminal, state, program, host, extra_args, None, false);
+    }
+    if std::env::var_os("TMUX").is_none() {
+        launch_with_reconnect(terminal, state, program, host, extra_args, None, false)?;
+        let outcome = state.status_message.take().unwrap_or_default();
+        state.set_status(state.status_kind, format!("not inside tmux, ran {} here; {}", program.name(), outcome));
+        return Ok(());
+    }
+    let mut command = Command::new("tmux");
+    match mode {
+        LaunchMode::TmuxWindow => command.args(["new-window", "-n", host.primary_alias()]),
+        _ => command.arg("split-window"),
+    };
+    let result = command
+        .arg(program.name())
+        .args(extra_args)
+        .args(program.args(host, state.ssh_config.as_deref()))
+        .output()
+        .context("failed to run tmux");
+    match result {
+        Ok(output) if output.status.success() => {
+            let place = if mode == LaunchMode::TmuxWindow { "window" } else { "pane" };
+            let opened = format!("opened {} {} in a new tmux {}", program.name(), host.primary_alias(), place);
+            state.set_status(StatusKind::Success, opened);
+        }
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            state.set_status(StatusKind::Failure, format!("tmux failed: {}", stderr.trim()));
+        }
+        Err(e) => state.set_status(StatusKind::Failure, format!("{:#}", e)),
+    }
+    Ok(())
+}
+
+/// Start the `spawn_terminal` command with `program` for `host` appended, detached
+/// from the picker: its output goes nowhere and it outlives a Ctrl-C here.
+fn spawn_in_terminal(template: &str, program: Program, host: &SshHostEntry, extra_args: &[String], config: Option<&Path>) -> Result<()> {
+    let words = split_args(template).context("spawn_terminal")?;
+    let Some((terminal, terminal_args)) = words.split_first() else {
+        return Err(anyhow::anyhow!("spawn_terminal is empty"));
+    };
+    let mut command = Command::new(terminal);
+    command
+        .args(terminal_args.iter().map(|arg| arg.replace("{host}", host.primary_alias())))
+        .arg(program.name())
+        .args(extra_args)
+        .args(program.args(host, config))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(&mut command, 0);
+    let mut child = command.spawn().with_context(|| format!("could not start terminal '{}'", terminal))?;
+    // Reap it when the window closes so it doesn't linger as a zombie
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+/// Run `program`, relaunching after a backoff while it keeps failing with a
+/// connection error (255), up to `settings.reconnect_attempts` times. Any key
+/// cancels the wait. After a remote `command` the picker waits for a key, so
+/// its output stays on screen until it has been read.
+fn launch_with_reconnect(
+    terminal: &mut Tui,
+    state: &mut AppState,
+    program: Program,
+    host: &SshHostEntry,
+    extra_args: &[String],
+    command: Option<&str>,
+    pause: bool,
+) -> Result<()> {
+    let max_attempts = state.settings.reconnect_attempts;
+    let mut attempt = 0;
+    loop {
+        // Tear down TUI before launching the client
+        teardown_terminal(terminal)?;
+        let status = run_client(program, host, extra_args, command, state.ssh_config.as_deref())?;
+        // 255 is ssh's own connection failure; anything else came from the remote side
+        let done = status.code() != Some(255) || attempt >= max_attempts;
+        let ran = match command {
+            Some(command) => format!("'{}' on {}", command, host.primary_alias()),
+            None => format!("{} {}", program.name(), host.primary_alias()),
+        };
+        let message = match status.code() {
+            Some(code) => format!("{} exited with code {}", ran, code),
+            None => format!("{} was terminated by a signal", ran),
+        };
+        if done && pause {
+            wait_for_key(&message)?;
+        }
+        // Re-init terminal to return to app after it exits
+        reinit_terminal(terminal)?;
+
+        if done {
+            let kind = if status.success() { StatusKind::Success } else { StatusKind::Failure };
+            state.set_status(kind, message);
+            return Ok(());
+        }
+        attempt += 1;
+        state.set_status(
+            StatusKind::Info,
+            format!("reconnecting ({}/{})… press any key to cancel", attempt, max_attempts),
+        );
+        terminal.draw(|f| crate::ui::draw_ui(f, state))?;
+        let backoff = Duration::from_secs(1 << (attempt - 1).min(5));
+        if key_pressed_within(backoff)? {
+            state.set_status(StatusKind::Failure, format!("reconnect to '{}' canceled", host.pattern));
+            return Ok(());
+        }
+    }
+}
 
-    teardown_terminal(&mut terminal)?;
+/// Open the main config file in `$VISUAL`, `$EDITOR`, or vi, then reload it
+/// with the same host selected. Reloading clears the undo history, whose
+/// snapshots would undo the editor's changes too, so a session that changed
+/// nothing keeps it. An editor that can't be started, or a config that no
+/// longer loads, is reported in the footer.
+fn edit_config(terminal: &mut Tui, state: &mut AppState, ssh_cfg: &mut SshConfigFile) -> Result<()> {
+    let editor = ["VISUAL", "EDITOR"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    // The variable may carry arguments, e.g. `code --wait`
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+    teardown_terminal(terminal)?;
+    let status = Command::new(program).args(words).arg(&ssh_cfg.path).status();
+    reinit_terminal(terminal)?;
+    match status {
+        Err(e) => {
+            state.set_status(StatusKind::Failure, format!("could not start editor '{}': {}", editor, e));
+            return Ok(());
+        }
+        Ok(status) if !status.success() => {
+            let how = status.code().map_or_else(|| "was terminated by a signal".to_string(), |code| format!("exited with code {}", code));
+            state.set_status(StatusKind::Failure, format!("{} {}; reloading the config anyway", program, how));
+        }
+        Ok(_) => {}
+    }
+    if ssh_cfg.external_changes().is_none() {
+        if state.status_kind != StatusKind::Failure {
+            state.set_status(StatusKind::Info, format!("{} is unchanged", ssh_cfg.path.display()));
+        }
+        return Ok(());
+    }
+    let selected = state.selected_host().map(|h| h.pattern.clone());
+    if let Err(e) = ssh_cfg.reload() {
+        state.set_status(StatusKind::Failure, format!("could not reload ssh config: {:#}", e));
+        return Ok(());
+    }
+    state.hosts = ssh_cfg.list_hosts();
+    state.marked.clear();
+    state.apply_filter();
+    if let Some(pattern) = selected {
+        state.select_pattern(&pattern);
+    }
+    if state.status_kind != StatusKind::Failure {
+        state.set_status(StatusKind::Success, format!("reloaded {}", ssh_cfg.path.display()));
+    }
     Ok(())
 }
 
-fn teardown_terminal(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>) -> Result<()> {
+/// Print `message` below the client's output and block until a key is pressed.
+fn wait_for_key(message: &str) -> Result<()> {
+    println!("\n[{}] press any key to return to ssh-picker", message);
+    enable_raw_mode()?;
+    loop {
+        if let event::Event::Key(_) = event::read()? {
+            return Ok(());
+        }
+    }
+}
+
+fn key_pressed_within(timeout: Duration) -> Result<bool> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(false);
+        }
+        if event::poll(remaining)? {
+            if let event::Event::Key(_) = event::read()? {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+/// Whether the terminal is in raw mode on the alternate screen, i.e. whether a
+/// panic has anything to restore.
+static TERMINAL_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Restore the terminal before a panic message is printed, so a crash leaves a
+/// usable shell rather than one that needs `reset`. Installed once; a panic
+/// after the picker has torn the terminal down only prints the message.
+fn install_panic_hook(draws_on_stderr: bool) {
+    static INSTALLED: Once = Once::new();
+    INSTALLED.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if TERMINAL_ACTIVE.swap(false, Ordering::SeqCst) {
+                let _ = disable_raw_mode();
+                let mut out: Box<dyn io::Write> = if draws_on_stderr { Box::new(io::stderr()) } else { Box::new(io::stdout()) };
+                let _ = execute!(out, LeaveAlternateScreen, DisableMouseCapture, Show);
+            }
+            previous(info);
+        }));
+    });
+}
+
+fn teardown_terminal(terminal: &mut Tui) -> Result<()> {
+    TERMINAL_ACTIVE.store(false, Ordering::SeqCst);
     disable_raw_mode()?;
     execute!(terminal.backend_mut(), LeaveAlternateScreen, DisableMouseCapture)?;
     terminal.show_cursor()?;
     Ok(())
 }
 
-fn reinit_terminal(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>) -> Result<()> {
+fn reinit_terminal(terminal: &mut Tui) -> Result<()> {
     enable_raw_mode()?;
     execute!(terminal.backend_mut(), EnterAlternateScreen, EnableMouseCapture)?;
+    TERMINAL_ACTIVE.store(true, Ordering::SeqCst);
     terminal.clear()?;
     Ok(())
 }
@@ -65,11 +711,204 @@ fn reinit_terminal(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>) -> Res
 #[derive(Clone, Debug)]
 pub struct AppState {
     pub hosts: Vec<SshHostEntry>,
+    /// Indices into `hosts` that pass the filter, in display order.
     pub filtered_hosts: Vec<usize>,
+    /// The visible list, built from `filtered_hosts`: one row per host, or
+    /// group headers and hosts in the tree view.
+    pub rows: Vec<ListRow>,
+    /// Position in `rows` of the selection.
     pub selected_index: usize,
+    /// Group hosts into a collapsible tree by name prefix.
+    pub tree_view: bool,
+    /// Tree groups (by prefix) whose hosts are hidden.
+    pub collapsed: HashSet<String>,
     pub filter_text: String,
+    /// `filter_text` changed but `apply_filter` hasn't run yet; keystrokes that
+    /// arrive together are filtered once.
+    pub filter_pending: bool,
+    /// Compiled filter when the text starts with `/`: the last one that parsed.
+    pub filter_regex: Option<Regex>,
+    /// Compiled filter when the text has `*` or `?`: the last one that parsed.
+    pub filter_glob: Option<glob::Pattern>,
     pub mode: Mode,
     pub needs_full_redraw: bool,
+    pub sort_mode: SortMode,
+    pub probe_results: HashMap<String, ProbeResult>,
+    /// Hosts with a probe in flight.
+    pub probing: HashSet<String>,
+    pub show_latency: bool,
+    pub sweep_requested: bool,
+    /// Host (by pattern) whose connection test should start on the next tick.
+    pub test_requested: Option<String>,
+    /// Host (by pattern) whose address should be looked up and copied on the next tick.
+    pub lookup_requested: Option<String>,
+    pub status_message: Option<String>,
+    pub status_kind: StatusKind,
+    /// Normal-mode keys, from `settings.keybindings` over the defaults.
+    pub keymap: Keymap,
+    /// Highlight color from `settings.theme.accent`.
+    pub accent: Color,
+    /// Name colors from `settings.host_colors`, in order; rules that didn't
+    /// parse are left out.
+    pub host_colors: Vec<(HostMatch, Color)>,
+    pub settings: AppSettings,
+    /// Config passed to ssh, sftp, and `ssh -G` with `-F`; `None` for ssh's default.
+    pub ssh_config: Option<PathBuf>,
+    /// Lines printed to stdout after the UI exits.
+    pub pending_output: Vec<String>,
+    /// Extra ssh arguments from the last `A` launch, offered again next time.
+    pub last_extra_args: String,
+    /// Command from the last `c` launch, offered again next time.
+    pub last_remote_command: String,
+    /// File from the last export, offered again next time.
+    pub last_export_path: String,
+    pub show_preview: bool,
+    pub preview_scroll: u16,
+    /// Digits typed in Normal mode: a 1-based host number to select, Enter connects.
+    pub jump_buffer: String,
+    /// JumpFirst was pressed once; pressing it again goes to the top.
+    pub jump_first_pending: bool,
+    /// DeleteSelected was pressed once with `confirm_deletes` off; pressing it
+    /// again deletes.
+    pub delete_pending: bool,
+    /// When the status message, if it is still this one, is cleared by itself.
+    pub status_expiry: Option<(Instant, String)>,
+    pub help_scroll: u16,
+    /// Editing actions are refused and the config is never written.
+    pub read_only: bool,
+    /// Filter also searches IdentityFile, ProxyJump, and other options.
+    pub search_all_fields: bool,
+    /// Indices into `hosts` marked for a batch delete; cleared whenever hosts reload.
+    pub marked: HashSet<usize>,
+    /// known_hosts entries still to be reviewed in the form, one after another.
+    pub import_queue: Vec<SshHostEntry>,
+    /// Last host and connection history, persisted in `state.toml`.
+    pub saved: SavedState,
+    /// Where the host list was last drawn, to map mouse clicks to rows.
+    pub list_view: Cell<ListView>,
+    /// Time and `rows` position of the last click, to spot double-clicks.
+    pub last_click: Option<(Instant, usize)>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ListView {
+    /// The list widget including its border.
+    pub area: Rect,
+    /// Index of the first visible row; kept across frames so the list scrolls
+    /// only when the selection nears an edge.
+    pub offset: usize,
+}
+
+impl ListView {
+    /// Rows visible at once, or 10 before the list has been drawn.
+    pub fn page_size(&self) -> usize {
+        match self.area.height.saturating_sub(2) {
+            0 => 10,
+            rows => usize::from(rows),
+        }
+    }
+}
+
+/// Which hosts a `host_colors` rule applies to: every condition given must hold.
+#[derive(Clone, Debug)]
+pub struct HostMatch {
+    pub tag: Option<String>,
+    pub pattern: Option<Regex>,
+}
+
+impl HostMatch {
+    pub fn matches(&self, entry: &SshHostEntry) -> bool {
+        self.tag.as_deref().is_none_or(|tag| entry.has_tag(tag)) && self.pattern.as_ref().is_none_or(|re| re.is_match(&entry.pattern))
+    }
+}
+
+/// `pattern` compiled to match case-insensitively, as the filter and
+/// `host_colors` do. Errors are cut to the crate's last line (e.g. "unclosed
+/// group"), since its full message draws the pattern over several lines.
+fn build_regex(pattern: &str) -> Result<Regex> {
+    RegexBuilder::new(pattern).case_insensitive(true).build().map_err(|e| {
+        let message = e.to_string();
+        let last = message.lines().last().unwrap_or_default();
+        anyhow::anyhow!("{}", last.trim_start_matches("error: "))
+    })
+}
+
+/// `settings.host_colors` ready to match, with a warning for each rule that
+/// can't be used.
+fn compile_host_colors(rules: &[HostColor], warnings: &mut Vec<String>) -> Vec<(HostMatch, Color)> {
+    let mut compiled = Vec::new();
+    for (i, rule) in rules.iter().enumerate() {
+        let which = format!("host_colors rule {}", i + 1);
+        let Some(color) = ui::parse_color(&rule.color) else {
+            warnings.push(format!("{}: unknown color '{}'", which, rule.color));
+            continue;
+        };
+        let pattern = match rule.pattern.as_deref().map(build_regex).transpose() {
+            Ok(pattern) => pattern,
+            Err(e) => {
+                warnings.push(format!("{}: bad pattern: {}", which, e));
+                continue;
+            }
+        };
+        if rule.tag.is_none() && pattern.is_none() {
+            warnings.push(format!("{}: needs a tag or a pattern", which));
+            continue;
+        }
+        compiled.push((HostMatch { tag: rule.tag.clone(), pattern }, color));
+    }
+    compiled
+}
+
+/// Glob filters ignore case, and `*` crosses `/` and leading dots like any
+/// other character: host names aren't paths.
+const GLOB_FILTER_OPTIONS: glob::MatchOptions =
+    glob::MatchOptions { case_sensitive: false, require_literal_separator: false, require_literal_leading_dot: false };
+
+/// How the footer colors `status_message`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatusKind {
+    Info,
+    Success,
+    Failure,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortMode {
+    /// Config file order, or best match first while filtering.
+    #[default]
+    ConfigOrder,
+    PatternAsc,
+    PatternDesc,
+    /// By HostName, falling back to the pattern for hosts without one.
+    HostnameAsc,
+    Latency,
+    /// Most used recently first; hosts never connected to keep config order after them.
+    Frecency,
+}
+
+impl SortMode {
+    pub fn next(self) -> Self {
+        match self {
+            SortMode::ConfigOrder => SortMode::PatternAsc,
+            SortMode::PatternAsc => SortMode::PatternDesc,
+            SortMode::PatternDesc => SortMode::HostnameAsc,
+            SortMode::HostnameAsc => SortMode::Latency,
+            SortMode::Latency => SortMode::Frecency,
+            SortMode::Frecency => SortMode::ConfigOrder,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SortMode::ConfigOrder => "config order",
+            SortMode::PatternAsc => "pattern A-Z",
+            SortMode::PatternDesc => "pattern Z-A",
+            SortMode::HostnameAsc => "hostname A-Z",
+            SortMode::Latency => "latency",
+            SortMode::Frecency => "frequently used",
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -78,83 +917,762 @@ pub enum Mode {
     Filter,
     Confirm(ConfirmContext),
     EditForm(FormData),
+    Prompt(PromptData),
+    /// Key reference overlay.
+    Help,
+    /// `ssh -G` output for a host, scrolled `scroll` lines down.
+    Resolved { host: String, lines: Vec<String>, scroll: u16 },
+    /// Config problems: lines the parser skipped and repeated Host blocks.
+    Diagnostics { lines: Vec<String>, scroll: u16 },
+    /// The config's `Match` blocks, listed for reference.
+    MatchBlocks { lines: Vec<String>, scroll: u16 },
+    /// Editor for the options of the `Host *` block.
+    GlobalOptions(GlobalOptions),
+    /// The picker's own settings that can be changed without editing `config.toml`.
+    Settings(SettingsForm),
+}
+
+/// The `Host *` options being edited, as `Key value` lines; the last is always
+/// blank for adding one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GlobalOptions {
+    pub lines: Vec<String>,
+    pub current: usize,
+    /// Non-blank lines when the editor opened.
+    pub initial: Vec<String>,
+    pub error: Option<String>,
+}
+
+impl GlobalOptions {
+    fn new(block: Option<&SshHostEntry>) -> Self {
+        let mut lines: Vec<String> = block.map_or_else(Vec::new, |b| b.options().into_iter().map(|(k, v)| format!("{} {}", k, v)).collect());
+        let initial = lines.clone();
+        lines.push(String::new());
+        Self { lines, current: 0, initial, error: None }
+    }
+
+    pub fn has_changes(&self) -> bool {
+        !self.lines.iter().filter(|l| !l.trim().is_empty()).eq(self.initial.iter())
+    }
+}
+
+/// The settings screen's values as edited so far; `current` is the focused
+/// row, in `SETTINGS_ROWS` order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SettingsForm {
+    pub show_user: bool,
+    pub show_hostname: bool,
+    pub page_size: String,
+    pub accent: String,
+    pub current: usize,
+    pub error: Option<String>,
+}
+
+/// Labels of the settings screen's rows.
+pub const SETTINGS_ROWS: [&str; 4] = ["Show user", "Show HostName", "Page size", "Accent color"];
+
+/// Accent colors Space steps through on the settings screen.
+const ACCENT_CHOICES: [&str; 8] = ["yellow", "cyan", "green", "magenta", "blue", "red", "white", "lightblue"];
+
+impl SettingsForm {
+    fn new(settings: &AppSettings) -> Self {
+        Self {
+            show_user: settings.show_user,
+            show_hostname: settings.show_hostname,
+            page_size: settings.page_size.to_string(),
+            accent: settings.theme.accent.clone(),
+            current: 0,
+            error: None,
+        }
+    }
+
+    /// Type `ch` into the focused row. Space flips a toggle and steps the
+    /// accent through `ACCENT_CHOICES`.
+    fn input(&mut self, ch: char) {
+        match (self.current, ch) {
+            (0, ' ') => self.show_user = !self.show_user,
+            (1, ' ') => self.show_hostname = !self.show_hostname,
+            (2, c) if c.is_ascii_digit() => self.page_size.push(c),
+            (3, ' ') => {
+                let at = ACCENT_CHOICES.iter().position(|c| c.eq_ignore_ascii_case(self.accent.trim()));
+                self.accent = ACCENT_CHOICES[at.map_or(0, |i| (i + 1) % ACCENT_CHOICES.len())].to_string();
+            }
+            (3, c) => self.accent.push(c),
+            _ => {}
+        }
+    }
+
+    fn backspace(&mut self) {
+        match self.current {
+            2 => drop(self.page_size.pop()),
+            3 => drop(self.accent.pop()),
+            _ => {}
+        }
+    }
+
+    /// `settings` with this form's values, or what is wrong with them.
+    fn apply(&self, settings: &AppSettings) -> Result<(AppSettings, Color)> {
+        let page_size = match self.page_size.trim() {
+            "" => 0,
+            text => text.parse().map_err(|_| anyhow::anyhow!("Page size must be a number"))?,
+        };
+        let accent = ui::parse_color(&self.accent).ok_or_else(|| anyhow::anyhow!("Unknown color '{}'", self.accent.trim()))?;
+        let mut settings = settings.clone();
+        settings.show_user = self.show_user;
+        settings.show_hostname = self.show_hostname;
+        settings.page_size = page_size;
+        settings.theme.accent = self.accent.trim().to_string();
+        Ok((settings, accent))
+    }
 }
 
+/// Pattern of the block the global options editor works on.
+const GLOBAL_PATTERN: &str = "*";
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ConfirmContext {
-    Delete { pattern: String },
+    /// Delete the listed block `host`, leaving others that share an alias with it.
+    Delete { host: Box<SshHostEntry> },
+    /// Delete every marked host at once.
+    DeleteMarked { hosts: Vec<SshHostEntry> },
+    /// Add hosts found in known_hosts: all at once, or one form each to review.
+    ImportKnownHosts { hosts: Vec<SshHostEntry>, hashed: usize },
+    /// Drop every repeated `Host` block, keeping the first of each.
+    MergeDuplicates,
+    /// Throw away the edits in `form`, then close it or, with `quit`, exit;
+    /// declining reopens the form.
+    DiscardForm { form: Box<FormData>, quit: bool },
+    /// Replace the existing file at `path` with an export of `hosts`.
+    OverwriteExport { path: PathBuf, hosts: Vec<SshHostEntry> },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PromptData {
+    pub kind: PromptKind,
+    pub input: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PromptKind {
+    CopyAs { source: String },
+    Forward { host: SshHostEntry },
+    ExtraArgs { host: SshHostEntry },
+    RemoteCommand { host: SshHostEntry },
+    Rename { host: SshHostEntry },
+    Export { hosts: Vec<SshHostEntry> },
+}
+
+impl PromptKind {
+    pub fn label(&self) -> String {
+        match self {
+            PromptKind::CopyAs { source } => format!("Copy '{}' as", source),
+            PromptKind::Forward { host } => format!("Forward for '{}' (localport:remotehost:remoteport)", host.pattern),
+            PromptKind::ExtraArgs { host } => format!("Extra ssh args for '{}'", host.pattern),
+            PromptKind::RemoteCommand { host } => format!("Command to run on '{}'", host.pattern),
+            PromptKind::Rename { host } => format!("Rename '{}' to", host.pattern),
+            PromptKind::Export { hosts } => format!("Export {} host(s) to file", hosts.len()),
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct FormData {
     pub is_editing: bool,  // true for edit, false for new
+    pub original_pattern: Option<String>,  // pattern of the host being edited
     pub pattern: String,
     pub hostname: String,
     pub user: String,
     pub port: String,
-    pub current_field: usize,  // 0=pattern, 1=hostname, 2=user, 3=port
+    pub identity_file: String,
+    pub proxy_jump: String,
+    /// One-line note, saved as a `# note:` comment.
+    pub note: String,
+    /// `FORM_TOGGLES` options: `yes`, `no`, or unset (no line at all).
+    pub toggles: [Option<bool>; FORM_TOGGLES.len()],
+    /// Port forwards as `L 8080 db:80`, `R 9000 localhost:3000`, or `D 1080`
+    /// lines, backing the `FORM_FORWARDS` options of `SshHostEntry.other`; the
+    /// last is always blank for adding one.
+    pub forwards: Vec<String>,
+    /// Other options as `Key value` lines, backing `SshHostEntry.other`; the last is always blank for adding one.
+    pub advanced: Vec<String>,
+    pub current_field: usize,  // 0=pattern, 1=hostname, 2=user, 3=port, 4=identity_file, 5=proxy_jump, 6=note, 7..=10 toggles, then forwards, then advanced
+    pub error: Option<String>,
+    /// Shown under the form while it is open, e.g. that a ProxyCommand is kept verbatim.
+    pub notice: Option<String>,
+    /// Basic field values when the form opened, to show what an edit replaces.
+    pub initial: Vec<String>,
+    /// Non-blank advanced lines when the form opened.
+    pub initial_advanced: Vec<String>,
+    /// Non-blank forward lines when the form opened.
+    pub initial_forwards: Vec<String>,
+    pub initial_toggles: [Option<bool>; FORM_TOGGLES.len()],
+    /// Pattern of the host a new host was cloned from; its unlisted fields
+    /// (tags, forward, source file) are copied on save.
+    pub template: Option<String>,
+}
+
+/// Fields before the toggles.
+pub const FORM_BASIC_FIELDS: usize = 7;
+
+/// Yes/no options the form shows as checkboxes rather than Advanced lines.
+pub const FORM_TOGGLES: [&str; 4] = ["ForwardAgent", "AddKeysToAgent", "Compression", "IdentitiesOnly"];
+
+/// Options the form's Forwards lines stand for, by the letter a line starts with.
+pub const FORM_FORWARDS: [(&str, &str); 3] = [("L", "LocalForward"), ("R", "RemoteForward"), ("D", "DynamicForward")];
+
+/// `current_field` of the first forward line.
+pub const FORM_FORWARDS_START: usize = FORM_BASIC_FIELDS + FORM_TOGGLES.len();
+
+impl FormData {
+    fn new(entry: Option<SshHostEntry>) -> Self {
+        let is_editing = entry.is_some();
+        let entry = entry.unwrap_or_default();
+        // A toggle takes the first yes/no line of its option; other values
+        // (`AddKeysToAgent confirm`) and repeats stay Advanced lines
+        let mut toggles = [None; FORM_TOGGLES.len()];
+        let mut advanced = Vec::new();
+        let mut forwards = Vec::new();
+        for (key, value) in &entry.other {
+            if let Some((letter, _)) = FORM_FORWARDS.iter().find(|(_, name)| name.eq_ignore_ascii_case(key)) {
+                forwards.push(format!("{} {}", letter, value));
+                continue;
+            }
+            let toggle = FORM_TOGGLES.iter().position(|t| t.eq_ignore_ascii_case(key)).filter(|&i| toggles[i].is_none());
+            match (toggle, value.to_ascii_lowercase().as_str()) {
+                (Some(i), "yes") => toggles[i] = Some(true),
+                (Some(i), "no") => toggles[i] = Some(false),
+                _ => advanced.push(format!("{} {}", key, value)),
+            }
+        }
+        advanced.push(String::new());
+        forwards.push(String::new());
+        let notice = entry
+            .other
+            .iter()
+            .any(|(k, _)| k.eq_ignore_ascii_case("proxycommand"))
+            .then(|| "ProxyCommand and other advanced options are saved exactly as written unless you change their line".to_string());
+        let mut form = Self {
+            is_editing,
+            original_pattern: is_editing.then(|| entry.pattern.clone()),
+            pattern: entry.pattern,
+            hostname: entry.hostname.unwrap_or_default(),
+            user: entry.user.unwrap_or_default(),
+            port: entry.port.map(|p| p.to_string()).unwrap_or_default(),
+            identity_file: entry.identity_file.unwrap_or_default(),
+            proxy_jump: entry.proxy_jump.unwrap_or_default(),
+            note: entry.note.unwrap_or_default(),
+            toggles,
+            forwards,
+            advanced,
+            current_field: 0,
+            error: None,
+            notice,
+            initial: Vec::new(),
+            initial_advanced: Vec::new(),
+            initial_forwards: Vec::new(),
+            initial_toggles: toggles,
+            template: None,
+        };
+        form.mark_clean();
+        form
+    }
+
+    /// Take the current values as the ones the form opened with.
+    fn mark_clean(&mut self) {
+        self.initial = self.basic_fields().into_iter().cloned().collect();
+        self.initial_advanced = non_blank(&self.advanced);
+        self.initial_forwards = non_blank(&self.forwards);
+        self.initial_toggles = self.toggles;
+    }
+
+    /// Whether anything was typed since the form opened.
+    pub fn has_changes(&self) -> bool {
+        !self.basic_fields().into_iter().eq(self.initial.iter())
+            || self.toggles != self.initial_toggles
+            || non_blank(&self.forwards) != self.initial_forwards
+            || non_blank(&self.advanced) != self.initial_advanced
+    }
+
+    /// A new-host form holding every field of `entry`, named with the next free
+    /// alias after its first one (`prod-web-2` becomes `prod-web-3`).
+    fn cloned_from(entry: SshHostEntry, hosts: &[SshHostEntry]) -> Self {
+        let pattern = next_free_alias(entry.primary_alias(), hosts);
+        let template = Some(entry.pattern.clone());
+        let mut form = Self::new(Some(entry));
+        form.is_editing = false;
+        form.original_pattern = None;
+        form.pattern = pattern;
+        form.template = template;
+        form.mark_clean();
+        form
+    }
+
+    /// The basic fields in `current_field` order.
+    pub fn basic_fields(&self) -> [&String; FORM_BASIC_FIELDS] {
+        [&self.pattern, &self.hostname, &self.user, &self.port, &self.identity_file, &self.proxy_jump, &self.note]
+    }
+
+    /// `current_field` of the first advanced option line.
+    pub fn advanced_start(&self) -> usize {
+        FORM_FORWARDS_START + self.forwards.len()
+    }
+
+    pub fn field_count(&self) -> usize {
+        self.advanced_start() + self.advanced.len()
+    }
+
+    /// The toggle `current_field` is on, if it is on one.
+    fn toggle_mut(&mut self) -> Option<&mut Option<bool>> {
+        let i = self.current_field.checked_sub(FORM_BASIC_FIELDS)?;
+        self.toggles.get_mut(i)
+    }
+
+    fn field_mut(&mut self) -> Option<&mut String> {
+        match self.current_field {
+            0 => Some(&mut self.pattern),
+            1 => Some(&mut self.hostname),
+            2 => Some(&mut self.user),
+            3 => Some(&mut self.port),
+            4 => Some(&mut self.identity_file),
+            5 => Some(&mut self.proxy_jump),
+            6 => Some(&mut self.note),
+            n if n >= self.advanced_start() => {
+                let i = n - self.advanced_start();
+                self.advanced.get_mut(i)
+            }
+            n => self.forwards.get_mut(n.checked_sub(FORM_FORWARDS_START)?),
+        }
+    }
+
+    fn tidy_lists(&mut self) {
+        tidy_lines(&mut self.forwards);
+        tidy_lines(&mut self.advanced);
+        self.current_field = self.current_field.min(self.field_count() - 1);
+    }
+}
+
+fn non_blank(lines: &[String]) -> Vec<String> {
+    lines.iter().filter(|l| !l.trim().is_empty()).cloned().collect()
+}
+
+/// Keep exactly one blank line at the end of `lines` to type a new option into.
+fn tidy_lines(lines: &mut Vec<String>) {
+    while lines.len() > 1 && lines.iter().rev().take(2).all(|l| l.trim().is_empty()) {
+        lines.pop();
+    }
+    if lines.last().is_none_or(|l| !l.trim().is_empty()) {
+        lines.push(String::new());
+    }
 }
 
 impl AppState {
-    pub fn new(hosts: Vec<SshHostEntry>) -> Self {
+    pub fn new(hosts: Vec<SshHostEntry>, settings: AppSettings) -> Self {
         let filtered_hosts = (0..hosts.len()).collect();
-        Self {
+        let (keymap, keymap_warnings) = Keymap::new(&settings.keybindings);
+        let mut warnings: Vec<String> = keymap_warnings.into_iter().map(|w| format!("keybindings: {}", w)).collect();
+        let accent = ui::parse_color(&settings.theme.accent).unwrap_or_else(|| {
+            warnings.push(format!("theme: unknown accent color '{}', using yellow", settings.theme.accent));
+            Color::Yellow
+        });
+        let host_colors = compile_host_colors(&settings.host_colors, &mut warnings);
+        let mut state = Self {
             hosts,
             filtered_hosts,
+            rows: Vec::new(),
             selected_index: 0,
+            tree_view: settings.tree_view,
+            collapsed: HashSet::new(),
             filter_text: String::new(),
+            filter_pending: false,
+            filter_regex: None,
+            filter_glob: None,
             mode: Mode::Normal,
             needs_full_redraw: false,
-        }
+            sort_mode: settings.sort_mode,
+            search_all_fields: settings.search_all_fields,
+            read_only: settings.read_only,
+            marked: HashSet::new(),
+            import_queue: Vec::new(),
+            probe_results: HashMap::new(),
+            probing: HashSet::new(),
+            show_latency: true,
+            sweep_requested: false,
+            test_requested: None,
+            lookup_requested: None,
+            // Bad settings fall back to defaults; say so instead of failing to start
+            status_message: (!warnings.is_empty()).then(|| warnings.join("; ")),
+            status_kind: StatusKind::Info,
+            keymap,
+            accent,
+            host_colors,
+            settings,
+            ssh_config: None,
+            pending_output: Vec::new(),
+            last_extra_args: String::new(),
+            last_remote_command: String::new(),
+            last_export_path: String::new(),
+            show_preview: false,
+            preview_scroll: 0,
+            jump_buffer: String::new(),
+            jump_first_pending: false,
+            delete_pending: false,
+            status_expiry: None,
+            help_scroll: 0,
+            saved: SavedState::default(),
+            list_view: Cell::new(ListView::default()),
+            last_click: None,
+        };
+        state.apply_filter();
+        state
     }
 
-    pub fn selected_host(&self) -> Option<&SshHostEntry> {
-        self.filtered_hosts
-            .get(self.selected_index)
-            .and_then(|&idx| self.hosts.get(idx))
+    pub fn set_status(&mut self, kind: StatusKind, message: String) {
+        self.status_message = Some(message);
+        self.status_kind = kind;
     }
 
-    pub fn apply_filter(&mut self) {
-        if self.filter_text.is_empty() {
-            self.filtered_hosts = (0..self.hosts.len()).collect();
-        } else {
-            let query = self.filter_text.to_lowercase();
-            self.filtered_hosts = self
-                .hosts
-                .iter()
-                .enumerate()
-                .filter(|(_, h)| h.matches(&query))
-                .map(|(i, _)| i)
-                .collect();
+    /// Clear a status message whose `status_expiry` has passed.
+    fn expire_status(&mut self) {
+        let Some((deadline, message)) = &self.status_expiry else { return };
+        if Instant::now() < *deadline {
+            return;
         }
-        if self.selected_index >= self.filtered_hosts.len() {
-            self.selected_index = self.filtered_hosts.len().saturating_sub(1);
+        if self.status_message.as_ref() == Some(message) {
+            self.status_message = None;
+            self.status_kind = StatusKind::Info;
         }
+        self.status_expiry = None;
     }
-}
 
-pub enum LoopControl {
-    Continue,
-    Exit,
-    Launch(String),
+    /// Hosts an export writes: the marked ones in config order, or else every
+    /// host the filter lets through, in list order.
+    pub fn export_selection(&self) -> Vec<SshHostEntry> {
+        if self.marked.is_empty() {
+            return self.filtered_hosts.iter().map(|&i| self.hosts[i].clone()).collect();
+        }
+        let mut marked: Vec<usize> = self.marked.iter().copied().collect();
+        marked.sort_unstable();
+        marked.into_iter().map(|i| self.hosts[i].clone()).collect()
+    }
+
+    /// Rows PageUp and PageDown move by: `settings.page_size`, or the list's height.
+    pub fn page_size(&self) -> usize {
+        match self.settings.page_size {
+            0 => self.list_view.get().page_size(),
+            n => n,
+        }
+    }
+
+    /// Position in `rows` of the typed 1-based row number, if it is in range.
+    pub fn jump_index(&self) -> Option<usize> {
+        let n: usize = self.jump_buffer.parse().ok()?;
+        (1..=self.rows.len()).contains(&n).then(|| n - 1)
+    }
+
+    /// Index into `hosts` of the host on the selected row; `None` on a group header.
+    pub fn selected_host_index(&self) -> Option<usize> {
+        match self.rows.get(self.selected_index)? {
+            ListRow::Host { index, .. } => Some(*index),
+            ListRow::Group { .. } => None,
+        }
+    }
+
+    pub fn selected_host(&self) -> Option<&SshHostEntry> {
+        self.selected_host_index().and_then(|idx| self.hosts.get(idx))
+    }
+
+    /// Move the selection onto the host with the given pattern, if it is visible.
+    pub fn select_pattern(&mut self, pattern: &str) {
+        let hosts = &self.hosts;
+        if let Some(pos) = self
+            .rows
+            .iter()
+            .position(|row| matches!(row, ListRow::Host { index, .. } if hosts[*index].pattern == pattern))
+        {
+            self.selected_index = pos;
+        }
+    }
+
+    /// Expand or collapse the group on the selected row. Returns false when the
+    /// selection is a host.
+    pub fn toggle_selected_group(&mut self) -> bool {
+        let Some(ListRow::Group { prefix, .. }) = self.rows.get(self.selected_index).cloned() else {
+            return false;
+        };
+        if !self.collapsed.remove(&prefix) {
+            self.collapsed.insert(prefix);
+        }
+        // Rows before the header are unchanged, so the selection stays on it
+        self.rebuild_rows();
+        true
+    }
+
+    /// Lay out `rows` from `filtered_hosts` for the current view.
+    fn rebuild_rows(&mut self) {
+        self.rows = if self.tree_view {
+            tree::build(&self.hosts, &self.filtered_hosts, &self.collapsed)
+        } else {
+            tree::flat(&self.filtered_hosts)
+        };
+        if self.selected_index >= self.rows.len() {
+            self.selected_index = self.rows.len().saturating_sub(1);
+        }
+    }
+
+    /// Re-run filtering and sorting while keeping the same host selected.
+    pub fn resort(&mut self) {
+        let selected = self.selected_host().map(|h| h.pattern.clone());
+        self.apply_filter();
+        if let Some(pattern) = selected {
+            self.select_pattern(&pattern);
+        }
+    }
+
+    /// The filter text without its `tag:` and `file:` words: what hosts are
+    /// fuzzy-matched against.
+    pub fn filter_query(&self) -> String {
+        let words: Vec<&str> = self
+            .filter_text
+            .split_whitespace()
+            .filter(|w| !w.starts_with("tag:") && !w.starts_with("file:"))
+            .collect();
+        words.join(" ")
+    }
+
+    /// Name color from the first `host_colors` rule matching `entry`.
+    pub fn host_color(&self, entry: &SshHostEntry) -> Option<Color> {
+        self.host_colors.iter().find(|(rule, _)| rule.matches(entry)).map(|&(_, color)| color)
+    }
+
+    /// Color a tag-only `host_colors` rule gives `tag`, so its chip matches the
+    /// names it colors.
+    pub fn tag_color(&self, tag: &str) -> Option<Color> {
+        self.host_colors
+            .iter()
+            .find(|(rule, _)| rule.pattern.is_none() && rule.tag.as_deref().is_some_and(|t| t.eq_ignore_ascii_case(tag)))
+            .map(|&(_, color)| color)
+    }
+
+    /// Byte offsets of the characters in `text` the filter matched, to highlight.
+    pub fn filter_highlights(&self, text: &str) -> Vec<usize> {
+        match &self.filter_regex {
+            Some(re) => re.find(text).map(|m| m.range()).map_or_else(Vec::new, |span| {
+                text[span.clone()].char_indices().map(|(i, _)| span.start + i).collect()
+            }),
+            None => match &self.filter_glob {
+                // Whole names the glob matched
+                Some(pattern) => text
+                    .split_whitespace()
+                    .filter(|word| pattern.matches_with(word, GLOB_FILTER_OPTIONS))
+                    .flat_map(|word| {
+                        let start = word.as_ptr() as usize - text.as_ptr() as usize;
+                        word.char_indices().map(move |(i, _)| start + i)
+                    })
+                    .collect(),
+                None => fuzzy::positions(text, &self.filter_query()).unwrap_or_default(),
+            },
+        }
+    }
+
+    /// Run a filter that typing left pending.
+    pub fn flush_filter(&mut self) {
+        if std::mem::take(&mut self.filter_pending) {
+            self.apply_filter();
+        }
+    }
+
+    pub fn apply_filter(&mut self) {
+        self.filter_pending = false;
+        // A leading `/` makes the rest a regex over the pattern and HostName
+        if let Some(source) = self.filter_text.strip_prefix('/') {
+            match build_regex(source) {
+                Ok(re) => self.filter_regex = Some(re),
+                // Keep the last pattern that parsed, so the list doesn't empty mid-typing
+                Err(e) => self.set_status(StatusKind::Failure, format!("regex: {}", e)),
+            }
+            let re = self.filter_regex.as_ref();
+            let matches = |h: &SshHostEntry| {
+                re.is_none_or(|re| re.is_match(&h.pattern) || h.hostname.as_deref().is_some_and(|hn| re.is_match(hn)))
+            };
+            self.filtered_hosts = (0..self.hosts.len()).filter(|&i| matches(&self.hosts[i])).collect();
+            self.sort_filtered();
+            return;
+        }
+        self.filter_regex = None;
+        let query = self.filter_query();
+        // `*` or `?` makes the rest a glob over each alias and the HostName
+        let glob = query.contains(['*', '?']);
+        if glob {
+            match glob::Pattern::new(&query) {
+                Ok(pattern) => self.filter_glob = Some(pattern),
+                // Keep the list as it was, so it doesn't empty mid-typing
+                Err(e) => {
+                    self.set_status(StatusKind::Failure, format!("glob: {}", e.msg));
+                    return;
+                }
+            }
+        } else {
+            self.filter_glob = None;
+        }
+        // `tag:x` words narrow to tagged hosts; the rest of the text is fuzzy-matched
+        let tags: Vec<&str> = self
+            .filter_text
+            .split_whitespace()
+            .filter_map(|w| w.strip_prefix("tag:"))
+            .filter(|t| !t.is_empty())
+            .collect();
+        // `file:x` words narrow to hosts from config files whose name fuzzy-matches x
+        let files: Vec<Vec<char>> = self
+            .filter_text
+            .split_whitespace()
+            .filter_map(|w| w.strip_prefix("file:"))
+            .filter(|f| !f.is_empty())
+            .map(|f| f.to_lowercase().chars().collect())
+            .collect();
+        let in_files = |h: &SshHostEntry| {
+            let name = h.source_name().map(str::to_lowercase);
+            files.iter().all(|f| name.as_deref().is_some_and(|name| fuzzy::score(name, f).is_some()))
+        };
+        let narrowed = |h: &SshHostEntry| tags.iter().all(|t| h.has_tag(t)) && in_files(h);
+        if let Some(pattern) = self.filter_glob.as_ref().filter(|_| glob) {
+            let matches = |h: &SshHostEntry| {
+                h.aliases().any(|a| pattern.matches_with(a, GLOB_FILTER_OPTIONS))
+                    || h.hostname.as_deref().is_some_and(|hn| pattern.matches_with(hn, GLOB_FILTER_OPTIONS))
+            };
+            self.filtered_hosts = (0..self.hosts.len()).filter(|&i| narrowed(&self.hosts[i]) && matches(&self.hosts[i])).collect();
+        } else if query.is_empty() {
+            self.filtered_hosts = (0..self.hosts.len()).filter(|&i| narrowed(&self.hosts[i])).collect();
+        } else {
+            let mut scored: Vec<(usize, i64)> = self
+                .hosts
+                .iter()
+                .enumerate()
+                .filter(|(_, h)| narrowed(h))
+                .filter_map(|(i, h)| h.match_score(&query, self.search_all_fields).map(|score| (i, score)))
+                .collect();
+            // Best match first; the stable sort keeps config order among ties
+            scored.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+            self.filtered_hosts = scored.into_iter().map(|(i, _)| i).collect();
+        }
+        self.sort_filtered();
+    }
+
+    /// Order `filtered_hosts` by the sort mode and rebuild the visible rows.
+    fn sort_filtered(&mut self) {
+        // Stable sorts keep the order above (config or relevance) among equal keys
+        let hosts = &self.hosts;
+        let sort_name = |i: usize| hosts[i].pattern.to_lowercase();
+        match self.sort_mode {
+            SortMode::ConfigOrder => {}
+            SortMode::PatternAsc => self.filtered_hosts.sort_by_key(|&i| sort_name(i)),
+            SortMode::PatternDesc => self.filtered_hosts.sort_by_key(|&i| std::cmp::Reverse(sort_name(i))),
+            SortMode::HostnameAsc => self.filtered_hosts.sort_by_key(|&i| {
+                hosts[i].hostname.as_deref().unwrap_or(&hosts[i].pattern).to_lowercase()
+            }),
+            SortMode::Latency => {
+                let results = &self.probe_results;
+                self.filtered_hosts
+                    .sort_by_key(|&i| probe::latency_rank(results.get(&hosts[i].pattern)));
+            }
+            SortMode::Frecency => {
+                let now = settings::unix_now();
+                let score = |i: usize| self.saved.history.get(&hosts[i].pattern).map_or(0.0, |h| h.frecency(now));
+                self.filtered_hosts.sort_by(|&a, &b| score(b).total_cmp(&score(a)));
+            }
+        }
+        self.rebuild_rows();
+    }
+}
+
+pub enum LoopControl {
+    Continue,
+    Exit,
+    /// With `command`, ssh runs it on the host instead of opening a shell. With
+    /// `pause`, the client's output stays on screen until a key is pressed.
+    Launch { program: Program, host: Box<SshHostEntry>, extra_args: Vec<String>, command: Option<String>, pause: bool },
+    /// Open the config in the user's editor, then reload it.
+    EditConfig,
 }
 
 fn handle_action(action: UiAction, state: &mut AppState, ssh_cfg: &mut SshConfigFile) -> Result<LoopControl> {
+    use UiAction::*;
+    if !matches!(action, Noop) {
+        state.status_message = None;
+        state.status_kind = StatusKind::Info;
+    }
+    // Checked here so no path below can reach an upsert or a delete; forms
+    // opened before read-only mode was turned on can't be saved either
+    let saves_form = matches!(action, FormSubmit) && matches!(state.mode, Mode::EditForm(_) | Mode::GlobalOptions(_));
+    if state.read_only && ((action.edits_config() && matches!(state.mode, Mode::Normal)) || saves_form) {
+        state.status_message = Some("read-only mode".to_string());
+        return Ok(LoopControl::Continue);
+    }
+    // Anything but more typing sees the list for the text typed so far
+    if !matches!(action, InputChar(_) | BackspaceFilter | Noop) {
+        state.flush_filter();
+    }
+    let selected_before = state.selected_host().map(|h| h.pattern.clone());
+    let typing_number = matches!(action, InputChar(c) if c.is_ascii_digit()) && matches!(state.mode, Mode::Normal);
+    let control = match dispatch_action(action, state, ssh_cfg)? {
+        // Every launch path ends here, so `Host *` and the like never reach ssh
+        LoopControl::Launch { host, .. } if host.is_wildcard() => {
+            state.set_status(
+                StatusKind::Failure,
+                format!("'{}' is a wildcard pattern that sets defaults for other hosts; pick a concrete host", host.pattern),
+            );
+            LoopControl::Continue
+        }
+        control => control,
+    };
+    // Any key other than a digit ends (or, for Enter, completes) a typed host number
+    if !typing_number && !matches!(action, Noop) {
+        state.jump_buffer.clear();
+    }
+    if !matches!(action, JumpFirst | Noop) {
+        state.jump_first_pending = false;
+    }
+    if !matches!(action, DeleteSelected | Noop) {
+        state.delete_pending = false;
+    }
+    // A different host starts its details pane from the top
+    if state.selected_host().map(|h| &h.pattern) != selected_before.as_ref() {
+        state.preview_scroll = 0;
+    }
+    Ok(control)
+}
+
+fn dispatch_action(action: UiAction, state: &mut AppState, ssh_cfg: &mut SshConfigFile) -> Result<LoopControl> {
     use UiAction::*;
     match action {
         MoveUp => {
-            state.selected_index = state.selected_index.saturating_sub(1);
+            if state.selected_index == 0 && state.settings.wrap_navigation {
+                state.selected_index = state.rows.len().saturating_sub(1);
+            } else {
+                state.selected_index = state.selected_index.saturating_sub(1);
+            }
         }
         MoveDown => {
-            if state.selected_index + 1 < state.filtered_hosts.len() {
+            if state.selected_index + 1 < state.rows.len() {
                 state.selected_index += 1;
+            } else if state.settings.wrap_navigation {
+                state.selected_index = 0;
             }
         }
         PageUp => {
-            state.selected_index = state.selected_index.saturating_sub(10);
+            state.selected_index = state.selected_index.saturating_sub(state.page_size());
         }
         PageDown => {
-            state.selected_index = (state.selected_index + 10).min(state.filtered_hosts.len().saturating_sub(1));
+            let page = state.page_size();
+            state.selected_index = (state.selected_index + page).min(state.rows.len().saturating_sub(1));
+        }
+        JumpFirst => {
+            if state.jump_first_pending {
+                state.selected_index = 0;
+            }
+            state.jump_first_pending = !state.jump_first_pending;
+        }
+        JumpLast => {
+            state.selected_index = state.rows.len().saturating_sub(1);
         }
         BeginFilter => {
             state.mode = Mode::Filter;
@@ -163,34 +1681,67 @@ fn handle_action(action: UiAction, state: &mut AppState, ssh_cfg: &mut SshConfig
             match &mut state.mode {
                 Mode::Filter => {
                     state.filter_text.push(ch);
-                    state.apply_filter();
+                    state.filter_pending = true;
                 }
                 Mode::Confirm(ctx) => {
                     match ch {
-                        'y' | 'Y' => {
-                            let ConfirmContext::Delete { pattern } = ctx.clone();
-                            ssh_cfg.delete_host(&pattern)?;
-                            state.hosts = ssh_cfg.list_hosts();
-                            state.apply_filter();
-                            state.mode = Mode::Normal;
-                            state.needs_full_redraw = true;
-                        }
-                        'n' | 'N' => {
-                            state.mode = Mode::Normal;
-                            state.needs_full_redraw = true;
+                        'y' | 'Y' => return accept_confirm(state, ssh_cfg),
+                        'e' | 'E' if matches!(ctx, ConfirmContext::ImportKnownHosts { .. }) => {
+                            if let ConfirmContext::ImportKnownHosts { hosts, .. } = ctx.clone() {
+                                state.import_queue = hosts;
+                                next_import(state);
+                            }
                         }
+                        'n' | 'N' => dismiss_confirm(state),
                         _ => {}
                     }
                 }
                 Mode::EditForm(form) => {
-                    let field = match form.current_field {
-                        0 => &mut form.pattern,
-                        1 => &mut form.hostname,
-                        2 => &mut form.user,
-                        3 => &mut form.port,
-                        _ => return Ok(LoopControl::Continue),
+                    // Space steps a toggle through yes, no, and unset; y and n set it
+                    if let Some(toggle) = form.toggle_mut() {
+                        *toggle = match (ch, *toggle) {
+                            ('y' | 'Y', _) | (' ', None) => Some(true),
+                            ('n' | 'N', _) | (' ', Some(true)) => Some(false),
+                            (' ', Some(false)) => None,
+                            (_, current) => current,
+                        };
+                    } else if let Some(field) = form.field_mut() {
+                        field.push(ch);
+                    }
+                    form.tidy_lists();
+                    form.error = None;
+                }
+                Mode::Prompt(prompt) => {
+                    prompt.input.push(ch);
+                }
+                Mode::Settings(form) => {
+                    form.input(ch);
+                    form.error = None;
+                }
+                Mode::GlobalOptions(global) => {
+                    if let Some(line) = global.lines.get_mut(global.current) {
+                        line.push(ch);
+                    }
+                    tidy_lines(&mut global.lines);
+                    global.error = None;
+                }
+                Mode::Normal if ch.is_ascii_digit() => {
+                    state.jump_buffer.push(ch);
+                    if let Some(index) = state.jump_index() {
+                        state.selected_index = index;
+                    }
+                }
+                // Unbound letters jump to the next host starting with them, wrapping around
+                Mode::Normal if ch.is_alphanumeric() => {
+                    let count = state.rows.len();
+                    let starts_with = |pos: usize| {
+                        let ListRow::Host { index, .. } = state.rows[pos] else { return false };
+                        let host = &state.hosts[index];
+                        host.primary_alias().chars().next().is_some_and(|c| c.to_lowercase().eq(ch.to_lowercase()))
                     };
-                    field.push(ch);
+                    if let Some(pos) = (1..=count).map(|step| (state.selected_index + step) % count).find(|&pos| starts_with(pos)) {
+                        state.selected_index = pos;
+                    }
                 }
                 _ => {}
             }
@@ -199,17 +1750,29 @@ fn handle_action(action: UiAction, state: &mut AppState, ssh_cfg: &mut SshConfig
             match &mut state.mode {
                 Mode::Filter => {
                     state.filter_text.pop();
-                    state.apply_filter();
+                    state.filter_pending = true;
                 }
                 Mode::EditForm(form) => {
-                    let field = match form.current_field {
-                        0 => &mut form.pattern,
-                        1 => &mut form.hostname,
-                        2 => &mut form.user,
-                        3 => &mut form.port,
-                        _ => return Ok(LoopControl::Continue),
-                    };
-                    field.pop();
+                    if let Some(toggle) = form.toggle_mut() {
+                        *toggle = None;
+                    } else if let Some(field) = form.field_mut() {
+                        field.pop();
+                    }
+                    form.tidy_lists();
+                    form.error = None;
+                }
+                Mode::Prompt(prompt) => {
+                    prompt.input.pop();
+                }
+                Mode::Settings(form) => {
+                    form.backspace();
+                    form.error = None;
+                }
+                Mode::GlobalOptions(global) => {
+                    if let Some(line) = global.lines.get_mut(global.current) {
+                        line.pop();
+                    }
+                    global.error = None;
                 }
                 _ => {}
             }
@@ -221,112 +1784,1193 @@ fn handle_action(action: UiAction, state: &mut AppState, ssh_cfg: &mut SshConfig
                     state.apply_filter();
                     state.mode = Mode::Normal;
                 }
-                Mode::Confirm(_) => {
-                    state.mode = Mode::Normal;
-                    state.needs_full_redraw = true;
+                Mode::Confirm(_) => dismiss_confirm(state),
+                Mode::Normal if !state.marked.is_empty() => {
+                    state.marked.clear();
+                    state.status_message = Some("marks cleared".to_string());
+                }
+                Mode::Normal if !state.filter_text.is_empty() => {
+                    state.filter_text.clear();
+                    state.resort();
                 }
+                // Esc with nothing left to cancel quits, like q
+                Mode::Normal if state.jump_buffer.is_empty() => return Ok(LoopControl::Exit),
                 _ => {}
             }
         }
+        // On a tree group header, Space expands or collapses it instead
+        ToggleMark if state.toggle_selected_group() => {}
+        ToggleMark => {
+            if let Some(index) = state.selected_host_index() {
+                if !state.marked.remove(&index) {
+                    state.marked.insert(index);
+                }
+                if state.selected_index + 1 < state.rows.len() {
+                    state.selected_index += 1;
+                }
+                state.status_message = Some(format!("{} marked", state.marked.len()));
+            }
+        }
         EditSelected => {
             if let Some(entry) = state.selected_host().cloned() {
-                state.mode = Mode::EditForm(FormData {
-                    is_editing: true,
-                    pattern: entry.pattern,
-                    hostname: entry.hostname.unwrap_or_default(),
-                    user: entry.user.unwrap_or_default(),
-                    port: entry.port.map(|p| p.to_string()).unwrap_or_default(),
-                    current_field: 0,
-                });
+                state.mode = Mode::EditForm(FormData::new(Some(entry)));
                 state.needs_full_redraw = true;
             }
         }
         NewHost => {
-            state.mode = Mode::EditForm(FormData {
-                is_editing: false,
-                pattern: String::new(),
-                hostname: String::new(),
-                user: String::new(),
-                port: String::new(),
-                current_field: 0,
-            });
+            state.mode = Mode::EditForm(FormData::new(None));
             state.needs_full_redraw = true;
         }
+        CloneSelected => {
+            if let Some(entry) = state.selected_host().cloned() {
+                state.mode = Mode::EditForm(FormData::cloned_from(entry, &state.hosts));
+                state.needs_full_redraw = true;
+            }
+        }
+        Undo => {
+            let message = match ssh_cfg.undo()? {
+                Some(description) => {
+                    state.hosts = ssh_cfg.list_hosts();
+                    state.marked.clear();
+                    state.resort();
+                    format!("Undid {}", description)
+                }
+                None => "Nothing to undo".to_string(),
+            };
+            state.status_message = Some(message);
+        }
+        CopySelectedAs => {
+            if let Some(entry) = state.selected_host() {
+                state.mode = Mode::Prompt(PromptData {
+                    kind: PromptKind::CopyAs { source: entry.pattern.clone() },
+                    input: String::new(),
+                });
+            }
+        }
+        PromptSubmit => {
+            if let Mode::Prompt(prompt) = std::mem::replace(&mut state.mode, Mode::Normal) {
+                state.needs_full_redraw = true;
+                let result = match prompt.kind {
+                    PromptKind::CopyAs { source } => copy_host_as(state, ssh_cfg, &source, prompt.input.trim()),
+                    PromptKind::Rename { host } => rename_host(state, ssh_cfg, &host, prompt.input.trim()),
+                    PromptKind::Export { hosts } => {
+                        let input = prompt.input.trim();
+                        let path = expand_tilde(input);
+                        if input.is_empty() {
+                            Err(anyhow::anyhow!("no file given"))
+                        } else if path.exists() {
+                            state.last_export_path = input.to_string();
+                            state.mode = Mode::Confirm(ConfirmContext::OverwriteExport { path, hosts });
+                            Ok(())
+                        } else {
+                            state.last_export_path = input.to_string();
+                            export_to(state, &path, &hosts)
+                        }
+                    }
+                    PromptKind::ExtraArgs { host } => match split_args(&prompt.input) {
+                        Ok(extra_args) => {
+                            state.last_extra_args = prompt.input.trim().to_string();
+                            return Ok(LoopControl::Launch { program: Program::Ssh, host: Box::new(host), extra_args, command: None, pause: false });
+                        }
+                        Err(e) => Err(e),
+                    },
+                    PromptKind::RemoteCommand { host } => {
+                        let command = prompt.input.trim();
+                        if command.is_empty() {
+                            Err(anyhow::anyhow!("no command to run"))
+                        } else {
+                            state.last_remote_command = command.to_string();
+                            let command = Some(command.to_string());
+                            return Ok(LoopControl::Launch { program: Program::Ssh, host: Box::new(host), extra_args: vec![], command, pause: false });
+                        }
+                    }
+                    PromptKind::Forward { host } => {
+                        let spec = prompt.input.trim();
+                        match validate_forward_spec(spec) {
+                            Ok(()) => return Ok(forward_launch(host, spec)),
+                            Err(e) => Err(e),
+                        }
+                    }
+                };
+                if let Err(e) = result {
+                    state.status_message = Some(format!("error: {}", e));
+                }
+            }
+        }
+        LaunchWithForward => {
+            if let Some(entry) = state.selected_host().cloned() {
+                match entry.forward.clone() {
+                    Some(spec) => return Ok(forward_launch(entry, &spec)),
+                    None => {
+                        state.mode = Mode::Prompt(PromptData {
+                            kind: PromptKind::Forward { host: entry },
+                            input: String::new(),
+                        });
+                    }
+                }
+            }
+        }
+        LaunchWithArgs => {
+            if let Some(entry) = state.selected_host().cloned() {
+                state.mode = Mode::Prompt(PromptData {
+                    kind: PromptKind::ExtraArgs { host: entry },
+                    input: state.last_extra_args.clone(),
+                });
+            }
+        }
+        ExportHosts => {
+            let hosts = state.export_selection();
+            if hosts.is_empty() {
+                state.status_message = Some("no hosts to export".to_string());
+            } else {
+                state.mode = Mode::Prompt(PromptData {
+                    kind: PromptKind::Export { hosts },
+                    input: state.last_export_path.clone(),
+                });
+            }
+        }
+        RenameSelected => {
+            if let Some(entry) = state.selected_host().cloned() {
+                state.mode = Mode::Prompt(PromptData { input: entry.pattern.clone(), kind: PromptKind::Rename { host: entry } });
+            }
+        }
+        RunCommand => {
+            if let Some(entry) = state.selected_host().cloned() {
+                state.mode = Mode::Prompt(PromptData {
+                    kind: PromptKind::RemoteCommand { host: entry },
+                    input: state.last_remote_command.clone(),
+                });
+            }
+        }
+        PromptCancel => {
+            if matches!(state.mode, Mode::Prompt(_)) {
+                state.mode = Mode::Normal;
+                state.needs_full_redraw = true;
+            }
+        }
+        CopyCommand => {
+            if let Some(entry) = state.selected_host() {
+                let command = entry.ssh_command(state.settings.verbose_commands, state.ssh_config.as_deref());
+                copy_text(state, command);
+            }
+        }
+        CopyHostname => {
+            if let Some(entry) = state.selected_host() {
+                let hostname = entry.connect_host().to_string();
+                copy_text(state, hostname);
+            }
+        }
+        CopyAddress => {
+            if let Some(host) = state.selected_host().cloned() {
+                if host.is_wildcard() {
+                    let message = format!("'{}' is a wildcard pattern; pick a concrete host to resolve", host.pattern);
+                    state.set_status(StatusKind::Failure, message);
+                } else {
+                    state.status_message = Some(format!("resolving {}…", host.connect_host()));
+                    state.lookup_requested = Some(host.pattern);
+                }
+            }
+        }
+        DeleteSelected if !state.marked.is_empty() => {
+            let mut marked: Vec<usize> = state.marked.iter().copied().collect();
+            marked.sort_unstable();
+            let hosts = marked.into_iter().map(|i| state.hosts[i].clone()).collect();
+            request_delete(state, ssh_cfg, ConfirmContext::DeleteMarked { hosts })?;
+        }
         DeleteSelected => {
             if let Some(entry) = state.selected_host().cloned() {
-                state.mode = Mode::Confirm(ConfirmContext::Delete { pattern: entry.pattern });
+                request_delete(state, ssh_cfg, ConfirmContext::Delete { host: Box::new(entry) })?;
+            }
+        }
+        MergeDuplicates => {
+            if ssh_cfg.validate_config().is_empty() {
+                state.status_message = Some("no duplicate Host blocks".to_string());
+            } else {
+                state.mode = Mode::Confirm(ConfirmContext::MergeDuplicates);
                 state.needs_full_redraw = true;
             }
         }
+        ConfirmAccept => {
+            if matches!(state.mode, Mode::Confirm(_)) {
+                return accept_confirm(state, ssh_cfg);
+            }
+        }
         LaunchSelected => {
             if matches!(state.mode, Mode::Confirm(_)) {
                 // ignore Enter while confirming
+            } else if !state.jump_buffer.is_empty() && state.jump_index().is_none() {
+                state.status_message = Some(format!("no host #{}", state.jump_buffer));
+            } else if state.toggle_selected_group() {
+                // Enter on a group header expands or collapses it
             } else if let Some(entry) = state.selected_host() {
-                return Ok(LoopControl::Launch(entry.pattern.clone()));
+                return Ok(LoopControl::Launch { program: Program::Ssh, host: Box::new(entry.clone()), extra_args: vec![], command: None, pause: false });
+            }
+        }
+        LaunchVerbose => {
+            if let Some(entry) = state.selected_host() {
+                let extra_args = vec!["-vvv".to_string()];
+                return Ok(LoopControl::Launch { program: Program::Ssh, host: Box::new(entry.clone()), extra_args, command: None, pause: true });
+            }
+        }
+        LaunchSftp => {
+            if let Some(entry) = state.selected_host() {
+                return Ok(LoopControl::Launch { program: Program::Sftp, host: Box::new(entry.clone()), extra_args: vec![], command: None, pause: false });
+            }
+        }
+        ShowResolved => match &state.mode {
+            Mode::Normal => {
+                if let Some(host) = state.selected_host().map(|h| h.primary_alias().to_string()) {
+                    match resolve_with_ssh(&host, state.ssh_config.as_deref()) {
+                        Ok(lines) => {
+                            state.mode = Mode::Resolved { host, lines, scroll: 0 };
+                        }
+                        Err(e) => state.set_status(StatusKind::Failure, format!("{:#}", e)),
+                    }
+                }
+            }
+            Mode::Resolved { .. } => {
+                state.mode = Mode::Normal;
+                state.needs_full_redraw = true;
+            }
+            _ => {}
+        },
+        ToggleDiagnostics => match &state.mode {
+            Mode::Normal => {
+                let lines: Vec<String> = ssh_cfg
+                    .parse_warnings()
+                    .iter()
+                    .map(ToString::to_string)
+                    .chain(ssh_cfg.validate_config())
+                    .collect();
+                if lines.is_empty() {
+                    state.status_message = Some("no problems found in the config".to_string());
+                } else {
+                    state.mode = Mode::Diagnostics { lines, scroll: 0 };
+                }
+            }
+            Mode::Diagnostics { .. } => {
+                state.mode = Mode::Normal;
+                state.needs_full_redraw = true;
+            }
+            _ => {}
+        },
+        ShowMatchBlocks => match &state.mode {
+            Mode::Normal => {
+                let lines = match_block_lines(&ssh_cfg.list_matches());
+                if lines.is_empty() {
+                    state.status_message = Some("the config has no Match blocks".to_string());
+                } else {
+                    state.mode = Mode::MatchBlocks { lines, scroll: 0 };
+                }
+            }
+            Mode::MatchBlocks { .. } => {
+                state.mode = Mode::Normal;
+                state.needs_full_redraw = true;
+            }
+            _ => {}
+        },
+        ProbeHosts => {
+            state.sweep_requested = true;
+        }
+        TestConnection => {
+            if let Some(host) = state.selected_host().cloned() {
+                if host.is_wildcard() {
+                    let message = format!("'{}' is a wildcard pattern; pick a concrete host to test", host.pattern);
+                    state.set_status(StatusKind::Failure, message);
+                } else {
+                    state.status_message = Some(format!("testing {}…", host.primary_alias()));
+                    state.test_requested = Some(host.pattern);
+                }
+            }
+        }
+        ToggleLatency => {
+            state.show_latency = !state.show_latency;
+        }
+        ToggleSearchAll => {
+            state.search_all_fields = !state.search_all_fields;
+            state.apply_filter();
+            state.status_message = Some(if state.search_all_fields {
+                "search: all fields and options".to_string()
+            } else {
+                "search: name, hostname, user".to_string()
+            });
+        }
+        CycleSort => {
+            state.sort_mode = state.sort_mode.next();
+            state.resort();
+            state.settings.sort_mode = state.sort_mode;
+            match settings::save(&state.settings) {
+                Ok(()) => state.status_message = Some(format!("sort: {}", state.sort_mode.label())),
+                Err(e) => {
+                    let message = format!("sort: {} (could not save settings: {:#})", state.sort_mode.label(), e);
+                    state.set_status(StatusKind::Failure, message);
+                }
+            }
+        }
+        TogglePreview => {
+            state.show_preview = !state.show_preview;
+        }
+        ToggleTree => {
+            state.tree_view = !state.tree_view;
+            state.resort();
+            state.settings.tree_view = state.tree_view;
+            let view = if state.tree_view { "grouped by name prefix" } else { "flat list" };
+            match settings::save(&state.settings) {
+                Ok(()) => state.status_message = Some(format!("view: {}", view)),
+                Err(e) => state.set_status(StatusKind::Failure, format!("view: {} (could not save settings: {:#})", view, e)),
+            }
+        }
+        Click { column, row } => {
+            let view = state.list_view.get();
+            let inner = view.area.inner(Margin { horizontal: 1, vertical: 1 });
+            if !inner.contains(Position { x: column, y: row }) {
+                return Ok(LoopControl::Continue);
+            }
+            let index = view.offset + usize::from(row - inner.y);
+            if index >= state.rows.len() {
+                return Ok(LoopControl::Continue);
+            }
+            state.selected_index = index;
+            let double = state
+                .last_click
+                .is_some_and(|(at, previous)| previous == index && at.elapsed() <= DOUBLE_CLICK_INTERVAL);
+            state.last_click = if double { None } else { Some((Instant::now(), index)) };
+            if double && !state.toggle_selected_group() {
+                if let Some(entry) = state.selected_host() {
+                    return Ok(LoopControl::Launch { program: Program::Ssh, host: Box::new(entry.clone()), extra_args: vec![], command: None, pause: false });
+                }
             }
         }
-        FormNextField => {
-            if let Mode::EditForm(form) = &mut state.mode {
-                form.current_field = (form.current_field + 1) % 4;
+        ToggleHelp => match state.mode {
+            Mode::Normal => {
+                state.mode = Mode::Help;
+                state.help_scroll = 0;
+            }
+            Mode::Help => {
+                state.mode = Mode::Normal;
+                state.needs_full_redraw = true;
+            }
+            _ => {}
+        },
+        ScrollPreviewDown => {
+            if let Mode::Help = state.mode {
+                state.help_scroll = state.help_scroll.saturating_add(1);
+            } else if let Mode::Resolved { lines, scroll, .. } | Mode::Diagnostics { lines, scroll } | Mode::MatchBlocks { lines, scroll } =
+                &mut state.mode
+            {
+                *scroll = scroll.saturating_add(1).min(u16::try_from(lines.len().saturating_sub(1)).unwrap_or(u16::MAX));
+            } else if state.show_preview {
+                state.preview_scroll = state.preview_scroll.saturating_add(1);
             }
         }
-        FormPrevField => {
-            if let Mode::EditForm(form) = &mut state.mode {
-                form.current_field = if form.current_field == 0 { 3 } else { form.current_field - 1 };
+        ScrollPreviewUp => {
+            if let Mode::Help = state.mode {
+                state.help_scroll = state.help_scroll.saturating_sub(1);
+            } else if let Mode::Resolved { scroll, .. } | Mode::Diagnostics { scroll, .. } | Mode::MatchBlocks { scroll, .. } = &mut state.mode {
+                *scroll = scroll.saturating_sub(1);
+            } else {
+                state.preview_scroll = state.preview_scroll.saturating_sub(1);
             }
         }
+        FormNextField => match &mut state.mode {
+            Mode::EditForm(form) => {
+                form.tidy_lists();
+                form.current_field = (form.current_field + 1) % form.field_count();
+            }
+            Mode::GlobalOptions(global) => {
+                tidy_lines(&mut global.lines);
+                global.current = (global.current + 1) % global.lines.len();
+            }
+            Mode::Settings(form) => form.current = (form.current + 1) % SETTINGS_ROWS.len(),
+            _ => {}
+        },
+        FormPrevField => match &mut state.mode {
+            Mode::EditForm(form) => {
+                form.tidy_lists();
+                form.current_field = (form.current_field + form.field_count() - 1) % form.field_count();
+            }
+            Mode::GlobalOptions(global) => {
+                tidy_lines(&mut global.lines);
+                global.current = (global.current + global.lines.len() - 1) % global.lines.len();
+            }
+            Mode::Settings(form) => form.current = (form.current + SETTINGS_ROWS.len() - 1) % SETTINGS_ROWS.len(),
+            _ => {}
+        },
+        ShowGlobalOptions => {
+            let block = state.hosts.iter().find(|h| h.pattern == GLOBAL_PATTERN);
+            state.mode = Mode::GlobalOptions(GlobalOptions::new(block));
+            state.needs_full_redraw = true;
+        }
+        FormSubmit if matches!(state.mode, Mode::GlobalOptions(_)) => save_global_options(state, ssh_cfg)?,
+        FormSubmit if matches!(state.mode, Mode::Settings(_)) => save_settings_form(state),
+        EditConfigFile => return Ok(LoopControl::EditConfig),
+        ShowSettings => {
+            state.mode = Mode::Settings(SettingsForm::new(&state.settings));
+        }
         FormSubmit => {
             if let Mode::EditForm(form) = &state.mode {
-                let port_num = if form.port.trim().is_empty() { 
-                    None 
-                } else { 
-                    match form.port.trim().parse::<u16>() {
-                        Ok(p) if p > 0 => Some(p),
-                        _ => return Err(anyhow::anyhow!("Invalid port number")),
+                let form = form.clone();
+                let pattern = form.pattern.trim();
+                let original = form.original_pattern.as_ref().and_then(|p| state.hosts.iter().find(|h| &h.pattern == p));
+
+                // Only hosts that already list several aliases may keep doing so
+                let allow_aliases = original.is_some_and(|h| h.aliases().count() > 1);
+                if let Err(e) = validate_pattern(pattern, allow_aliases) {
+                    set_form_error(state, e.to_string());
+                    return Ok(LoopControl::Continue);
+                }
+                // Adding, or renaming during an edit, must not shadow another host
+                let renamed = original.is_none_or(|h| h.pattern != pattern);
+                if renamed {
+                    if let Some(other) = state.hosts.iter().find(|h| Some(*h) != original && h.shares_alias_with(pattern)) {
+                        set_form_error(state, format!("host '{}' already uses that name", other.pattern));
+                        return Ok(LoopControl::Continue);
+                    }
+                }
+
+                // `host:port` in HostName fills both fields; the Port field wins
+                let (hostname, hostname_port) = match split_host_port(&form.hostname) {
+                    Ok(parts) => parts,
+                    Err(e) => {
+                        set_form_error(state, e.to_string());
+                        return Ok(LoopControl::Continue);
+                    }
+                };
+                let port_num = match parse_port(&form.port) {
+                    Ok(port) => port.or(hostname_port),
+                    Err(e) => {
+                        set_form_error(state, e.to_string());
+                        return Ok(LoopControl::Continue);
                     }
                 };
                 
+                let mut other = match parse_advanced(&form.advanced) {
+                    Ok(other) => other,
+                    Err(e) => {
+                        set_form_error(state, e.to_string());
+                        return Ok(LoopControl::Continue);
+                    }
+                };
+                match parse_forward_lines(&form.forwards) {
+                    Ok(forwards) => other.extend(forwards),
+                    Err(e) => {
+                        set_form_error(state, e.to_string());
+                        return Ok(LoopControl::Continue);
+                    }
+                }
+                for (name, value) in FORM_TOGGLES.iter().zip(form.toggles) {
+                    let Some(value) = value else { continue };
+                    if other.iter().any(|(k, _)| k.eq_ignore_ascii_case(name)) {
+                        set_form_error(state, format!("{} is set by its checkbox; remove its Advanced line", name));
+                        return Ok(LoopControl::Continue);
+                    }
+                    other.push((name.to_string(), if value { "yes" } else { "no" }.to_string()));
+                }
+                // New hosts get the configured defaults, unless the form sets the option itself
+                if original.is_none() {
+                    match default_options(&state.settings) {
+                        Ok(defaults) => {
+                            for (key, value) in defaults {
+                                let in_form = other.iter().any(|(k, _)| k.eq_ignore_ascii_case(&key))
+                                    || (key.eq_ignore_ascii_case("IdentityFile") && !form.identity_file.trim().is_empty());
+                                if !in_form {
+                                    other.push((key, value));
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            set_form_error(state, format!("{:#}", e));
+                            return Ok(LoopControl::Continue);
+                        }
+                    }
+                }
+
+                // Keep fields the form doesn't expose when editing an existing host
+                // or cloning one
+                let template = form.template.as_ref().and_then(|p| state.hosts.iter().find(|h| &h.pattern == p));
+                let base = original.or(template).cloned().unwrap_or_default();
                 let entry = SshHostEntry {
                     pattern: form.pattern.trim().to_string(),
-                    hostname: if form.hostname.trim().is_empty() { None } else { Some(form.hostname.trim().to_string()) },
+                    hostname: if hostname.is_empty() { None } else { Some(hostname) },
                     user: if form.user.trim().is_empty() { None } else { Some(form.user.trim().to_string()) },
                     port: port_num,
-                    other: vec![],
+                    identity_file: if form.identity_file.trim().is_empty() { None } else { Some(form.identity_file.trim().to_string()) },
+                    proxy_jump: if form.proxy_jump.trim().is_empty() { None } else { Some(form.proxy_jump.trim().to_string()) },
+                    other,
+                    note: if form.note.trim().is_empty() { None } else { Some(form.note.trim().to_string()) },
+                    ..base
                 };
                 
-                // Validate entry before saving
-                entry.validate()?;
-                
-                ssh_cfg.upsert_host(&entry)?;
+                // Validate entry before saving; failures keep the form open. An
+                // edit rewrites exactly the block it opened, even when the
+                // pattern changed, and never another that shares an alias
+                let original = form.original_pattern.as_deref();
+                if let Err(e) = entry.validate().and_then(|()| ssh_cfg.upsert_exact_host(original, &entry)) {
+                    set_form_error(state, e.to_string());
+                    return Ok(LoopControl::Continue);
+                }
                 state.hosts = ssh_cfg.list_hosts();
+                state.marked.clear();
                 state.apply_filter();
                 state.mode = Mode::Normal;
                 state.needs_full_redraw = true;
+                next_import(state);
+                let unknown = entry.unknown_jump_hosts(&state.hosts);
+                if !unknown.is_empty() {
+                    state.status_message =
+                        Some(format!("saved; ProxyJump host(s) not in your config: {}", unknown.join(", ")));
+                }
             }
         }
-        FormCancel => {
-            if matches!(state.mode, Mode::EditForm(_)) {
+        FormCancel => match &state.mode {
+            Mode::EditForm(form) if form.has_changes() => {
+                state.mode = Mode::Confirm(ConfirmContext::DiscardForm { form: Box::new(form.clone()), quit: false });
+            }
+            Mode::EditForm(_) => close_form(state),
+            Mode::GlobalOptions(_) | Mode::Settings(_) => {
                 state.mode = Mode::Normal;
                 state.needs_full_redraw = true;
             }
+            _ => {}
+        },
+        ImportKnownHosts => {
+            let path = known_hosts::default_known_hosts_path();
+            let text = match std::fs::read_to_string(&path) {
+                Ok(text) => text,
+                Err(e) => {
+                    state.set_status(StatusKind::Failure, format!("could not read {}: {}", path.display(), e));
+                    return Ok(LoopControl::Continue);
+                }
+            };
+            let defaults = match default_options(&state.settings) {
+                Ok(defaults) => defaults,
+                Err(e) => {
+                    state.set_status(StatusKind::Failure, format!("{:#}", e));
+                    return Ok(LoopControl::Continue);
+                }
+            };
+            let found = known_hosts::parse(&text);
+            // Hosts the config already names, as an alias or a HostName
+            let known = |name: &str| {
+                state.hosts.iter().any(|h| {
+                    h.aliases().any(|a| a.eq_ignore_ascii_case(name))
+                        || h.hostname.as_deref().is_some_and(|hn| hn.eq_ignore_ascii_case(name))
+                })
+            };
+            let hosts: Vec<SshHostEntry> = found
+                .hosts
+                .into_iter()
+                .filter(|(name, _)| !known(name) && validate_pattern(name, false).is_ok())
+                .map(|(name, port)| SshHostEntry {
+                    pattern: name.clone(),
+                    hostname: Some(name),
+                    port,
+                    other: defaults.clone(),
+                    ..Default::default()
+                })
+                .collect();
+            let hashed_note = match found.hashed {
+                0 => String::new(),
+                n => format!(" ({} hashed entries skipped)", n),
+            };
+            if hosts.is_empty() {
+                state.status_message = Some(format!("no new hosts in known_hosts{}", hashed_note));
+            } else {
+                state.mode = Mode::Confirm(ConfirmContext::ImportKnownHosts { hosts, hashed: found.hashed });
+                state.needs_full_redraw = true;
+            }
         }
-        Quit => return Ok(LoopControl::Exit),
+        Quit => match &state.mode {
+            Mode::EditForm(form) if form.has_changes() => {
+                state.mode = Mode::Confirm(ConfirmContext::DiscardForm { form: Box::new(form.clone()), quit: true });
+            }
+            _ => return Ok(LoopControl::Exit),
+        },
         Noop => {}
     }
     Ok(LoopControl::Continue)
 }
 
-fn launch_ssh(host_pattern: &str) -> Result<()> {
-    // Let user's ssh config resolve the final host; rely on external ssh binary
-    let status = Command::new("ssh").arg(host_pattern).status().context("failed to spawn ssh")?;
-    if !status.success() {
-        eprintln!("ssh exited with status: {}", status);
+/// `matches` as the `m` window shows them: each `Match` line with where it is,
+/// then its options indented, and a blank line between blocks.
+fn match_block_lines(matches: &[MatchEntry]) -> Vec<String> {
+    let mut lines = Vec::new();
+    for (n, entry) in matches.iter().enumerate() {
+        if n > 0 {
+            lines.push(String::new());
+        }
+        let file = entry.source_path.as_deref().and_then(Path::file_name).map(|f| f.to_string_lossy()).unwrap_or_default();
+        lines.push(format!("Match {}    ({}:{})", entry.criteria, file, entry.line));
+        lines.extend(entry.options.iter().map(|(k, v)| format!("    {} {}", k, v)));
+    }
+    lines
+}
+
+/// Parse the form's advanced `Key value` lines, skipping blank ones. Options the
+/// form has its own field for are rejected so they cannot be written twice.
+fn parse_advanced(lines: &[String]) -> Result<Vec<(String, String)>> {
+    let other = parse_option_lines(lines)?;
+    let own_field = |key: &str| matches!(key.to_ascii_lowercase().as_str(), "hostname" | "user" | "port" | "proxyjump");
+    if let Some((key, _)) = other.iter().find(|(key, _)| own_field(key)) {
+        return Err(anyhow::anyhow!("Set {} with its own field, not as an advanced option", key));
+    }
+    Ok(other)
+}
+
+/// Parse the form's Forwards lines (`L 8080 db:80`, `L 8080:db:80`, `D 1080`)
+/// into forward options, skipping blank ones. The kind may also be spelled out.
+fn parse_forward_lines(lines: &[String]) -> Result<Vec<(String, String)>> {
+    let mut forwards = Vec::new();
+    for line in lines.iter().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+        let (kind, spec) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let Some((_, option)) = FORM_FORWARDS.iter().find(|(letter, name)| letter.eq_ignore_ascii_case(kind) || name.eq_ignore_ascii_case(kind)) else {
+            return Err(anyhow::anyhow!("Forward '{}' must start with L, R, or D", line));
+        };
+        forwards.push((option.to_string(), normalize_forward(option, spec)?));
+    }
+    Ok(forwards)
+}
+
+/// Parse `Key value` (or `Key=value`) lines for a host block, skipping blank ones.
+fn parse_option_lines(lines: &[String]) -> Result<Vec<(String, String)>> {
+    let mut options = Vec::new();
+    for line in lines.iter().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+        let (key, value) = line.split_once(|c: char| c.is_whitespace() || c == '=').unwrap_or((line, ""));
+        let value = value.trim_start_matches(|c: char| c.is_whitespace() || c == '=').trim();
+        if !key.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(anyhow::anyhow!("Option name '{}' must be letters and digits only", key));
+        }
+        if matches!(key.to_ascii_lowercase().as_str(), "host" | "match" | "include") {
+            return Err(anyhow::anyhow!("{} cannot be used inside a host block", key));
+        }
+        if value.is_empty() {
+            return Err(anyhow::anyhow!("Option '{}' needs a value", key));
+        }
+        options.push((key.to_string(), value.to_string()));
+    }
+    Ok(options)
+}
+
+/// `settings.default_options`, checked the same way as the form's Advanced lines.
+fn default_options(settings: &AppSettings) -> Result<Vec<(String, String)>> {
+    let lines: Vec<String> = settings.default_options.iter().map(|(k, v)| format!("{} {}", k, v)).collect();
+    parse_advanced(&lines).context("default_options in settings")
+}
+
+/// Split a command line into arguments the way a POSIX shell would for simple
+/// cases: whitespace separates, single quotes are literal, double quotes allow
+/// backslash escapes, and a backslash outside quotes escapes the next character.
+fn split_args(input: &str) -> Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_word = false;
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_word {
+                    args.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => current.push(c),
+                        None => return Err(anyhow::anyhow!("unterminated single quote")),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) => current.push(c),
+                            None => return Err(anyhow::anyhow!("unterminated double quote")),
+                        },
+                        Some(c) => current.push(c),
+                        None => return Err(anyhow::anyhow!("unterminated double quote")),
+                    }
+                }
+            }
+            '\\' => {
+                in_word = true;
+                if let Some(c) = chars.next() {
+                    current.push(c);
+                }
+            }
+            c => {
+                in_word = true;
+                current.push(c);
+            }
+        }
+    }
+    if in_word {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+fn set_form_error(state: &mut AppState, message: String) {
+    if let Mode::EditForm(form) = &mut state.mode {
+        form.error = Some(message);
+    }
+}
+
+/// Answer yes to the open confirmation (`y` or Enter).
+fn accept_confirm(state: &mut AppState, ssh_cfg: &mut SshConfigFile) -> Result<LoopControl> {
+    let Mode::Confirm(ctx) = &state.mode else {
+        return Ok(LoopControl::Continue);
+    };
+    // A dialog opened before read-only mode was turned on can't write either
+    let writes = matches!(
+        ctx,
+        ConfirmContext::Delete { .. } | ConfirmContext::DeleteMarked { .. } | ConfirmContext::MergeDuplicates | ConfirmContext::ImportKnownHosts { .. }
+    );
+    if state.read_only && writes {
+        state.mode = Mode::Normal;
+        state.needs_full_redraw = true;
+        state.set_status(StatusKind::Failure, "read-only mode".to_string());
+        return Ok(LoopControl::Continue);
+    }
+    let written = match ctx.clone() {
+        ConfirmContext::DiscardForm { quit: true, .. } => return Ok(LoopControl::Exit),
+        ConfirmContext::DiscardForm { quit: false, .. } => {
+            close_form(state);
+            return Ok(LoopControl::Continue);
+        }
+        ConfirmContext::OverwriteExport { path, hosts } => {
+            state.mode = Mode::Normal;
+            state.needs_full_redraw = true;
+            if let Err(e) = export_to(state, &path, &hosts) {
+                state.set_status(StatusKind::Failure, format!("error: {}", e));
+            }
+            return Ok(LoopControl::Continue);
+        }
+        // A write that fails (a read-only file, a change `validate_write`
+        // refuses) is reported in the footer and leaves the picker running
+        ConfirmContext::Delete { host } => ssh_cfg.delete_exact_host(&host).map(|()| None),
+        ConfirmContext::DeleteMarked { hosts } => {
+            ssh_cfg.delete_exact_hosts(&hosts).map(|()| Some(format!("deleted {} hosts", hosts.len())))
+        }
+        ConfirmContext::MergeDuplicates => {
+            ssh_cfg.merge_duplicates().map(|removed| Some(format!("removed {} duplicate Host block(s)", removed)))
+        }
+        ConfirmContext::ImportKnownHosts { hosts, .. } => {
+            ssh_cfg.add_hosts(&hosts).map(|added| Some(format!("imported {} hosts from known_hosts", added)))
+        }
+    };
+    state.mode = Mode::Normal;
+    state.needs_full_redraw = true;
+    match written {
+        Ok(message) => {
+            state.status_message = message;
+            state.hosts = ssh_cfg.list_hosts();
+            state.marked.clear();
+            state.apply_filter();
+        }
+        Err(e) => state.set_status(StatusKind::Failure, format!("error: {:#}", e)),
+    }
+    Ok(LoopControl::Continue)
+}
+
+/// How long the footer offers undo after a delete that wasn't confirmed.
+const UNDO_HINT_DURATION: Duration = Duration::from_secs(3);
+
+/// Ask before a delete, or with `confirm_deletes` off take a second press of
+/// the key (like vim's `dd`) and delete right away, offering undo in the footer.
+fn request_delete(state: &mut AppState, ssh_cfg: &mut SshConfigFile, ctx: ConfirmContext) -> Result<()> {
+    let what = match &ctx {
+        ConfirmContext::DeleteMarked { hosts } => format!("{} hosts", hosts.len()),
+        ConfirmContext::Delete { host } => host.pattern.clone(),
+        _ => String::new(),
+    };
+    if state.settings.confirm_deletes {
+        state.mode = Mode::Confirm(ctx);
+        state.needs_full_redraw = true;
+        return Ok(());
+    }
+    if !state.delete_pending {
+        state.delete_pending = true;
+        let key = state.keymap.label("DeleteSelected");
+        state.status_message = Some(format!("press {} again to delete {}", key, what));
+        return Ok(());
     }
+    state.delete_pending = false;
+    state.mode = Mode::Confirm(ctx);
+    accept_confirm(state, ssh_cfg)?;
+    if state.status_kind == StatusKind::Failure {
+        return Ok(());
+    }
+    let message = format!("deleted {} — press {} to undo", what, state.keymap.label("Undo"));
+    state.set_status(StatusKind::Success, message.clone());
+    state.status_expiry = Some((Instant::now() + UNDO_HINT_DURATION, message));
     Ok(())
 }
 
+/// Apply the settings screen and write it to `config.toml`. Values that don't
+/// parse, or a file that can't be written, keep the screen open.
+fn save_settings_form(state: &mut AppState) {
+    let Mode::Settings(form) = &mut state.mode else {
+        return;
+    };
+    let (settings, accent) = match form.apply(&state.settings) {
+        Ok(applied) => applied,
+        Err(e) => {
+            form.error = Some(e.to_string());
+            return;
+        }
+    };
+    if let Err(e) = settings::save(&settings) {
+        form.error = Some(format!("could not save settings: {:#}", e));
+        return;
+    }
+    state.settings = settings;
+    state.accent = accent;
+    state.mode = Mode::Normal;
+    state.needs_full_redraw = true;
+    state.set_status(StatusKind::Success, "settings saved".to_string());
+}
+
+/// Write the global options editor back to the `Host *` block, adding the
+/// block at the end of the config if there is none. Errors keep the editor open.
+fn save_global_options(state: &mut AppState, ssh_cfg: &mut SshConfigFile) -> Result<()> {
+    let Mode::GlobalOptions(global) = &mut state.mode else {
+        return Ok(());
+    };
+    let existing = state.hosts.iter().find(|h| h.pattern == GLOBAL_PATTERN);
+    if !global.has_changes() {
+        state.mode = Mode::Normal;
+        state.needs_full_redraw = true;
+        return Ok(());
+    }
+    let block = existing.cloned().unwrap_or_else(|| SshHostEntry { pattern: GLOBAL_PATTERN.to_string(), ..Default::default() });
+    // Clearing every option removes the block rather than leaving a bare `Host *`
+    let emptied = global.lines.iter().all(|l| l.trim().is_empty());
+    // Only the block that is exactly `Host *`; `Host web *` is a host of its own
+    let saved = if emptied {
+        ssh_cfg.delete_exact_host(&block)
+    } else {
+        parse_option_lines(&global.lines).and_then(|options| block.with_options(options)).and_then(|entry| ssh_cfg.upsert_exact_host(None, &entry))
+    };
+    if let Err(e) = saved {
+        global.error = Some(e.to_string());
+        return Ok(());
+    }
+    state.hosts = ssh_cfg.list_hosts();
+    state.marked.clear();
+    state.apply_filter();
+    state.mode = Mode::Normal;
+    state.needs_full_redraw = true;
+    let message = if emptied { "removed the empty Host * block" } else { "saved global options (Host *)" };
+    state.set_status(StatusKind::Success, message.to_string());
+    Ok(())
+}
+
+/// Leave the host form without saving; an import in progress stops too.
+fn close_form(state: &mut AppState) {
+    state.mode = Mode::Normal;
+    state.needs_full_redraw = true;
+    if !state.import_queue.is_empty() {
+        state.status_message = Some(format!("import stopped; {} hosts not added", state.import_queue.len() + 1));
+        state.import_queue.clear();
+    }
+}
+
+/// Answer no to a confirmation: back to the list, or to the form it interrupted.
+fn dismiss_confirm(state: &mut AppState) {
+    state.mode = match std::mem::replace(&mut state.mode, Mode::Normal) {
+        Mode::Confirm(ConfirmContext::DiscardForm { form, .. }) => Mode::EditForm(*form),
+        _ => Mode::Normal,
+    };
+    state.needs_full_redraw = true;
+}
+
+/// Open the form for the next host waiting to be imported, if any. Saving it
+/// moves on to the one after; Esc stops the import.
+fn next_import(state: &mut AppState) {
+    if state.import_queue.is_empty() {
+        return;
+    }
+    let entry = state.import_queue.remove(0);
+    let mut form = FormData::new(None);
+    form.pattern = entry.pattern;
+    form.hostname = entry.hostname.unwrap_or_default();
+    form.port = entry.port.map(|p| p.to_string()).unwrap_or_default();
+    form.notice = Some(format!(
+        "Importing from known_hosts: {} more after this. Enter saves, Esc stops the import",
+        state.import_queue.len()
+    ));
+    form.mark_clean();
+    state.mode = Mode::EditForm(form);
+    state.needs_full_redraw = true;
+}
+
+fn forward_launch(host: SshHostEntry, spec: &str) -> LoopControl {
+    LoopControl::Launch {
+        program: Program::Ssh,
+        host: Box::new(host),
+        extra_args: vec!["-L".to_string(), spec.to_string()],
+        command: None,
+        pause: false,
+    }
+}
+
+fn export_to(state: &mut AppState, path: &Path, hosts: &[SshHostEntry]) -> Result<()> {
+    export_hosts(path, hosts)?;
+    state.set_status(StatusKind::Success, format!("exported {} host(s) to {}", hosts.len(), path.display()));
+    Ok(())
+}
+
+/// Give `host` a new name by rewriting just its `Host` line. Its connection
+/// history moves with it.
+fn rename_host(state: &mut AppState, ssh_cfg: &mut SshConfigFile, host: &SshHostEntry, pattern: &str) -> Result<()> {
+    if pattern == host.pattern {
+        return Ok(());
+    }
+    validate_pattern(pattern, host.aliases().count() > 1)?;
+    if let Some(other) = state.hosts.iter().find(|h| h.pattern != host.pattern && h.shares_alias_with(pattern)) {
+        return Err(anyhow::anyhow!("host '{}' already uses that name", other.pattern));
+    }
+    ssh_cfg.rename_host(host, pattern)?;
+    state.hosts = ssh_cfg.list_hosts();
+    state.marked.clear();
+    state.apply_filter();
+    state.select_pattern(pattern);
+    if let Some(history) = state.saved.history.remove(&host.pattern) {
+        state.saved.history.insert(pattern.to_string(), history);
+    }
+    if state.saved.last_host.as_deref() == Some(host.pattern.as_str()) {
+        state.saved.last_host = Some(pattern.to_string());
+    }
+    state.status_message = Some(format!("renamed '{}' to '{}'", host.pattern, pattern));
+    let saved = settings::save_state(&state.saved);
+    report_state_save(state, saved);
+    Ok(())
+}
+
+fn copy_host_as(state: &mut AppState, ssh_cfg: &mut SshConfigFile, source: &str, pattern: &str) -> Result<()> {
+    let Some(mut entry) = state.hosts.iter().find(|h| h.pattern == source).cloned() else {
+        return Err(anyhow::anyhow!("host '{}' no longer exists", source));
+    };
+    if state.hosts.iter().any(|h| h.shares_alias_with(pattern)) {
+        return Err(anyhow::anyhow!("host '{}' already exists", pattern));
+    }
+    validate_pattern(pattern, false)?;
+    entry.pattern = pattern.to_string();
+    entry.validate()?;
+
+    ssh_cfg.upsert_host(None, &entry)?;
+    state.hosts = ssh_cfg.list_hosts();
+    state.marked.clear();
+    state.apply_filter();
+    state.select_pattern(pattern);
+    state.status_message = Some(format!("created '{}' from '{}'", pattern, source));
+    Ok(())
+}
+
+/// Client programs a host can be opened with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Program {
+    Ssh,
+    Sftp,
+}
+
+impl Program {
+    pub fn name(self) -> &'static str {
+        match self {
+            Program::Ssh => "ssh",
+            Program::Sftp => "sftp",
+        }
+    }
+
+    /// Arguments naming `host` (and `config`, if any); sftp spells the port flag `-P`.
+    fn args(self, host: &SshHostEntry, config: Option<&Path>) -> Vec<String> {
+        let mut args = host.ssh_args(false, config);
+        if self == Program::Sftp {
+            if let Some(flag) = args.iter_mut().find(|a| *a == "-p") {
+                *flag = "-P".to_string();
+            }
+        }
+        args
+    }
+}
+
+/// What ssh makes of `host` with every `Host *` block and `Match` rule applied:
+/// the `key value` lines of `ssh -G`.
+fn resolve_with_ssh(host: &str, config: Option<&Path>) -> Result<Vec<String>> {
+    let config_args = config.into_iter().flat_map(|path| [Path::new("-F"), path]);
+    let output = Command::new("ssh").args(config_args).arg("-G").arg(host).output().context("could not run ssh -G")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow::anyhow!("ssh -G {} failed: {}", host, stderr.trim()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).lines().map(str::to_string).collect())
+}
+
+fn run_client(program: Program, entry: &SshHostEntry, extra_args: &[String], command: Option<&str>, config: Option<&Path>) -> Result<ExitStatus> {
+    // Let user's ssh config resolve the final host; rely on the external binary.
+    // The remote command is one argument: ssh hands it to the remote shell as is
+    let status = Command::new(program.name())
+        .args(extra_args)
+        .args(program.args(entry, config))
+        .args(command)
+        .status()
+        .with_context(|| format!("failed to spawn {}", program.name()))?;
+    Ok(status)
+}
+
 mod ui {
-    pub use crate::ui::read_event;
+    pub use crate::ui::{draw_loading, is_quit_key, parse_color, read_event, SPINNER_STEP};
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A picker with default settings listing `hosts` as (pattern, HostName).
+    fn picker(hosts: &[(&str, &str)]) -> AppState {
+        let hosts = hosts
+            .iter()
+            .map(|&(pattern, hostname)| SshHostEntry {
+                pattern: pattern.into(),
+                hostname: (!hostname.is_empty()).then(|| hostname.into()),
+                ..Default::default()
+            })
+            .collect();
+        AppState::new(hosts, AppSettings::default())
+    }
+
+    fn listed(state: &AppState) -> Vec<&str> {
+        state.filtered_hosts.iter().map(|&i| state.hosts[i].pattern.as_str()).collect()
+    }
+
+    fn filter(state: &mut AppState, text: &str) {
+        state.filter_text = text.to_string();
+        state.filter_pending = true;
+        state.flush_filter();
+    }
+
+    #[test]
+    fn regex_filter_matches_names_and_hostnames_ignoring_case() {
+        let mut state = picker(&[("Prod-web-DB", ""), ("prod-cache", ""), ("box", "prod-1.db"), ("stage-db", "PROD-old-db")]);
+        filter(&mut state, "/^prod-.*-db$");
+        assert_eq!(listed(&state), ["Prod-web-DB", "stage-db"]);
+        assert_eq!(state.status_kind, StatusKind::Info);
+        // Highlights are byte offsets of each matched character
+        assert_eq!(state.filter_highlights("Prod-web-DB"), (0..11).collect::<Vec<_>>());
+        filter(&mut state, "/é");
+        assert_eq!(state.filter_highlights("café bar"), [3]);
+    }
+
+    #[test]
+    fn bad_regex_keeps_the_last_result_and_reports_one_line() {
+        let mut state = picker(&[("prod-a", ""), ("prod-b", ""), ("dev", "")]);
+        filter(&mut state, "/^prod");
+        assert_eq!(listed(&state), ["prod-a", "prod-b"]);
+        filter(&mut state, "/(prod");
+        assert_eq!(listed(&state), ["prod-a", "prod-b"]);
+        assert_eq!(state.status_kind, StatusKind::Failure);
+        assert_eq!(state.status_message.as_deref(), Some("regex: unclosed group"));
+
+        let mut warnings = Vec::new();
+        let rule = HostColor { pattern: Some("web[".into()), color: "red".into(), ..Default::default() };
+        assert!(compile_host_colors(&[rule], &mut warnings).is_empty());
+        assert_eq!(warnings, ["host_colors rule 1: bad pattern: unclosed character class"]);
+    }
+
+    #[test]
+    fn read_only_refuses_every_action_that_edits_the_config() {
+        let original = "Host a\n    User x\n\nHost a\n    User y\n";
+        let mut ssh_cfg = SshConfigFile::in_memory(original);
+        let mut state = AppState::new(ssh_cfg.list_hosts(), AppSettings::default());
+        state.read_only = true;
+        for action in [
+            UiAction::EditSelected,
+            UiAction::NewHost,
+            UiAction::DeleteSelected,
+            UiAction::Undo,
+            UiAction::EditConfigFile,
+            UiAction::MergeDuplicates,
+            UiAction::ImportKnownHosts,
+            UiAction::ShowGlobalOptions,
+        ] {
+            assert!(action.edits_config(), "{:?}", action);
+            let control = handle_action(action, &mut state, &mut ssh_cfg).unwrap();
+            assert!(matches!(control, LoopControl::Continue), "{:?}", action);
+            assert_eq!(state.mode, Mode::Normal, "{:?}", action);
+            assert_eq!(state.status_message.as_deref(), Some("read-only mode"), "{:?}", action);
+        }
+        assert_eq!(ssh_cfg.text, original);
+    }
+
+    #[test]
+    fn a_dialog_opened_before_read_only_mode_cannot_write() {
+        let original = "Host a\n    User x\n\nHost b\n";
+        let mut ssh_cfg = SshConfigFile::in_memory(original);
+        let mut state = AppState::new(ssh_cfg.list_hosts(), AppSettings::default());
+        handle_action(UiAction::DeleteSelected, &mut state, &mut ssh_cfg).unwrap();
+        assert!(matches!(state.mode, Mode::Confirm(ConfirmContext::Delete { .. })));
+        state.read_only = true;
+        handle_action(UiAction::ConfirmAccept, &mut state, &mut ssh_cfg).unwrap();
+        assert_eq!(state.mode, Mode::Normal);
+        assert_eq!(state.status_kind, StatusKind::Failure);
+        assert_eq!(ssh_cfg.text, original);
+
+        // Nor can a form
+        state.read_only = false;
+        handle_action(UiAction::EditSelected, &mut state, &mut ssh_cfg).unwrap();
+        assert!(matches!(state.mode, Mode::EditForm(_)));
+        state.read_only = true;
+        handle_action(UiAction::FormSubmit, &mut state, &mut ssh_cfg).unwrap();
+        assert!(matches!(state.mode, Mode::EditForm(_)));
+        assert_eq!(ssh_cfg.text, original);
+    }
+
+    #[test]
+    fn resort_keeps_the_same_host_selected() {
+        let mut state = picker(&[("c", "3.com"), ("a", "2.com"), ("b", "1.com"), ("d", "")]);
+        state.select_pattern("a");
+        let mut mode = SortMode::ConfigOrder;
+        for expected in [["a", "b", "c", "d"], ["d", "c", "b", "a"], ["b", "a", "c", "d"], ["c", "a", "b", "d"], ["c", "a", "b", "d"], ["c", "a", "b", "d"]] {
+            mode = mode.next();
+            state.sort_mode = mode;
+            state.resort();
+            assert_eq!(listed(&state), expected, "{:?}", mode);
+            assert_eq!(state.selected_host().map(|h| h.pattern.as_str()), Some("a"), "{:?}", mode);
+        }
+        assert_eq!(mode, SortMode::ConfigOrder);
+    }
+
+    #[test]
+    fn latency_sort_lists_fastest_then_unprobed_then_unreachable() {
+        let mut state = picker(&[("down", ""), ("slow", ""), ("unknown", ""), ("fast", "")]);
+        state.probe_results.insert("down".into(), ProbeResult::Unreachable);
+        state.probe_results.insert("slow".into(), ProbeResult::Reachable(Duration::from_millis(300)));
+        state.probe_results.insert("fast".into(), ProbeResult::Reachable(Duration::from_millis(20)));
+        state.sort_mode = SortMode::Latency;
+        state.resort();
+        assert_eq!(listed(&state), ["fast", "slow", "unknown", "down"]);
+    }
+
+    #[test]
+    fn frecency_sort_weighs_recent_connections_above_old_ones() {
+        let now = settings::unix_now();
+        let day = 24 * 60 * 60;
+        let mut state = picker(&[("never", ""), ("often-last-year", ""), ("twice-this-week", ""), ("once-just-now", "")]);
+        for (pattern, connections, age) in [("often-last-year", 10, 365 * day), ("twice-this-week", 2, 2 * day), ("once-just-now", 1, 0)] {
+            let history = settings::HostHistory { connections, last_connected: now - age };
+            state.saved.history.insert(pattern.into(), history);
+        }
+        state.sort_mode = SortMode::Frecency;
+        state.resort();
+        // 1 × 4 (this hour), 10 × 0.25 (older than a month), 2 × 1 (this week), 0
+        assert_eq!(listed(&state), ["once-just-now", "often-last-year", "twice-this-week", "never"]);
+    }
+}
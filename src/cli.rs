@@ -0,0 +1,291 @@
+use crate::ssh_config::{default_ssh_config_path, SshConfigFile, SshHostEntry};
+use anyhow::{bail, Context, Result};
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// Environment variable naming the ssh config file to use instead of `~/.ssh/config`.
+pub const CONFIG_ENV: &str = "SSH_PICKER_CONFIG";
+
+pub const USAGE: &str = "\
+Usage: ssh-picker [OPTIONS]
+       ssh-picker [OPTIONS] resolve <pattern> [--json]
+       ssh-picker [OPTIONS] --list [--format <columns>]
+
+Browse, edit, and connect to hosts from your ssh config.
+
+Commands:
+  resolve <pattern>   Print what ssh-picker parsed for a host (key=value, or JSON with --json)
+
+Options:
+  --config <path>     ssh config file to read and edit (default: $SSH_PICKER_CONFIG, then ~/.ssh/config)
+  --print             Print the chosen host's name instead of connecting
+  --read-only         Browse and connect only; adding, editing, and deleting hosts is disabled
+  --list              Print every host, one per line, and exit without opening the picker
+  --format <columns>  Comma-separated columns for --list, printed tab-separated
+                      (pattern, alias, hostname, user, port; default: pattern)
+  -h, --help          Show this help
+
+Exit status:
+  0    a host was launched (or printed with --print)
+  130  the picker was closed without launching anything
+  1    an error occurred
+";
+
+pub struct Args {
+    pub config: Option<PathBuf>,
+    /// Print the chosen host instead of connecting to it.
+    pub print: bool,
+    pub read_only: bool,
+    pub command: Command,
+}
+
+pub enum Command {
+    Pick,
+    Resolve { pattern: String, json: bool },
+    List { columns: Vec<ListColumn> },
+    Help,
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut config = None;
+        let mut print = false;
+        let mut read_only = false;
+        let mut command = None;
+        let mut json = false;
+        let mut list = false;
+        let mut format = None;
+        let mut positional = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => return Ok(Self { config, print, read_only, command: Command::Help }),
+                "--print" => print = true,
+                "--read-only" => read_only = true,
+                "--config" => config = Some(PathBuf::from(args.next().context("--config needs a path")?)),
+                "--json" => json = true,
+                "--list" => list = true,
+                "--format" => format = Some(args.next().context("--format needs a list of columns")?),
+                a if a.starts_with("--format=") => format = Some(a["--format=".len()..].to_string()),
+                a if a.starts_with("--config=") => config = Some(PathBuf::from(&a["--config=".len()..])),
+                a if a.starts_with('-') => bail!("unknown option: {}\n\n{}", a, USAGE),
+                "resolve" if command.is_none() && positional.is_empty() => command = Some("resolve"),
+                _ => positional.push(arg),
+            }
+        }
+        if format.is_some() && !list {
+            bail!("--format only applies to --list");
+        }
+        let command = match (command, positional.as_slice()) {
+            (None, []) if list && !json => Command::List { columns: ListColumn::parse_list(format.as_deref().unwrap_or("pattern"))? },
+            (None, []) if !json => Command::Pick,
+            (Some("resolve"), [pattern]) if !list => Command::Resolve { pattern: pattern.clone(), json },
+            (Some("resolve"), _) if !list => bail!("usage: ssh-picker resolve <pattern> [--json]"),
+            _ => bail!("unexpected arguments\n\n{}", USAGE),
+        };
+        if (print || read_only) && !matches!(command, Command::Pick) {
+            bail!("--print and --read-only only apply to the picker");
+        }
+        Ok(Self { config, print, read_only, command })
+    }
+
+    /// The ssh config to use: `--config`, then `$SSH_PICKER_CONFIG`, then `~/.ssh/config`.
+    pub fn config_path(&self) -> PathBuf {
+        choose_config_path(self.config.clone(), std::env::var_os(CONFIG_ENV))
+    }
+}
+
+/// `flag`, else `env` unless it is empty, else `~/.ssh/config`.
+fn choose_config_path(flag: Option<PathBuf>, env: Option<OsString>) -> PathBuf {
+    flag.or_else(|| env.filter(|v| !v.is_empty()).map(PathBuf::from)).unwrap_or_else(default_ssh_config_path)
+}
+
+/// A field `--list` can print for each host.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ListColumn {
+    /// The whole `Host` value, every alias included.
+    Pattern,
+    /// The first alias, the name ssh is given on launch.
+    Alias,
+    Hostname,
+    User,
+    Port,
+}
+
+impl ListColumn {
+    const NAMES: [(&'static str, ListColumn); 5] = [
+        ("pattern", ListColumn::Pattern),
+        ("alias", ListColumn::Alias),
+        ("hostname", ListColumn::Hostname),
+        ("user", ListColumn::User),
+        ("port", ListColumn::Port),
+    ];
+
+    /// Columns from a `--format` value like `pattern,hostname,user`.
+    fn parse_list(spec: &str) -> Result<Vec<Self>> {
+        spec.split(',')
+            .map(|name| {
+                let name = name.trim();
+                Self::NAMES
+                    .iter()
+                    .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                    .map(|&(_, column)| column)
+                    .with_context(|| {
+                        let known: Vec<_> = Self::NAMES.iter().map(|(n, _)| *n).collect();
+                        format!("unknown --format column '{}' (expected {})", name, known.join(", "))
+                    })
+            })
+            .collect()
+    }
+
+    fn value(self, entry: &SshHostEntry) -> String {
+        match self {
+            ListColumn::Pattern => entry.pattern.clone(),
+            ListColumn::Alias => entry.primary_alias().to_string(),
+            ListColumn::Hostname => entry.hostname.clone().unwrap_or_default(),
+            ListColumn::User => entry.user.clone().unwrap_or_default(),
+            ListColumn::Port => entry.display_port().unwrap_or_default(),
+        }
+    }
+}
+
+/// `ssh-picker --list`: print the chosen columns of every host, tab-separated,
+/// in config order. Unset values print as empty fields.
+pub fn list(config_path: PathBuf, columns: &[ListColumn]) -> Result<i32> {
+    let ssh_cfg = SshConfigFile::load(config_path)?;
+    let mut out = io::stdout().lock();
+    for entry in ssh_cfg.list_hosts() {
+        // A closed pipe (`| head`) ends the listing quietly
+        if writeln!(out, "{}", list_row(&entry, columns)).is_err() {
+            break;
+        }
+    }
+    Ok(0)
+}
+
+/// One `--list` line: `columns` of `entry`, tab-separated.
+fn list_row(entry: &SshHostEntry, columns: &[ListColumn]) -> String {
+    let fields: Vec<String> = columns.iter().map(|c| c.value(entry)).collect();
+    fields.join("\t")
+}
+
+/// `ssh-picker resolve <pattern> [--json]`: print what this tool parsed for a host.
+/// Returns the process exit code.
+pub fn resolve(config_path: PathBuf, pattern: &str, json: bool) -> Result<i32> {
+    let ssh_cfg = SshConfigFile::load(config_path)?;
+    let hosts = ssh_cfg.list_hosts();
+    let Some(entry) = hosts.iter().find(|h| h.aliases().any(|a| a == pattern)) else {
+        eprintln!("ssh-picker: no host matching '{}'", pattern);
+        return Ok(1);
+    };
+
+    if json {
+        println!("{}", entry_to_json(entry));
+    } else {
+        println!("pattern={}", entry.pattern);
+        println!("hostname={}", entry.hostname.as_deref().unwrap_or(""));
+        println!("user={}", entry.user.as_deref().unwrap_or(""));
+        println!("port={}", entry.display_port().unwrap_or_default());
+    }
+    Ok(0)
+}
+
+fn entry_to_json(entry: &SshHostEntry) -> String {
+    let opt = |v: Option<&str>| v.map(json_string).unwrap_or_else(|| "null".to_string());
+    format!(
+        "{{\"pattern\":{},\"hostname\":{},\"user\":{},\"port\":{}}}",
+        json_string(&entry.pattern),
+        opt(entry.hostname.as_deref()),
+        opt(entry.user.as_deref()),
+        entry.port.map(|p| p.to_string()).unwrap_or_else(|| "null".to_string()),
+    )
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args> {
+        Args::parse(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn config_flag_takes_a_separate_or_joined_path() {
+        assert_eq!(parse(&["--config", "/tmp/a"]).unwrap().config, Some(PathBuf::from("/tmp/a")));
+        assert_eq!(parse(&["--config=/tmp/b"]).unwrap().config, Some(PathBuf::from("/tmp/b")));
+        assert_eq!(parse(&["--config"]).err().unwrap().to_string(), "--config needs a path");
+        assert_eq!(parse(&[]).unwrap().config, None);
+    }
+
+    #[test]
+    fn config_path_prefers_the_flag_then_the_environment() {
+        let flag = Some(PathBuf::from("/flag"));
+        let env = Some(OsString::from("/env"));
+        assert_eq!(choose_config_path(flag, env.clone()), PathBuf::from("/flag"));
+        assert_eq!(choose_config_path(None, env), PathBuf::from("/env"));
+        // An empty variable counts as unset
+        assert_eq!(choose_config_path(None, Some(OsString::new())), default_ssh_config_path());
+        assert_eq!(choose_config_path(None, None), default_ssh_config_path());
+    }
+
+    #[test]
+    fn list_takes_format_columns_and_refuses_json() {
+        let Command::List { columns } = parse(&["--list", "--format", "alias,Port"]).unwrap().command else {
+            panic!("expected --list");
+        };
+        assert_eq!(columns, [ListColumn::Alias, ListColumn::Port]);
+        let Command::List { columns } = parse(&["--list"]).unwrap().command else {
+            panic!("expected --list");
+        };
+        assert_eq!(columns, [ListColumn::Pattern]);
+        let err = parse(&["--list", "--format=alias,os"]).err().unwrap().to_string();
+        assert_eq!(err, "unknown --format column 'os' (expected pattern, alias, hostname, user, port)");
+        assert_eq!(parse(&["--format", "alias"]).err().unwrap().to_string(), "--format only applies to --list");
+        assert!(parse(&["--list", "--json"]).is_err());
+
+        let entry = SshHostEntry { pattern: "web w".into(), hostname: Some("web.com".into()), ..Default::default() };
+        let all = ListColumn::parse_list("pattern,alias,hostname,user,port").unwrap();
+        assert_eq!(list_row(&entry, &all), "web w\tweb\tweb.com\t\t");
+    }
+
+    #[test]
+    fn resolve_takes_json_and_prints_null_for_unset_fields() {
+        let Command::Resolve { pattern, json } = parse(&["resolve", "web", "--json"]).unwrap().command else {
+            panic!("expected resolve");
+        };
+        assert_eq!((pattern.as_str(), json), ("web", true));
+        assert!(parse(&["resolve"]).is_err());
+        assert!(parse(&["resolve", "web", "--print"]).is_err());
+
+        let entry = SshHostEntry { pattern: "web".into(), user: Some("a\"b".into()), port: Some(2222), ..Default::default() };
+        assert_eq!(entry_to_json(&entry), r#"{"pattern":"web","hostname":null,"user":"a\"b","port":2222}"#);
+    }
+
+    #[test]
+    fn resolve_exits_1_for_an_unknown_host() {
+        let path = std::env::temp_dir().join(format!("ssh-picker-resolve-{}", std::process::id()));
+        std::fs::write(&path, "Host web w\n    HostName web.com\n").unwrap();
+        assert_eq!(resolve(path.clone(), "nope", false).unwrap(), 1);
+        assert_eq!(resolve(path.clone(), "w", false).unwrap(), 0);
+        std::fs::remove_file(path).unwrap();
+    }
+}
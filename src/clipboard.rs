@@ -0,0 +1,18 @@
+use anyhow::{anyhow, Result};
+use std::sync::Mutex;
+
+/// Held for the whole session: on X11/Wayland this process serves the copied
+/// text, so dropping the handle right after copying would clear it again.
+static CLIPBOARD: Mutex<Option<arboard::Clipboard>> = Mutex::new(None);
+
+/// Put `text` on the system clipboard.
+pub fn copy(text: &str) -> Result<()> {
+    let mut guard = CLIPBOARD.lock().map_err(|_| anyhow!("clipboard lock poisoned"))?;
+    if guard.is_none() {
+        *guard = Some(arboard::Clipboard::new()?);
+    }
+    if let Some(clipboard) = guard.as_mut() {
+        clipboard.set_text(text)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,85 @@
+/// Subsequence matcher for the host filter.
+///
+/// Every character of `needle` must appear in `haystack` in order, but not
+/// necessarily adjacent, so `wbp` matches `web-prod`. Both must already be
+/// lowercase, so callers scoring many fields lowercase each once. Higher scores
+/// mean a better match: consecutive runs and matches at word starts are
+/// rewarded, gaps are lightly penalized. Returns `None` when `needle` is not a
+/// subsequence.
+pub fn score(haystack: &str, needle: &[char]) -> Option<i64> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    let mut score = 0i64;
+    let mut next = 0;
+    let mut prev_match: Option<usize> = None;
+    let mut prev_char = None;
+    for (i, c) in haystack.chars().enumerate() {
+        let before = prev_char.replace(c);
+        if next == needle.len() {
+            break;
+        }
+        if c != needle[next] {
+            continue;
+        }
+        score += 1;
+        match prev_match {
+            Some(p) if p + 1 == i => score += 5,
+            Some(p) => score -= (i - p - 1).min(5) as i64,
+            None => score -= i.min(5) as i64,
+        }
+        if before.is_none_or(|b| matches!(b, '-' | '_' | '.' | ' ' | '@')) {
+            score += 3;
+        }
+        prev_match = Some(i);
+        next += 1;
+    }
+    (next == needle.len()).then_some(score)
+}
+
+/// Byte offsets in `haystack` of the characters `score` matches against
+/// `needle`, for highlighting. `None` when `needle` is not a subsequence.
+pub fn positions(haystack: &str, needle: &str) -> Option<Vec<usize>> {
+    let needle: Vec<char> = needle.to_lowercase().chars().collect();
+    let mut found = Vec::with_capacity(needle.len());
+    for (offset, c) in haystack.char_indices() {
+        if found.len() == needle.len() {
+            break;
+        }
+        if c.to_lowercase().eq(needle[found.len()].to_lowercase()) {
+            found.push(offset);
+        }
+    }
+    (found.len() == needle.len()).then_some(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(haystack: &str, needle: &str) -> Option<i64> {
+        let needle: Vec<char> = needle.to_lowercase().chars().collect();
+        super::score(&haystack.to_lowercase(), &needle)
+    }
+
+    #[test]
+    fn matches_skipped_characters_in_order() {
+        assert!(score("web-prod", "wbp").is_some());
+        assert!(score("web-prod", "WEB").is_some());
+        assert!(score("web-prod", "pw").is_none());
+    }
+
+    #[test]
+    fn contiguous_and_word_start_matches_rank_higher() {
+        assert!(score("web-prod", "prod") > score("pxrxoxd", "prod"));
+        assert!(score("db-prod", "dp") > score("adxp", "dp"));
+    }
+
+    #[test]
+    fn positions_are_the_matched_byte_offsets() {
+        assert_eq!(positions("web-prod", "wbp"), Some(vec![0, 2, 4]));
+        assert_eq!(positions("Web-Prod", "prod"), Some(vec![4, 5, 6, 7]));
+        assert_eq!(positions("é-web", "w"), Some(vec![3]));
+        assert_eq!(positions("web-prod", "pw"), None);
+    }
+}
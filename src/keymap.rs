@@ -0,0 +1,279 @@
+use crate::ui::UiAction;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Normal-mode actions that can be rebound, with their default keys and help text.
+const BINDABLE: &[(&str, UiAction, &[&str], &str)] = &[
+    ("MoveDown", UiAction::MoveDown, &["j", "Down"], "move down"),
+    ("MoveUp", UiAction::MoveUp, &["k", "Up"], "move up"),
+    ("PageDown", UiAction::PageDown, &["PageDown", "Ctrl-f"], "page down"),
+    ("PageUp", UiAction::PageUp, &["PageUp", "Ctrl-b"], "page up"),
+    ("JumpFirst", UiAction::JumpFirst, &["g"], "go to the first host (press twice)"),
+    ("JumpLast", UiAction::JumpLast, &["G"], "go to the last host"),
+    ("LaunchSelected", UiAction::LaunchSelected, &["Enter"], "ssh to selected host"),
+    ("BeginFilter", UiAction::BeginFilter, &["/"], "filter hosts"),
+    ("ClearFilter", UiAction::ClearFilter, &["Esc"], "clear filter"),
+    ("BackspaceFilter", UiAction::BackspaceFilter, &["Backspace"], "delete filter char"),
+    ("EditSelected", UiAction::EditSelected, &["e"], "edit host"),
+    ("EditConfigFile", UiAction::EditConfigFile, &["E"], "open the config in $VISUAL / $EDITOR"),
+    ("NewHost", UiAction::NewHost, &["a"], "add host"),
+    ("ToggleMark", UiAction::ToggleMark, &["Space"], "mark host for a batch delete (expand/collapse a group)"),
+    ("DeleteSelected", UiAction::DeleteSelected, &["d"], "delete host (or all marked hosts)"),
+    ("MergeDuplicates", UiAction::MergeDuplicates, &["M"], "merge duplicate Host blocks"),
+    ("ImportKnownHosts", UiAction::ImportKnownHosts, &["I"], "import hosts from known_hosts"),
+    ("ExportHosts", UiAction::ExportHosts, &["X"], "export marked (or all listed) hosts to a file"),
+    ("Undo", UiAction::Undo, &["u"], "undo last change"),
+    ("RenameSelected", UiAction::RenameSelected, &["R"], "rename host in place"),
+    ("CopySelectedAs", UiAction::CopySelectedAs, &["C"], "copy host to a new pattern"),
+    ("CloneSelected", UiAction::CloneSelected, &["Y"], "add a host prefilled from the selected one"),
+    ("CopyCommand", UiAction::CopyCommand, &["y"], "copy ssh command"),
+    ("CopyHostname", UiAction::CopyHostname, &["H"], "copy HostName"),
+    ("CopyAddress", UiAction::CopyAddress, &["i"], "look up and copy the IP address"),
+    ("LaunchWithForward", UiAction::LaunchWithForward, &["F"], "ssh with port forward"),
+    ("LaunchWithArgs", UiAction::LaunchWithArgs, &["A"], "ssh with extra arguments"),
+    ("RunCommand", UiAction::RunCommand, &["c"], "run a command on the host"),
+    ("LaunchSftp", UiAction::LaunchSftp, &["S"], "sftp to selected host"),
+    ("LaunchVerbose", UiAction::LaunchVerbose, &["V"], "ssh with -vvv, pausing after it exits"),
+    ("ProbeHosts", UiAction::ProbeHosts, &["r"], "probe reachability"),
+    ("TestConnection", UiAction::TestConnection, &["t"], "time a connection to the selected host"),
+    ("ToggleLatency", UiAction::ToggleLatency, &["L"], "toggle latency column"),
+    ("ToggleSearchAll", UiAction::ToggleSearchAll, &["Ctrl-a"], "filter all fields or just name/host/user"),
+    ("CycleSort", UiAction::CycleSort, &["s"], "cycle sort mode"),
+    ("TogglePreview", UiAction::TogglePreview, &["p"], "toggle details pane"),
+    ("ShowResolved", UiAction::ShowResolved, &["O"], "show the options ssh resolves (ssh -G)"),
+    ("ShowGlobalOptions", UiAction::ShowGlobalOptions, &["o"], "edit the options every host gets (Host *)"),
+    ("ToggleDiagnostics", UiAction::ToggleDiagnostics, &["!"], "list problems found in the config"),
+    ("ShowMatchBlocks", UiAction::ShowMatchBlocks, &["m"], "list the config's Match blocks"),
+    ("ToggleTree", UiAction::ToggleTree, &["T"], "group hosts by name prefix"),
+    ("ShowSettings", UiAction::ShowSettings, &[","], "change display settings"),
+    ("ScrollPreviewDown", UiAction::ScrollPreviewDown, &["J"], "scroll details down"),
+    ("ScrollPreviewUp", UiAction::ScrollPreviewUp, &["K"], "scroll details up"),
+    ("ToggleHelp", UiAction::ToggleHelp, &["?"], "show this help"),
+    ("Quit", UiAction::Quit, &["q"], "quit"),
+];
+
+/// Actions that still work while typing a filter; their character keys are ignored there.
+const FILTER_ACTIONS: &[&str] = &[
+    "MoveDown",
+    "MoveUp",
+    "PageDown",
+    "PageUp",
+    "LaunchSelected",
+    "ClearFilter",
+    "BackspaceFilter",
+    "ToggleSearchAll",
+];
+
+/// The `[keybindings]` settings table: action name to the keys that trigger it.
+/// Actions left out keep their default keys.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Keybindings(pub BTreeMap<String, Vec<String>>);
+
+impl Default for Keybindings {
+    fn default() -> Self {
+        Self(
+            BINDABLE
+                .iter()
+                .map(|(name, _, keys, _)| (name.to_string(), keys.iter().map(|k| k.to_string()).collect()))
+                .collect(),
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Key {
+    code: KeyCode,
+    ctrl: bool,
+    alt: bool,
+}
+
+impl Key {
+    fn from_event(key: KeyEvent) -> Self {
+        Self {
+            code: key.code,
+            ctrl: key.modifiers.contains(KeyModifiers::CONTROL),
+            alt: key.modifiers.contains(KeyModifiers::ALT),
+        }
+    }
+}
+
+/// Parse a key string such as `j`, `J`, `Down`, `PageUp`, `Ctrl-f`, or `Alt-x`.
+fn parse_key(s: &str) -> Option<Key> {
+    let mut ctrl = false;
+    let mut alt = false;
+    let mut rest = s;
+    loop {
+        if let Some(r) = rest.strip_prefix("Ctrl-").or_else(|| rest.strip_prefix("C-")) {
+            ctrl = true;
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix("Alt-").or_else(|| rest.strip_prefix("M-")) {
+            alt = true;
+            rest = r;
+        } else {
+            break;
+        }
+    }
+    let mut chars = rest.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) => KeyCode::Char(c),
+        _ => match rest.to_ascii_lowercase().as_str() {
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "enter" => KeyCode::Enter,
+            "esc" => KeyCode::Esc,
+            "backspace" => KeyCode::Backspace,
+            "delete" => KeyCode::Delete,
+            "tab" => KeyCode::Tab,
+            "space" => KeyCode::Char(' '),
+            f => KeyCode::F(f.strip_prefix('f')?.parse().ok()?),
+        },
+    };
+    Some(Key { code, ctrl, alt })
+}
+
+/// Normal-mode key lookup built from `Keybindings` on top of the defaults.
+#[derive(Clone, Debug)]
+pub struct Keymap {
+    /// Key, index into `BINDABLE`, and the key as written (for hints).
+    bindings: Vec<(Key, usize, String)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::new(&Keybindings::default()).0
+    }
+}
+
+impl Keymap {
+    /// Build the keymap, returning warnings for unknown actions, unparsable keys,
+    /// and actions left without any key. None of these are fatal.
+    pub fn new(config: &Keybindings) -> (Self, Vec<String>) {
+        let mut warnings = Vec::new();
+        for name in config.0.keys() {
+            if !BINDABLE.iter().any(|(n, _, _, _)| n == name) {
+                warnings.push(format!("unknown action '{}'", name));
+            }
+        }
+        let mut bindings: Vec<(Key, usize, String)> = Vec::new();
+        for (idx, (name, _, defaults, _)) in BINDABLE.iter().enumerate() {
+            let keys: Vec<&str> = match config.0.get(*name) {
+                Some(keys) => keys.iter().map(String::as_str).collect(),
+                None => defaults.to_vec(),
+            };
+            for k in keys {
+                match parse_key(k) {
+                    Some(key) => {
+                        // A key bound twice goes to the later action
+                        bindings.retain(|(existing, _, _)| *existing != key);
+                        bindings.push((key, idx, k.to_string()));
+                    }
+                    None => warnings.push(format!("bad key '{}' for {}", k, name)),
+                }
+            }
+        }
+        for (idx, (name, _, _, _)) in BINDABLE.iter().enumerate() {
+            if !bindings.iter().any(|(_, i, _)| *i == idx) {
+                warnings.push(format!("{} has no key", name));
+            }
+        }
+        (Self { bindings }, warnings)
+    }
+
+    fn lookup(&self, key: KeyEvent) -> Option<usize> {
+        let key = Key::from_event(key);
+        self.bindings.iter().find(|(k, _, _)| *k == key).map(|(_, idx, _)| *idx)
+    }
+
+    /// The action bound to `key` in Normal mode.
+    pub fn normal_action(&self, key: KeyEvent) -> Option<UiAction> {
+        self.lookup(key).map(|idx| BINDABLE[idx].1)
+    }
+
+    /// The action bound to `key` while filtering: plain characters are filter input,
+    /// so only navigation bound to non-character keys applies.
+    pub fn filter_action(&self, key: KeyEvent) -> Option<UiAction> {
+        if matches!(key.code, KeyCode::Char(_)) && !key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) {
+            return None;
+        }
+        self.lookup(key)
+            .filter(|&idx| FILTER_ACTIONS.contains(&BINDABLE[idx].0))
+            .map(|idx| BINDABLE[idx].1)
+    }
+
+    /// Every bindable action's help text and its keys joined with `/` (empty when unbound).
+    pub fn describe(&self) -> Vec<(&'static str, String)> {
+        BINDABLE
+            .iter()
+            .enumerate()
+            .map(|(idx, (_, _, _, help))| {
+                let keys: Vec<&str> = self
+                    .bindings
+                    .iter()
+                    .filter(|(_, i, _)| *i == idx)
+                    .map(|(_, _, label)| label.as_str())
+                    .collect();
+                (*help, keys.join("/"))
+            })
+            .collect()
+    }
+
+    /// First key bound to the named action, for hints; `?` when unbound.
+    pub fn label(&self, name: &str) -> &str {
+        let idx = BINDABLE.iter().position(|(n, _, _, _)| *n == name);
+        self.bindings
+            .iter()
+            .find(|(_, i, _)| Some(*i) == idx)
+            .map(|(_, _, label)| label.as_str())
+            .unwrap_or("?")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn defaults_bind_every_action() {
+        let (keymap, warnings) = Keymap::new(&Keybindings::default());
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert!(matches!(keymap.normal_action(press(KeyCode::Char('j'), KeyModifiers::NONE)), Some(UiAction::MoveDown)));
+        assert!(matches!(keymap.normal_action(press(KeyCode::Char('f'), KeyModifiers::CONTROL)), Some(UiAction::PageDown)));
+        assert!(matches!(keymap.normal_action(press(KeyCode::Char('J'), KeyModifiers::SHIFT)), Some(UiAction::ScrollPreviewDown)));
+        assert!(keymap.filter_action(press(KeyCode::Char('j'), KeyModifiers::NONE)).is_none());
+    }
+
+    #[test]
+    fn overrides_replace_defaults_and_warn_on_problems() {
+        let mut config = BTreeMap::new();
+        config.insert("MoveDown".to_string(), vec!["Down".to_string()]);
+        config.insert("Quit".to_string(), vec![]);
+        config.insert("Bogus".to_string(), vec!["x".to_string()]);
+        config.insert("Undo".to_string(), vec!["Hyper-u".to_string()]);
+        let (keymap, warnings) = Keymap::new(&Keybindings(config));
+        assert!(keymap.normal_action(press(KeyCode::Char('j'), KeyModifiers::NONE)).is_none());
+        assert!(matches!(keymap.normal_action(press(KeyCode::Down, KeyModifiers::NONE)), Some(UiAction::MoveDown)));
+        assert_eq!(keymap.label("MoveDown"), "Down");
+        assert_eq!(keymap.label("Quit"), "?");
+        assert_eq!(
+            warnings,
+            vec![
+                "unknown action 'Bogus'".to_string(),
+                "bad key 'Hyper-u' for Undo".to_string(),
+                "Undo has no key".to_string(),
+                "Quit has no key".to_string(),
+            ]
+        );
+    }
+}
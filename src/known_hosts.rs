@@ -0,0 +1,81 @@
+use crate::ssh_config::DEFAULT_SSH_PORT;
+use home::home_dir;
+use std::path::PathBuf;
+
+/// Hosts found in a known_hosts file, in file order without repeats.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct KnownHosts {
+    /// Hostname or IP, and the port when the entry used the `[host]:port` form.
+    pub hosts: Vec<(String, Option<u16>)>,
+    /// Hashed (`|1|…`) names, which can't be turned back into hostnames.
+    pub hashed: usize,
+}
+
+pub fn default_known_hosts_path() -> PathBuf {
+    home_dir()
+        .map(|h| h.join(".ssh").join("known_hosts"))
+        .unwrap_or_else(|| PathBuf::from("~/.ssh/known_hosts"))
+}
+
+/// Collect the host names of a known_hosts file. Lines with a marker
+/// (`@cert-authority`, `@revoked`), wildcard and negated names, and hashed
+/// names are skipped; hashed ones are counted.
+pub fn parse(text: &str) -> KnownHosts {
+    let mut found = KnownHosts::default();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with('@') {
+            continue;
+        }
+        let Some(names) = line.split_whitespace().next() else { continue };
+        for name in names.split(',') {
+            if name.starts_with('|') {
+                found.hashed += 1;
+                continue;
+            }
+            if name.is_empty() || name.contains(['*', '?', '!']) {
+                continue;
+            }
+            let (host, port) = match name.strip_prefix('[').and_then(|rest| rest.split_once("]:")) {
+                Some((host, port)) => match port.parse::<u16>() {
+                    Ok(port) => (host, (port != DEFAULT_SSH_PORT).then_some(port)),
+                    Err(_) => continue,
+                },
+                None => (name, None),
+            };
+            if !found.hosts.iter().any(|(h, _)| h.eq_ignore_ascii_case(host)) {
+                found.hosts.push((host.to_string(), port));
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_plain_bracketed_and_hashed_names() {
+        let text = "\
+github.com,140.82.121.3 ssh-ed25519 AAAA
+[git.example.com]:2222 ssh-ed25519 AAAA
+[old.example.com]:22 ssh-rsa AAAA
+|1|abc=|def= ssh-ed25519 AAAA
+@cert-authority *.example.com ssh-ed25519 AAAA
+# comment
+GitHub.com ecdsa-sha2-nistp256 AAAA
+*.lan,!printer.lan ssh-rsa AAAA
+";
+        let found = parse(text);
+        assert_eq!(
+            found.hosts,
+            vec![
+                ("github.com".to_string(), None),
+                ("140.82.121.3".to_string(), None),
+                ("git.example.com".to_string(), Some(2222)),
+                ("old.example.com".to_string(), None),
+            ]
+        );
+        assert_eq!(found.hashed, 1);
+    }
+}
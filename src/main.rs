@@ -1,9 +1,37 @@
 mod ui;
 mod app;
+mod cli;
+mod clipboard;
+mod fuzzy;
+mod keymap;
+mod known_hosts;
+mod probe;
+mod settings;
 mod ssh_config;
+mod tree;
 
 use anyhow::Result;
+use cli::{Args, Command};
 
 fn main() -> Result<()> {
-    app::run()
+    let args = Args::parse(std::env::args().skip(1))?;
+    let config_path = args.config_path();
+    match args.command {
+        Command::Help => {
+            print!("{}", cli::USAGE);
+            Ok(())
+        }
+        Command::Resolve { pattern, json } => {
+            let code = cli::resolve(config_path, &pattern, json)?;
+            std::process::exit(code);
+        }
+        Command::List { columns } => {
+            let code = cli::list(config_path, &columns)?;
+            std::process::exit(code);
+        }
+        Command::Pick => {
+            let code = app::run(app::RunOptions { config_path, print_only: args.print, read_only: args.read_only })?;
+            std::process::exit(code);
+        }
+    }
 }
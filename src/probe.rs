@@ -0,0 +1,159 @@
+use crate::ssh_config::SshHostEntry;
+use anyhow::{anyhow, Context, Result};
+use std::io::ErrorKind;
+use std::net::{IpAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_CONCURRENT_PROBES: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProbeResult {
+    Reachable(Duration),
+    Unreachable,
+}
+
+impl ProbeResult {
+    pub fn latency(&self) -> Option<Duration> {
+        match self {
+            ProbeResult::Reachable(rtt) => Some(*rtt),
+            ProbeResult::Unreachable => None,
+        }
+    }
+}
+
+/// Runs TCP-connect sweeps on a background thread and collects the results.
+pub struct Prober {
+    tx: Sender<(String, ProbeResult)>,
+    rx: Receiver<(String, ProbeResult)>,
+    /// Single-host tests started with `start_test`, kept apart from sweeps so
+    /// their errors can be reported.
+    test_tx: Sender<(String, Result<Duration>)>,
+    test_rx: Receiver<(String, Result<Duration>)>,
+    /// Address lookups started with `start_lookup`.
+    lookup_tx: Sender<(String, Result<IpAddr>)>,
+    lookup_rx: Receiver<(String, Result<IpAddr>)>,
+}
+
+impl Default for Prober {
+    fn default() -> Self {
+        let (tx, rx) = mpsc::channel();
+        let (test_tx, test_rx) = mpsc::channel();
+        let (lookup_tx, lookup_rx) = mpsc::channel();
+        Self { tx, rx, test_tx, test_rx, lookup_tx, lookup_rx }
+    }
+}
+
+impl Prober {
+    /// Start probing every concrete host; results arrive via `drain`.
+    /// Returns the patterns that were queued.
+    pub fn start_sweep<'a>(&self, hosts: impl IntoIterator<Item = &'a SshHostEntry>) -> Vec<String> {
+        let targets: Vec<(String, String, u16)> = hosts
+            .into_iter()
+            .filter(|h| !h.is_wildcard())
+            .map(|h| {
+                (h.pattern.clone(), h.connect_host().to_string(), h.effective_port())
+            })
+            .collect();
+        let queued = targets.iter().map(|(pattern, _, _)| pattern.clone()).collect();
+        let tx = self.tx.clone();
+        thread::spawn(move || {
+            for chunk in targets.chunks(MAX_CONCURRENT_PROBES) {
+                thread::scope(|s| {
+                    for (pattern, host, port) in chunk {
+                        let tx = tx.clone();
+                        s.spawn(move || {
+                            let _ = tx.send((pattern.clone(), probe(host, *port)));
+                        });
+                    }
+                });
+            }
+        });
+        queued
+    }
+
+    /// Collect any results that have completed since the last call.
+    pub fn drain(&self) -> Vec<(String, ProbeResult)> {
+        self.rx.try_iter().collect()
+    }
+
+    /// Time one connect to `host` on a background thread; the result arrives
+    /// via `drain_tests`.
+    pub fn start_test(&self, host: &SshHostEntry, timeout: Duration) {
+        let (pattern, target, port) = (host.pattern.clone(), host.connect_host().to_string(), host.effective_port());
+        let tx = self.test_tx.clone();
+        thread::spawn(move || {
+            let _ = tx.send((pattern, time_connect(&target, port, timeout)));
+        });
+    }
+
+    /// Collect any tests that have finished since the last call.
+    pub fn drain_tests(&self) -> Vec<(String, Result<Duration>)> {
+        self.test_rx.try_iter().collect()
+    }
+
+    /// Look up `host`'s address on a background thread; the result arrives
+    /// via `drain_lookups`.
+    pub fn start_lookup(&self, host: &SshHostEntry, timeout: Duration) {
+        let (pattern, target) = (host.pattern.clone(), host.connect_host().to_string());
+        let tx = self.lookup_tx.clone();
+        thread::spawn(move || {
+            let _ = tx.send((pattern, resolve_address(&target, timeout)));
+        });
+    }
+
+    /// Collect any lookups that have finished since the last call.
+    pub fn drain_lookups(&self) -> Vec<(String, Result<IpAddr>)> {
+        self.lookup_rx.try_iter().collect()
+    }
+}
+
+/// The first address `host` resolves to. The system resolver can't be
+/// cancelled, so it runs on a thread of its own that is left to finish
+/// unheard once `timeout` passes.
+pub fn resolve_address(host: &str, timeout: Duration) -> Result<IpAddr> {
+    let (tx, rx) = mpsc::channel();
+    let target = host.to_string();
+    thread::spawn(move || {
+        let _ = tx.send((target.as_str(), 0).to_socket_addrs().map(|mut addrs| addrs.next()));
+    });
+    match rx.recv_timeout(timeout) {
+        Ok(Ok(Some(addr))) => Ok(addr.ip()),
+        Ok(Ok(None)) => Err(anyhow!("{} has no addresses", host)),
+        Ok(Err(e)) => Err(anyhow!(e).context(format!("can't resolve {}", host))),
+        Err(_) => Err(anyhow!("no answer for {} within {}s", host, timeout.as_secs_f32())),
+    }
+}
+
+fn probe(host: &str, port: u16) -> ProbeResult {
+    time_connect(host, port, CONNECT_TIMEOUT).map_or(ProbeResult::Unreachable, ProbeResult::Reachable)
+}
+
+/// How long a TCP connect to `host:port` takes, trying each address the name
+/// resolves to until one answers. The error from the last address says why none did.
+pub fn time_connect(host: &str, port: u16, timeout: Duration) -> Result<Duration> {
+    let addrs = (host, port).to_socket_addrs().with_context(|| format!("can't resolve {}", host))?;
+    let mut last_error = anyhow!("{} has no addresses", host);
+    for addr in addrs {
+        let started = Instant::now();
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(_) => return Ok(started.elapsed()),
+            Err(e) if e.kind() == ErrorKind::TimedOut => {
+                last_error = anyhow!("no answer within {}s", timeout.as_secs_f32());
+            }
+            Err(e) => last_error = anyhow!(e),
+        }
+    }
+    Err(last_error)
+}
+
+/// Sort key for latency ordering: fastest first, unprobed next, unreachable last.
+pub fn latency_rank(result: Option<&ProbeResult>) -> (u8, Duration) {
+    match result {
+        Some(ProbeResult::Reachable(rtt)) => (0, *rtt),
+        None => (1, Duration::ZERO),
+        Some(ProbeResult::Unreachable) => (2, Duration::ZERO),
+    }
+}
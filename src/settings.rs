@@ -0,0 +1,334 @@
+use crate::app::SortMode;
+use crate::keymap::Keybindings;
+use crate::ssh_config::{write_file_atomic, OptionOrder};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppSettings {
+    /// Relaunch ssh this many times when it exits with a connection error (255). 0 disables.
+    pub reconnect_attempts: u32,
+    /// Show the basename of each host's IdentityFile in the list.
+    pub show_identity: bool,
+    /// Show each host's User in the list.
+    pub show_user: bool,
+    /// Show each host's HostName (and port) in the list.
+    pub show_hostname: bool,
+    /// Hosts PageUp and PageDown move by; 0 moves by the list's height.
+    pub page_size: usize,
+    /// Copied ssh commands spell out `-p 22` when a host sets the default port explicitly.
+    pub verbose_commands: bool,
+    /// Probe listed hosts in the background and show a reachability dot for each.
+    pub auto_probe: bool,
+    /// Host list order; updated whenever it is changed with `s`.
+    pub sort_mode: SortMode,
+    /// Normal-mode keys per action, e.g. `MoveDown = ["j", "Down"]`.
+    pub keybindings: Keybindings,
+    pub theme: Theme,
+    /// Start with the host selected last time (kept in `state.toml` next to this file).
+    pub remember_last_host: bool,
+    /// Where ssh runs when a host is launched.
+    pub launch_mode: LaunchMode,
+    /// Open each session in a new window of this terminal instead, e.g.
+    /// `"alacritty -e"`; the ssh command is appended and `{host}` is replaced
+    /// with the host's name. Takes precedence over `launch_mode`.
+    pub spawn_terminal: Option<String>,
+    /// Filter matches IdentityFile, ProxyJump, and other options too; toggled with Ctrl-a.
+    pub search_all_fields: bool,
+    /// Never change the ssh config: editing, adding, and deleting are disabled.
+    pub read_only: bool,
+    /// Reload the ssh config when another program changes it. Watches the
+    /// directories of the config and its includes; turn off where file change
+    /// notifications don't work, e.g. some network filesystems.
+    pub watch_config: bool,
+    /// Widest the host-name column gets; longer names are cut short with `…`.
+    pub max_name_width: usize,
+    /// Show hosts grouped by name prefix; updated whenever it is toggled with `T`.
+    pub tree_view: bool,
+    /// Options written into every host added from the picker, e.g.
+    /// `[["ConnectTimeout", "10"], ["ServerAliveInterval", "30"]]`. Editing
+    /// an existing host never adds them.
+    pub default_options: Vec<(String, String)>,
+    /// `MoveUp` on the first host selects the last one and `MoveDown` on the
+    /// last selects the first. Paging still stops at either end.
+    pub wrap_navigation: bool,
+    /// Seconds the `t` connection test waits for a host to answer.
+    pub test_timeout_secs: u64,
+    /// Ask before deleting. When off, the delete key must be pressed twice
+    /// instead and the footer offers undo for a few seconds.
+    pub confirm_deletes: bool,
+    /// Colors for host names, first matching rule wins, e.g.
+    /// `[[host_colors]] tag = "prod" color = "red"`. Unmatched hosts stay white.
+    pub host_colors: Vec<HostColor>,
+    /// Order of the options in a host block the picker writes: `preserve`,
+    /// `alphabetical`, or `canonical` (HostName, User, Port, IdentityFile, then
+    /// the rest alphabetically).
+    pub option_order: OptionOrder,
+}
+
+/// One `host_colors` rule: hosts tagged `tag` and/or whose Host value matches
+/// the `pattern` regex (case ignored) have their name drawn in `color`. A rule
+/// with both needs both to match.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HostColor {
+    pub tag: Option<String>,
+    pub pattern: Option<String>,
+    /// A color name or `#rrggbb`, as for `theme.accent`.
+    pub color: String,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LaunchMode {
+    /// Run ssh in this terminal and return to the picker when it exits.
+    #[default]
+    Replace,
+    /// Open ssh in a new tmux window named after the host (inside tmux only).
+    TmuxWindow,
+    /// Open ssh in a new pane split from the current one (inside tmux only).
+    TmuxSplit,
+}
+
+impl Default for AppSettings {
+    fn default() -> Self {
+        Self {
+            reconnect_attempts: 0,
+            show_identity: false,
+            show_user: true,
+            show_hostname: true,
+            page_size: 0,
+            verbose_commands: false,
+            auto_probe: false,
+            sort_mode: SortMode::default(),
+            keybindings: Keybindings::default(),
+            theme: Theme::default(),
+            remember_last_host: true,
+            launch_mode: LaunchMode::default(),
+            spawn_terminal: None,
+            search_all_fields: false,
+            read_only: false,
+            watch_config: true,
+            max_name_width: 32,
+            tree_view: false,
+            default_options: Vec::new(),
+            wrap_navigation: false,
+            test_timeout_secs: 5,
+            confirm_deletes: true,
+            host_colors: Vec::new(),
+            option_order: OptionOrder::default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Theme {
+    /// Selection, header title, and confirm-dialog color: a name like "cyan" or `#rrggbb`.
+    pub accent: String,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self { accent: "yellow".to_string() }
+    }
+}
+
+/// Directory holding `config.toml` and `state.toml`: `$SSH_PICKER_CONFIG_DIR`
+/// if set, else `ssh-picker` under the platform config directory. On macOS
+/// that is `$XDG_CONFIG_HOME` or an existing `~/.config` before
+/// `~/Library/Application Support`.
+pub fn config_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("SSH_PICKER_CONFIG_DIR").filter(|d| !d.is_empty()) {
+        return Some(PathBuf::from(dir));
+    }
+    #[cfg(target_os = "macos")]
+    {
+        let xdg = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from).filter(|d| d.is_absolute());
+        let dot_config = dirs::home_dir().map(|h| h.join(".config")).filter(|d| d.is_dir());
+        if let Some(base) = xdg.or(dot_config) {
+            return Some(base.join("ssh-picker"));
+        }
+    }
+    dirs::config_dir().map(|d| d.join("ssh-picker"))
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    config_dir().map(|d| d.join("config.toml"))
+}
+
+/// The settings in `config.toml`, with warnings to show at startup. On first
+/// run the defaults are written there; an existing file is never replaced. A
+/// file that can't be read or parsed falls back to the defaults, so a broken
+/// config never keeps the picker from starting.
+pub fn load_or_default() -> (AppSettings, Vec<String>) {
+    let Some(path) = config_path() else {
+        return (AppSettings::default(), Vec::new());
+    };
+    if !path.exists() {
+        let settings = AppSettings::default();
+        let warnings = match write_default(&path, &settings) {
+            Ok(()) => Vec::new(),
+            Err(e) => vec![format!("settings: {:#}", e)],
+        };
+        return (settings, warnings);
+    }
+    match read(&path) {
+        Ok(settings) => (settings, Vec::new()),
+        Err(e) => (AppSettings::default(), vec![format!("settings: {:#}; using defaults", e)]),
+    }
+}
+
+fn read(path: &Path) -> Result<AppSettings> {
+    let text = fs::read_to_string(path).with_context(|| format!("read config {}", path.display()))?;
+    toml::from_str(&text).map_err(|e| {
+        // The full error quotes the offending line over several lines; the
+        // status bar has room for one
+        let line = e.span().map(|span| text[..span.start].matches('\n').count() + 1);
+        let at = line.map(|n| format!(" line {}", n)).unwrap_or_default();
+        anyhow::anyhow!("{}{}: {}", path.display(), at, e.message().trim())
+    })
+}
+
+fn write_default(path: &Path, settings: &AppSettings) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("create config directory {}", parent.display()))?;
+    }
+    let text = toml::to_string_pretty(settings)?;
+    // `create_new` so a file written since the check above is kept
+    match OpenOptions::new().write(true).create_new(true).open(path) {
+        Ok(mut file) => file.write_all(text.as_bytes()),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(()),
+        Err(e) => Err(e),
+    }
+    .with_context(|| format!("write default config {}", path.display()))
+}
+
+/// Things remembered between runs, as opposed to settings the user chooses.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SavedState {
+    /// Pattern of the host selected when ssh-picker last launched ssh or quit.
+    pub last_host: Option<String>,
+    /// Connections made from the picker, by host pattern.
+    pub history: BTreeMap<String, HostHistory>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct HostHistory {
+    pub connections: u32,
+    /// Unix time in seconds.
+    pub last_connected: u64,
+}
+
+impl HostHistory {
+    /// Connection count weighted by how recently the host was used, so a box
+    /// used daily this week outranks one used often last year.
+    pub fn frecency(&self, now: u64) -> f64 {
+        let age = now.saturating_sub(self.last_connected);
+        let weight = match age {
+            a if a < 60 * 60 => 4.0,
+            a if a < 24 * 60 * 60 => 2.0,
+            a if a < 7 * 24 * 60 * 60 => 1.0,
+            a if a < 30 * 24 * 60 * 60 => 0.5,
+            _ => 0.25,
+        };
+        f64::from(self.connections) * weight
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+fn state_path() -> Option<PathBuf> {
+    config_path().map(|p| p.with_file_name("state.toml"))
+}
+
+/// The saved state, or the default if it is missing or unreadable.
+pub fn load_state() -> SavedState {
+    state_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|text| toml::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+pub fn save_state(state: &SavedState) -> Result<()> {
+    let path = state_path().context("no config directory")?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("create config directory {}", parent.display()))?;
+    }
+    write_file_atomic(&path, &toml::to_string_pretty(state)?).with_context(|| format!("write state {}", path.display()))
+}
+
+/// Write `settings` to the config file, creating its directory if needed. A
+/// file that doesn't parse is left alone rather than replaced with defaults.
+pub fn save(settings: &AppSettings) -> Result<()> {
+    save_to(&config_path().context("no config directory")?, settings)
+}
+
+fn save_to(path: &Path, settings: &AppSettings) -> Result<()> {
+    if path.exists() && read(path).is_err() {
+        return Err(anyhow::anyhow!("{} has errors", path.display()));
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("create config directory {}", parent.display()))?;
+    }
+    // Replaced in one rename, so a crash mid-write never truncates it
+    write_file_atomic(path, &toml::to_string_pretty(settings)?).with_context(|| format!("write config {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory under the system temp dir, unique to this test run.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("ssh-picker-settings-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn save_round_trips_and_leaves_no_temporary_file() {
+        let dir = scratch_dir("save");
+        let path = dir.join("nested").join("config.toml");
+        let settings = AppSettings { page_size: 7, tree_view: true, sort_mode: SortMode::Frecency, ..Default::default() };
+        save_to(&path, &settings).unwrap();
+        let read_back = read(&path).unwrap();
+        assert_eq!((read_back.page_size, read_back.tree_view, read_back.sort_mode), (7, true, SortMode::Frecency));
+        let names: Vec<_> = fs::read_dir(path.parent().unwrap()).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(names, ["config.toml"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn read_errors_name_the_file_and_line_on_one_line() {
+        let dir = scratch_dir("read");
+        let path = dir.join("config.toml");
+        fs::write(&path, "page_size = 3\nshow_user = \"yes\"\n").unwrap();
+        let message = format!("{:#}", read(&path).unwrap_err());
+        assert!(message.starts_with(&format!("{} line 2: ", path.display())), "{}", message);
+        assert_eq!(message.lines().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn save_refuses_to_overwrite_a_broken_file() {
+        let dir = scratch_dir("broken");
+        let path = dir.join("config.toml");
+        fs::write(&path, "page_size = [\n").unwrap();
+        let err = save_to(&path, &AppSettings::default()).unwrap_err();
+        assert_eq!(err.to_string(), format!("{} has errors", path.display()));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "page_size = [\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
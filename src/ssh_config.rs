@@ -1,37 +1,244 @@
+use crate::fuzzy;
 use anyhow::Result;
 use home::home_dir;
+use serde::{Deserialize, Serialize};
 use std::fs::{self, OpenOptions};
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
 
-#[derive(Clone, Debug)]
+/// Port ssh uses when a host block has no `Port` line.
+pub const DEFAULT_SSH_PORT: u16 = 22;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct SshHostEntry {
+    /// Everything after `Host`; may list several whitespace-separated aliases.
     pub pattern: String,
     pub hostname: Option<String>,
     pub user: Option<String>,
     pub port: Option<u16>,
+    pub identity_file: Option<String>,
+    /// `ProxyJump` value: one or more comma-separated `[user@]host[:port]` hops.
+    pub proxy_jump: Option<String>,
+    /// Every other option in file order; a key given several times (`LocalForward`,
+    /// extra `IdentityFile`s) has one entry per line.
     pub other: Vec<(String, String)>,
+    /// Config file this host was read from; `None` for hosts not yet saved.
+    pub source_path: Option<PathBuf>,
+    /// Default `-L` spec from a `# forward: <spec>` comment in the block.
+    pub forward: Option<String>,
+    /// Labels from a `# tags: <tag> <tag>` comment in the block, for grouping and filtering.
+    pub tags: Vec<String>,
+    /// Free-text note from a `# note: <text>` comment in the block, shown in the details pane.
+    pub note: Option<String>,
+    /// Lowercased searchable fields, filled by `list_hosts` so filtering doesn't
+    /// lowercase every field of every host on each keystroke. Stale once the
+    /// entry is edited; hosts are re-listed after every write.
+    pub search_key: Option<SearchKey>,
+}
+
+/// Lowercased fields of a host, in the order `match_score` tries them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchKey {
+    /// Pattern, HostName, and User.
+    narrow: Vec<String>,
+    /// IdentityFile, ProxyJump, and every option's key and value.
+    wide: Vec<String>,
 }
 
 impl SshHostEntry {
-    pub fn matches(&self, q: &str) -> bool {
-        // Check each field independently to avoid string concatenation
-        self.pattern.to_lowercase().contains(q) ||
-        self.hostname.as_ref().map_or(false, |h| h.to_lowercase().contains(q)) ||
-        self.user.as_ref().map_or(false, |u| u.to_lowercase().contains(q))
+    /// Best fuzzy score of `q` against the pattern, hostname, and user, or `None` if
+    /// no field matches. With `all_fields`, IdentityFile, ProxyJump, and every
+    /// option in `other` (key and value) are searched too.
+    pub fn match_score(&self, q: &str, all_fields: bool) -> Option<i64> {
+        let built;
+        let key = match &self.search_key {
+            Some(key) => key,
+            None => {
+                built = self.build_search_key();
+                &built
+            }
+        };
+        // Score each field independently to avoid string concatenation
+        let needle: Vec<char> = q.to_lowercase().chars().collect();
+        let wide = key.wide.iter().filter(|_| all_fields);
+        key.narrow.iter().chain(wide).filter_map(|field| fuzzy::score(field, &needle)).max()
     }
 
-    pub fn validate(&self) -> Result<()> {
-        // Validate pattern - no dangerous characters
-        if self.pattern.is_empty() {
-            return Err(anyhow::anyhow!("Host pattern cannot be empty"));
+    /// Lowercase the searchable fields for `search_key`.
+    pub fn build_search_key(&self) -> SearchKey {
+        let narrow = vec![Some(&self.pattern), self.hostname.as_ref(), self.user.as_ref()];
+        let wide = [self.identity_file.as_ref(), self.proxy_jump.as_ref()]
+            .into_iter()
+            .chain(self.other.iter().flat_map(|(k, v)| [Some(k), Some(v)]))
+            .collect();
+        let lower = |fields: Vec<Option<&String>>| fields.into_iter().flatten().map(|f| f.to_lowercase()).collect();
+        SearchKey { narrow: lower(narrow), wide: lower(wide) }
+    }
+
+    /// Whether the host carries `tag`, ignoring case.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t.eq_ignore_ascii_case(tag))
+    }
+
+    /// Whether any alias is a glob (`*`, `?`) or a negation (`!`), so the block
+    /// only supplies defaults for other hosts and can't be connected to itself.
+    pub fn is_wildcard(&self) -> bool {
+        self.aliases().any(|a| a.contains(['*', '?', '!']))
+    }
+
+    /// This host with its options replaced by `options`, read the way the
+    /// parser reads a block's lines. Comment-backed fields are kept.
+    pub fn with_options(&self, options: Vec<(String, String)>) -> Result<Self> {
+        let mut entry = Self {
+            hostname: None,
+            user: None,
+            port: None,
+            identity_file: None,
+            proxy_jump: None,
+            other: Vec::new(),
+            ..self.clone()
+        };
+        for (key, value) in options {
+            entry.set_option(&key, value)?;
         }
-        if self.pattern.contains('\n') || self.pattern.contains('\r') {
-            return Err(anyhow::anyhow!("Host pattern cannot contain newlines"));
+        Ok(entry)
+    }
+
+    /// Every option of the block as `Key value` pairs, common ones first.
+    pub fn options(&self) -> Vec<(String, String)> {
+        let mut options: Vec<(String, String)> = Vec::new();
+        if let Some(hn) = &self.hostname { options.push(("HostName".to_string(), hn.clone())); }
+        if let Some(u) = &self.user { options.push(("User".to_string(), u.clone())); }
+        if let Some(p) = self.port { options.push(("Port".to_string(), p.to_string())); }
+        if let Some(id) = &self.identity_file { options.push(("IdentityFile".to_string(), id.clone())); }
+        if let Some(jump) = &self.proxy_jump { options.push(("ProxyJump".to_string(), jump.clone())); }
+        options.extend(self.other.iter().cloned());
+        options
+    }
+
+    /// Store one `Key value` line: the common options in their own fields,
+    /// everything else in `other`.
+    fn set_option(&mut self, key: &str, value: String) -> Result<()> {
+        match key.to_lowercase().as_str() {
+            "hostname" => self.hostname = Some(value),
+            "user" => self.user = Some(value),
+            "port" => {
+                self.port = parse_port(&value).map_err(|e| anyhow::anyhow!("invalid Port value '{}' ({})", value, e))?;
+            }
+            // ssh allows several IdentityFile lines; extras stay in `other`
+            "identityfile" if self.identity_file.is_none() => self.identity_file = Some(value),
+            "proxyjump" => self.proxy_jump = Some(value),
+            _ => self.other.push((key.to_string(), value)),
         }
-        if self.pattern.contains(';') || self.pattern.contains('#') {
-            return Err(anyhow::anyhow!("Host pattern cannot contain semicolons or hash symbols"));
+        Ok(())
+    }
+
+    /// File name of the config file the host was read from, e.g. `work.conf`.
+    pub fn source_name(&self) -> Option<&str> {
+        self.source_path.as_deref()?.file_name()?.to_str()
+    }
+
+    /// Names excluded with `!`, without the `!`: `Host * !bastion` gives `bastion`.
+    pub fn negated_aliases(&self) -> impl Iterator<Item = &str> {
+        self.aliases().filter_map(|a| a.strip_prefix('!'))
+    }
+
+    /// Each alias on the `Host` line, in order.
+    pub fn aliases(&self) -> impl Iterator<Item = &str> {
+        self.pattern.split_whitespace()
+    }
+
+    /// The first alias: used as the list label and passed to ssh.
+    pub fn primary_alias(&self) -> &str {
+        self.aliases().next().unwrap_or(&self.pattern)
+    }
+
+    /// Whether this host and `pattern` (a full `Host` value) have any alias in common.
+    pub fn shares_alias_with(&self, pattern: &str) -> bool {
+        aliases_overlap(&self.pattern, pattern)
+    }
+
+    /// Address ssh connects to: the HostName (an IPv6 literal without any
+    /// brackets around it, a zone like `%eth0` kept) or else the first alias.
+    pub fn connect_host(&self) -> &str {
+        match self.hostname.as_deref() {
+            Some(hostname) => {
+                let inner = hostname.strip_prefix('[').and_then(|h| h.strip_suffix(']'));
+                inner.unwrap_or(hostname)
+            }
+            None => self.primary_alias(),
+        }
+    }
+
+    /// Port ssh will actually connect on.
+    pub fn effective_port(&self) -> u16 {
+        self.port.unwrap_or(DEFAULT_SSH_PORT)
+    }
+
+    /// Port as shown in the UI: only when the config sets one explicitly.
+    pub fn display_port(&self) -> Option<String> {
+        self.port.map(|p| p.to_string())
+    }
+
+    /// Whether a standalone ssh command for this host should carry `-p`.
+    /// Unset ports never do; an explicit default port only does when `verbose`.
+    pub fn needs_port_flag(&self, verbose: bool) -> bool {
+        match self.port {
+            None => false,
+            Some(DEFAULT_SSH_PORT) => verbose,
+            Some(_) => true,
         }
+    }
+
+    /// Arguments passed to `ssh` to connect to this host, starting with
+    /// `-F config` when the host comes from a config other than ssh's default.
+    pub fn ssh_args(&self, verbose: bool, config: Option<&Path>) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(config) = config {
+            args.push("-F".to_string());
+            args.push(config.display().to_string());
+        }
+        if self.needs_port_flag(verbose) {
+            args.push("-p".to_string());
+            args.push(self.effective_port().to_string());
+        }
+        args.push(self.primary_alias().to_string());
+        args
+    }
+
+    /// Shell command line that connects to this host, e.g. `ssh web-prod`.
+    pub fn ssh_command(&self, verbose: bool, config: Option<&Path>) -> String {
+        let mut cmd = String::from("ssh");
+        for arg in self.ssh_args(verbose, config) {
+            cmd.push(' ');
+            cmd.push_str(&shell_quote(&arg));
+        }
+        cmd
+    }
+
+    /// ProxyJump hops that name neither a configured alias nor `none`. ssh can
+    /// still reach plain host names, so callers should warn rather than reject.
+    pub fn unknown_jump_hosts<'a>(&'a self, hosts: &[SshHostEntry]) -> Vec<&'a str> {
+        let Some(jump) = self.proxy_jump.as_deref().filter(|j| !j.eq_ignore_ascii_case("none")) else {
+            return Vec::new();
+        };
+        jump.split(',')
+            .map(|hop| {
+                let host = hop.rsplit_once('@').map_or(hop, |(_, h)| h);
+                // Strip a port, leaving bracketed IPv6 addresses intact
+                match host.strip_prefix('[') {
+                    Some(v6) => v6.split(']').next().unwrap_or(v6),
+                    None => host.split(':').next().unwrap_or(host),
+                }
+            })
+            .filter(|host| !hosts.iter().any(|h| h.aliases().any(|a| a == *host)))
+            .collect()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        // Validate pattern - no dangerous characters
+        validate_pattern(&self.pattern, true)?;
 
         // Validate hostname if present
         if let Some(hostname) = &self.hostname {
@@ -53,6 +260,25 @@ impl SshHostEntry {
             }
         }
 
+        if let Some(identity) = &self.identity_file {
+            if identity.contains('\n') || identity.contains('\r') {
+                return Err(anyhow::anyhow!("IdentityFile cannot contain newlines"));
+            }
+        }
+
+        if let Some(jump) = &self.proxy_jump {
+            if jump.contains(char::is_whitespace) || jump.contains('#') {
+                return Err(anyhow::anyhow!("ProxyJump cannot contain spaces or hash symbols"));
+            }
+            if jump.split(',').any(str::is_empty) {
+                return Err(anyhow::anyhow!("ProxyJump has an empty hop"));
+            }
+        }
+
+        if let Some(spec) = &self.forward {
+            validate_forward_spec(spec)?;
+        }
+
         // Port validation is handled by parsing
         Ok(())
     }
@@ -61,157 +287,1065 @@ impl SshHostEntry {
 pub struct SshConfigFile {
     pub path: PathBuf,
     pub text: String,
+    /// Files pulled in by `Include`, in the order they were found.
+    pub includes: Vec<IncludedFile>,
+    backend: Backend,
+    /// Most recent change last; each entry restores the files it touched.
+    undo_stack: Vec<UndoEntry>,
+    /// Order of the directives in blocks this writes.
+    pub option_order: OptionOrder,
 }
 
-impl SshConfigFile {
-    pub fn load_default() -> Result<Self> {
-        let path = default_ssh_config_path();
-        Self::load(path)
+/// How the directives of a written host block are ordered.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OptionOrder {
+    /// Keep the block's order; new options go after its last directive.
+    #[default]
+    Preserve,
+    /// Sort by option name.
+    Alphabetical,
+    /// `HostName`, `User`, `Port`, `IdentityFile`, then the rest by name.
+    Canonical,
+}
+
+impl OptionOrder {
+    const CANONICAL: [&'static str; 4] = ["hostname", "user", "port", "identityfile"];
+
+    /// Sort key for an option named `key`; sorts are stable, so repeated keys
+    /// (several `IdentityFile`s) keep their order.
+    fn rank(self, key: &str) -> (usize, String) {
+        let key = key.to_lowercase();
+        let group = match self {
+            OptionOrder::Canonical => Self::CANONICAL.iter().position(|k| *k == key).unwrap_or(Self::CANONICAL.len()),
+            _ => 0,
+        };
+        (group, key)
+    }
+}
+
+struct UndoEntry {
+    description: String,
+    /// Contents of each touched file before the change.
+    files: Vec<(PathBuf, String)>,
+}
+
+/// How many changes `undo` can step back through.
+const MAX_UNDO: usize = 10;
+
+pub struct IncludedFile {
+    pub path: PathBuf,
+    pub text: String,
+}
+
+/// A `Match` block: ssh applies its options to connections meeting the
+/// criteria. Listed for reference only; edits leave these blocks as they are.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MatchEntry {
+    /// Everything after `Match`, e.g. `host *.internal user deploy`.
+    pub criteria: String,
+    pub options: Vec<(String, String)>,
+    pub source_path: Option<PathBuf>,
+    /// 1-based line of the `Match` keyword.
+    pub line: usize,
+}
+
+/// A config line the parser couldn't use. Loading carries on without it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseWarning {
+    /// Included file the line is in; `None` for the main config.
+    pub path: Option<PathBuf>,
+    /// 1-based.
+    pub line: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "{}:{}: {}", path.display(), self.line, self.message),
+            None => write!(f, "line {}: {}", self.line, self.message),
+        }
     }
+}
+
+/// Where edits are read from and written to.
+enum Backend {
+    File,
+    /// Keeps everything in `text`; lets tests assert exact post-edit output.
+    #[cfg(test)]
+    Memory,
+}
 
+/// Nesting limit for `Include`, matching ssh's own.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+impl SshConfigFile {
     pub fn load(path: PathBuf) -> Result<Self> {
-        let mut text = String::new();
-        if path.exists() {
-            std::fs::File::open(&path)?.read_to_string(&mut text)?;
+        Self::load_reporting(path, &mut |_| {})
+    }
+
+    /// `load`, calling `report` with each included file before it is read.
+    pub fn load_reporting(path: PathBuf, report: &mut dyn FnMut(&Path)) -> Result<Self> {
+        let text = read_if_exists(&path)?;
+        // Relative Include paths are resolved against the main config's directory
+        let base = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        let mut visited = HashSet::new();
+        visited.insert(fs::canonicalize(&path).unwrap_or_else(|_| path.clone()));
+        let mut includes = Vec::new();
+        expand_includes(&text, &base, 1, &mut visited, &mut includes, report)?;
+        Ok(Self { path, text, includes, backend: Backend::File, undo_stack: Vec::new(), option_order: OptionOrder::default() })
+    }
+
+    #[cfg(test)]
+    pub fn in_memory(text: &str) -> Self {
+        Self {
+            path: PathBuf::from("<memory>"),
+            text: text.to_string(),
+            includes: vec![],
+            backend: Backend::Memory,
+            undo_stack: Vec::new(),
+            option_order: OptionOrder::default(),
         }
-        Ok(Self { path, text })
     }
 
-    pub fn list_hosts(&self) -> Vec<SshHostEntry> {
-        parse_hosts_from_text(&self.text)
+    /// Whether any source file differs on disk from the copy loaded here, e.g.
+    /// after an edit in another program. Returns the disk contents when it does.
+    pub fn external_changes(&self) -> Option<Vec<String>> {
+        if !matches!(self.backend, Backend::File) {
+            return None;
+        }
+        let sources = std::iter::once((&self.path, &self.text))
+            .chain(self.includes.iter().map(|inc| (&inc.path, &inc.text)));
+        let mut disk = Vec::new();
+        let mut changed = false;
+        for (path, text) in sources {
+            // An unreadable file counts as empty, like a missing one at load time
+            let current = read_if_exists(path).unwrap_or_default();
+            changed |= current != *text;
+            disk.push(current);
+        }
+        changed.then_some(disk)
+    }
+
+    /// The config to hand ssh with `-F`: this one's path, unless it is ssh's
+    /// default `~/.ssh/config` that ssh reads anyway.
+    pub fn ssh_flag_path(&self) -> Option<&Path> {
+        (self.path != default_ssh_config_path()).then_some(self.path.as_path())
     }
 
-    pub fn upsert_host(&mut self, entry: &SshHostEntry) -> Result<()> {
-        // naive approach: append or replace by pattern - preserves comments by appending
-        // Parse existing file to string and rebuild
-        let mut text = String::new();
-        if self.path.exists() {
-            std::fs::File::open(&self.path)?.read_to_string(&mut text)?;
+    /// The main file followed by each included file.
+    pub fn source_paths(&self) -> Vec<&Path> {
+        std::iter::once(self.path.as_path()).chain(self.includes.iter().map(|inc| inc.path.as_path())).collect()
+    }
+
+    /// Re-read the main file and its includes from disk. The undo history is
+    /// dropped: its snapshots predate the new contents, so restoring one would
+    /// throw away whatever changed the files.
+    pub fn reload(&mut self) -> Result<()> {
+        let fresh = Self::load(self.path.clone())?;
+        self.text = fresh.text;
+        self.includes = fresh.includes;
+        self.undo_stack.clear();
+        Ok(())
+    }
+
+    /// Current contents of one source file (re-read from disk for files).
+    fn read_text(&self, path: &Path) -> Result<String> {
+        match self.backend {
+            Backend::File => read_if_exists(path),
+            #[cfg(test)]
+            Backend::Memory => Ok(self.text.clone()),
         }
+    }
 
-        let lines: Vec<&str> = text.lines().collect();
-        // Find existing block starting with "Host <pattern>" (exact match)
-        let mut start = None;
-        for (i, line) in lines.iter().enumerate() {
-            if line.trim_start().starts_with("Host ") {
-                let rest = line.trim_start()[5..].trim();
-                if rest == entry.pattern {
-                    start = Some(i);
-                    break;
+    /// Check that `new_text`, about to be written, reads back as `entries`:
+    /// every option is one `Key value` line, and some block with each entry's
+    /// pattern parses to the same options, tags, note, and forward. Catches a
+    /// value that would otherwise leave a broken config behind.
+    pub fn validate_write<'a>(new_text: &str, entries: impl IntoIterator<Item = &'a SshHostEntry>) -> Result<()> {
+        let (written, _) = parse_hosts_from_text(new_text);
+        for entry in entries {
+            if entry.pattern.contains(['\n', '\r']) {
+                return Err(anyhow::anyhow!("Host cannot contain newlines"));
+            }
+            for (key, value) in entry.options() {
+                if key.is_empty() {
+                    return Err(anyhow::anyhow!("an option of '{}' has no name", entry.pattern));
+                }
+                if key.contains(|c: char| c.is_whitespace() || c == '=' || c == '#') {
+                    return Err(anyhow::anyhow!("option name '{}' cannot contain spaces, '=' or '#'", key.trim()));
+                }
+                if value.trim().is_empty() {
+                    return Err(anyhow::anyhow!("{} has no value", key));
                 }
+                if value.contains(['\n', '\r']) {
+                    return Err(anyhow::anyhow!("{} cannot contain newlines", key));
+                }
+            }
+            let comments = [entry.note.as_deref(), entry.forward.as_deref()].into_iter().flatten().chain(entry.tags.iter().map(String::as_str));
+            if comments.into_iter().any(|c| c.contains(['\n', '\r'])) {
+                return Err(anyhow::anyhow!("notes, tags, and forwards cannot contain newlines"));
+            }
+            let pattern = host_line_pattern(&format!("Host {}", entry.pattern)).unwrap_or_default();
+            if !written.iter().any(|h| h.pattern == pattern && reads_back_as(h, entry)) {
+                return Err(anyhow::anyhow!("'{}' would not read back as saved; the config was not changed", entry.pattern));
             }
         }
+        Ok(())
+    }
 
-        let new_block = render_host_block(entry);
-        let mut new_text = String::new();
-        if let Some(i) = start {
-            // Replace until next "Host " or EOF
-            let mut j = i + 1;
-            while j < lines.len() && !lines[j].trim_start().starts_with("Host ") {
-                j += 1;
+    /// Persist `text` to one source file and refresh the in-memory copy.
+    fn write_text(&mut self, path: &Path, text: &str) -> Result<()> {
+        match self.backend {
+            Backend::File => {
+                // Atomic write to prevent corruption
+                write_file_atomic(path, text)?;
+                let fresh = Self::load(self.path.clone())?;
+                self.text = fresh.text;
+                self.includes = fresh.includes;
             }
+            #[cfg(test)]
+            Backend::Memory => self.text = text.to_string(),
+        }
+        Ok(())
+    }
+
+    fn push_undo(&mut self, description: String, files: Vec<(PathBuf, String)>) {
+        if self.undo_stack.len() == MAX_UNDO {
+            self.undo_stack.remove(0);
+        }
+        self.undo_stack.push(UndoEntry { description, files });
+    }
+
+    /// Restore the files touched by the most recent change. Returns a
+    /// description of what was undone, or `None` if there is nothing to undo.
+    pub fn undo(&mut self) -> Result<Option<String>> {
+        let Some(entry) = self.undo_stack.pop() else {
+            return Ok(None);
+        };
+        for (path, text) in &entry.files {
+            self.write_text(path, text)?;
+        }
+        Ok(Some(entry.description))
+    }
+
+    /// Hosts from the main file followed by those from each included file,
+    /// each tagged with the file it came from.
+    pub fn list_hosts(&self) -> Vec<SshHostEntry> {
+        let sources = std::iter::once((&self.path, &self.text))
+            .chain(self.includes.iter().map(|inc| (&inc.path, &inc.text)));
+        let mut hosts = Vec::new();
+        for (path, text) in sources {
+            hosts.extend(parse_hosts_from_text(text).0.into_iter().map(|mut h| {
+                h.source_path = Some(path.clone());
+                h.search_key = Some(h.build_search_key());
+                h
+            }));
+        }
+        hosts
+    }
+
+    /// `Match` blocks from the main file followed by those from each included file.
+    pub fn list_matches(&self) -> Vec<MatchEntry> {
+        let sources = std::iter::once((&self.path, &self.text))
+            .chain(self.includes.iter().map(|inc| (&inc.path, &inc.text)));
+        let mut matches = Vec::new();
+        for (path, text) in sources {
+            matches.extend(parse_matches_from_text(text).into_iter().map(|mut m| {
+                m.source_path = Some(path.clone());
+                m
+            }));
+        }
+        matches
+    }
+
+    /// Lines of the main file and its includes that `list_hosts` had to skip.
+    pub fn parse_warnings(&self) -> Vec<ParseWarning> {
+        let sources = std::iter::once((None, &self.text))
+            .chain(self.includes.iter().map(|inc| (Some(&inc.path), &inc.text)));
+        let mut warnings = Vec::new();
+        for (path, text) in sources {
+            warnings.extend(parse_hosts_from_text(text).1.into_iter().map(|mut w| {
+                w.path = path.cloned();
+                w
+            }));
+        }
+        warnings
+    }
+
+    /// Rewrite the block named by `original` (the pattern the host had before
+    /// an edit, or `entry`'s own when `None`) to match `entry`, or append a new
+    /// block if there is none. Locating the block by its old name keeps a
+    /// renamed host in place instead of adding a second block. A single alias
+    /// finds the first block that lists it; a longer value only its own block.
+    pub fn upsert_host(&mut self, original: Option<&str>, entry: &SshHostEntry) -> Result<()> {
+        self.replace_block(original.unwrap_or(&entry.pattern), entry, names_block)
+    }
+
+    /// Like `upsert_host`, but only a block whose `Host` value is exactly
+    /// `original` (or `entry.pattern`) counts, so editing a listed host never
+    /// rewrites another block that merely shares an alias or a wildcard with it.
+    pub fn upsert_exact_host(&mut self, original: Option<&str>, entry: &SshHostEntry) -> Result<()> {
+        self.replace_block(original.unwrap_or(&entry.pattern), entry, same_host_value)
+    }
+
+    /// Rewrite the first block of `entry`'s file whose `Host` value `matches`
+    /// `original`, or append one.
+    fn replace_block(&mut self, original: &str, entry: &SshHostEntry, matches: fn(&str, &str) -> bool) -> Result<()> {
+        // Edits go back to the file the host came from; new hosts go to the main file
+        let path = entry.source_path.clone().unwrap_or_else(|| self.path.clone());
+        // Replace an existing block in place (keeping its comments and layout) or append a new one
+        let text = self.read_text(&path)?;
+        let text_before = text.clone();
+
+        let lines: Vec<&str> = text.lines().collect();
+        let found = block_range(&lines, original, matches);
+
+        let mut new_text = String::new();
+        if let Some((i, j)) = found {
             // Reconstruct
             for l in &lines[..i] {
                 new_text.push_str(l);
                 new_text.push('\n');
             }
-            new_text.push_str(&new_block);
+            new_text.push_str(&merge_host_block(&lines[i..j], entry, self.option_order));
             for l in &lines[j..] {
                 new_text.push_str(l);
                 new_text.push('\n');
             }
         } else {
-            new_text = text;
-            if !new_text.ends_with('\n') && !new_text.is_empty() { new_text.push('\n'); }
-            new_text.push_str(&new_block);
+            // Exactly one blank line between the last block and the new one
+            new_text = trim_trailing_blank_lines(&text);
+            if !new_text.is_empty() { new_text.push('\n'); }
+            new_text.push_str(&render_host_block(entry, self.option_order));
         }
 
-        // Atomic write to prevent corruption
-        write_file_atomic(&self.path, &new_text)?;
+        let action = if found.is_some() { "edit" } else { "add" };
+        let new_text = trim_trailing_blank_lines(&new_text);
+        Self::validate_write(&new_text, [entry])?;
+        self.write_text(&path, &new_text)?;
+        self.push_undo(format!("{} of '{}'", action, entry.pattern), vec![(path, text_before)]);
+        Ok(())
+    }
 
-        // Refresh in-memory
-        *self = Self::load(self.path.clone())?;
+    /// Change only the `Host` line of `entry`'s block to `pattern`; its options,
+    /// comments, and place in the file stay as they are.
+    pub fn rename_host(&mut self, entry: &SshHostEntry, pattern: &str) -> Result<()> {
+        let path = entry.source_path.clone().unwrap_or_else(|| self.path.clone());
+        let text = self.read_text(&path)?;
+        let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+        let line = lines
+            .iter_mut()
+            .find(|l| host_line_pattern(l).is_some_and(|p| p == entry.pattern))
+            .ok_or_else(|| anyhow::anyhow!("host '{}' not found in {}", entry.pattern, path.display()))?;
+        let indent = &line[..line.len() - line.trim_start().len()];
+        *line = format!("{}Host {}", indent, pattern);
+
+        let mut new_text = lines.join("\n");
+        new_text.push('\n');
+        self.write_text(&path, &new_text)?;
+        self.push_undo(format!("rename of '{}' to '{}'", entry.pattern, pattern), vec![(path, text)]);
         Ok(())
     }
 
-    pub fn delete_host(&mut self, pattern: &str) -> Result<()> {
-        if !self.path.exists() { return Ok(()); }
-        let mut text = String::new();
-        std::fs::File::open(&self.path)?.read_to_string(&mut text)?;
-        let lines: Vec<&str> = text.lines().collect();
+    /// Problems ssh tolerates but that make editing ambiguous. Currently: `Host`
+    /// values defined by more than one block. Each block is listed as its own
+    /// host, but edits and deletes always change the first block of that file,
+    /// and ssh itself takes each option from the first block that sets it.
+    pub fn validate_config(&self) -> Vec<String> {
+        let mut counts: Vec<(String, usize)> = Vec::new();
+        for host in self.list_hosts() {
+            match counts.iter_mut().find(|(p, _)| *p == host.pattern) {
+                Some((_, n)) => *n += 1,
+                None => counts.push((host.pattern, 1)),
+            }
+        }
+        counts
+            .into_iter()
+            .filter(|(_, n)| *n > 1)
+            .map(|(pattern, n)| format!("'{}' has {} Host blocks; edits change the first", pattern, n))
+            .collect()
+    }
 
-        // Find and remove block with exact pattern
-        let mut i = 0;
-        let mut new_text = String::new();
-        while i < lines.len() {
-            if lines[i].trim_start().starts_with("Host ") {
-                let rest = lines[i].trim_start()[5..].trim();
-                if rest == pattern {
-                    // skip this block
-                    i += 1;
-                    while i < lines.len() && !lines[i].trim_start().starts_with("Host ") { i += 1; }
-                    continue;
-                }
+    /// Keep only the first block for each exact `Host` value (main file first,
+    /// then includes) and drop the rest. Returns how many blocks were removed.
+    pub fn merge_duplicates(&mut self) -> Result<usize> {
+        let paths: Vec<PathBuf> = std::iter::once(self.path.clone())
+            .chain(self.includes.iter().map(|inc| inc.path.clone()))
+            .collect();
+        let before = self.list_hosts().len();
+        let mut seen = HashSet::new();
+        let mut touched = Vec::new();
+        for path in paths {
+            let text = self.read_text(&path)?;
+            if let Some(new_text) = remove_repeated_blocks(&text, &mut seen) {
+                self.write_text(&path, &trim_trailing_blank_lines(&new_text))?;
+                touched.push((path, text));
             }
-            new_text.push_str(lines[i]);
-            new_text.push('\n');
-            i += 1;
         }
+        if touched.is_empty() {
+            return Ok(0);
+        }
+        self.push_undo("merge of duplicate hosts".to_string(), touched);
+        Ok(before - self.list_hosts().len())
+    }
 
-        write_file_atomic(&self.path, &new_text)?;
-        *self = Self::load(self.path.clone())?;
+    /// Append a block for each of `entries` to the main file in one write, so a
+    /// single undo removes them all. Returns how many were added.
+    pub fn add_hosts(&mut self, entries: &[SshHostEntry]) -> Result<usize> {
+        let path = self.path.clone();
+        let text = self.read_text(&path)?;
+        let mut new_text = trim_trailing_blank_lines(&text);
+        for entry in entries {
+            if !new_text.is_empty() { new_text.push('\n'); }
+            new_text.push_str(&render_host_block(entry, self.option_order));
+        }
+        if !entries.is_empty() {
+            Self::validate_write(&new_text, entries.iter())?;
+            self.write_text(&path, &new_text)?;
+            self.push_undo(format!("import of {} hosts", entries.len()), vec![(path, text)]);
+        }
+        Ok(entries.len())
+    }
+
+    /// Remove the first block of `entry`'s file whose `Host` value is exactly
+    /// `entry.pattern`, leaving blocks that merely share an alias with it.
+    pub fn delete_exact_host(&mut self, entry: &SshHostEntry) -> Result<()> {
+        self.delete_exact_hosts(std::slice::from_ref(entry))
+    }
+
+    /// `delete_exact_host` for each of `entries`, writing each affected file
+    /// once; a single undo restores them all.
+    pub fn delete_exact_hosts(&mut self, entries: &[SshHostEntry]) -> Result<()> {
+        let mut paths: Vec<PathBuf> = Vec::new();
+        for entry in entries {
+            let path = entry.source_path.clone().unwrap_or_else(|| self.path.clone());
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+        let mut touched = Vec::new();
+        for path in paths {
+            let text = self.read_text(&path)?;
+            let mut lines: Vec<&str> = text.lines().collect();
+            let mut removed = false;
+            for entry in entries.iter().filter(|e| e.source_path.as_ref().unwrap_or(&self.path) == &path) {
+                if let Some((i, j)) = block_range(&lines, &entry.pattern, same_host_value) {
+                    lines.drain(i..j);
+                    removed = true;
+                }
+            }
+            if removed {
+                let mut new_text = String::new();
+                for l in &lines {
+                    new_text.push_str(l);
+                    new_text.push('\n');
+                }
+                self.write_text(&path, &trim_trailing_blank_lines(&new_text))?;
+                touched.push((path, text));
+            }
+        }
+        if !touched.is_empty() {
+            let description = match entries {
+                [entry] => format!("delete of '{}'", entry.pattern),
+                _ => format!("delete of {} hosts", entries.len()),
+            };
+            self.push_undo(description, touched);
+        }
         Ok(())
     }
 }
 
-fn render_host_block(entry: &SshHostEntry) -> String {
+/// `text` without blocks whose exact `Host` value is already in `seen` (adding
+/// each kept block's value to it), or `None` if nothing was removed.
+fn remove_repeated_blocks(text: &str, seen: &mut HashSet<String>) -> Option<String> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut removed = false;
+    let mut i = 0;
+    let mut new_text = String::new();
+    while i < lines.len() {
+        if let Some(rest) = host_line_pattern(lines[i]) {
+            if !seen.insert(rest) {
+                removed = true;
+                i += 1;
+                while i < lines.len() && !is_block_start(lines[i]) { i += 1; }
+                continue;
+            }
+        }
+        new_text.push_str(lines[i]);
+        new_text.push('\n');
+        i += 1;
+    }
+    removed.then_some(new_text)
+}
+
+/// `entries` as a standalone config fragment: one block each, separated by a blank line.
+pub fn render_hosts(entries: &[SshHostEntry]) -> String {
+    entries.iter().map(|e| render_host_block(e, OptionOrder::Preserve)).collect::<Vec<_>>().join("\n")
+}
+
+/// Write `entries` to `path` as a fragment for someone else's `config.d/`,
+/// replacing whatever the file held. The loaded config is not touched.
+pub fn export_hosts(path: &Path, entries: &[SshHostEntry]) -> Result<()> {
+    write_file_atomic(path, &render_hosts(entries)).map_err(|e| anyhow::anyhow!("could not write {}: {}", path.display(), e))
+}
+
+fn render_host_block(entry: &SshHostEntry, order: OptionOrder) -> String {
     let mut out = String::new();
     out.push_str(&format!("Host {}\n", entry.pattern));
-    if let Some(hn) = &entry.hostname { out.push_str(&format!("    HostName {}\n", hn)); }
-    if let Some(u) = &entry.user { out.push_str(&format!("    User {}\n", u)); }
-    if let Some(p) = entry.port { out.push_str(&format!("    Port {}\n", p)); }
-    for (k, v) in &entry.other { out.push_str(&format!("    {} {}\n", k, v)); }
-    out.push('\n');
+    let mut options = entry.options();
+    if order != OptionOrder::Preserve {
+        options.sort_by_cached_key(|(k, _)| order.rank(k));
+    }
+    for (k, v) in options { out.push_str(&format!("    {} {}\n", k, v)); }
+    if let Some(f) = &entry.forward { out.push_str(&format!("    {} {}\n", FORWARD_COMMENT, f)); }
+    if !entry.tags.is_empty() { out.push_str(&format!("    {} {}\n", TAGS_COMMENT, entry.tags.join(" "))); }
+    if let Some(note) = &entry.note { out.push_str(&format!("    {} {}\n", NOTE_COMMENT, note)); }
     out
 }
 
-fn default_ssh_config_path() -> PathBuf {
+/// Whether `written`, parsed back from a file, holds what `entry` asked for.
+/// Option order and the case of option names are not compared, since
+/// `OptionOrder` and in-place edits may change them.
+fn reads_back_as(written: &SshHostEntry, entry: &SshHostEntry) -> bool {
+    let normalized = |e: &SshHostEntry| {
+        let mut options: Vec<(String, String)> = e.options().into_iter().map(|(k, v)| (k.to_lowercase(), v)).collect();
+        // Stable, so repeated keys keep their order
+        options.sort_by(|a, b| a.0.cmp(&b.0));
+        options
+    };
+    normalized(written) == normalized(entry) && written.tags == entry.tags && written.note == entry.note && written.forward == entry.forward
+}
+
+/// `text` ending in exactly one newline (or empty), so repeated edits at the end
+/// of a file don't pile up blank lines.
+fn trim_trailing_blank_lines(text: &str) -> String {
+    let trimmed = text.trim_end();
+    if trimmed.is_empty() {
+        String::new()
+    } else {
+        format!("{}\n", trimmed)
+    }
+}
+
+/// Quote `arg` for a POSIX shell if it contains anything beyond plain word characters.
+fn shell_quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_.,:/@%+=".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// The value of a `Host` line with runs of whitespace collapsed to one space,
+/// or `None` for any other line. Like ssh, the keyword is case-insensitive and
+/// may be followed by whitespace or `=`.
+fn host_line_pattern(line: &str) -> Option<String> {
+    block_line_value(line, "host")
+}
+
+/// The criteria of a `Match` line, normalized like `host_line_pattern`.
+fn match_line_criteria(line: &str) -> Option<String> {
+    block_line_value(line, "match")
+}
+
+fn block_line_value(line: &str, keyword: &str) -> Option<String> {
+    let trimmed = line.trim();
+    let split = trimmed.find(|c: char| c.is_whitespace() || c == '=')?;
+    let (found, rest) = trimmed.split_at(split);
+    if !found.eq_ignore_ascii_case(keyword) {
+        return None;
+    }
+    let rest = rest.trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest);
+    Some(rest.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+/// Keyword and value of a directive line. Like ssh, the keyword may be followed
+/// by whitespace or `=` (`User root`, `User=root`, `User = root`). The value
+/// keeps its inner spacing, since options like `ProxyCommand` hold a whole
+/// shell command.
+fn split_directive(line: &str) -> (&str, &str) {
+    let trimmed = line.trim();
+    let Some(split) = trimmed.find(|c: char| c.is_whitespace() || c == '=') else {
+        return (trimmed, "");
+    };
+    let (key, rest) = trimmed.split_at(split);
+    let rest = rest.trim_start();
+    let value = rest.strip_prefix('=').unwrap_or(rest);
+    (key, value.trim())
+}
+
+/// Whether `line` ends the block before it: a `Host` or a `Match` line.
+fn is_block_start(line: &str) -> bool {
+    host_line_pattern(line).is_some() || match_line_criteria(line).is_some()
+}
+
+/// Whether two `Host` values name at least one alias in common.
+fn aliases_overlap(a: &str, b: &str) -> bool {
+    a.split_whitespace().any(|x| b.split_whitespace().any(|y| x == y))
+}
+
+/// Whether `pattern` picks out the block whose `Host` value is `value`: a
+/// single alias names every block that lists it, anything longer only the
+/// block with exactly that value.
+fn names_block(value: &str, pattern: &str) -> bool {
+    if pattern.split_whitespace().nth(1).is_some() {
+        same_host_value(value, pattern)
+    } else {
+        aliases_overlap(value, pattern)
+    }
+}
+
+/// Whether two `Host` values are the same, ignoring runs of whitespace.
+fn same_host_value(a: &str, b: &str) -> bool {
+    a.split_whitespace().eq(b.split_whitespace())
+}
+
+/// Lines of the first block whose `Host` value `matches` `pattern`: its `Host`
+/// line up to the next `Host` or `Match` line, or the end.
+fn block_range(lines: &[&str], pattern: &str, matches: fn(&str, &str) -> bool) -> Option<(usize, usize)> {
+    let start = lines.iter().position(|line| host_line_pattern(line).is_some_and(|rest| matches(&rest, pattern)))?;
+    let end = lines[start + 1..].iter().position(|line| is_block_start(line)).map_or(lines.len(), |n| start + 1 + n);
+    Some((start, end))
+}
+
+/// Rewrite an existing block (its `Host` line through the line before the next
+/// `Host`) to match `entry`. Comments, blank lines, indentation, and directives
+/// whose value is unchanged are kept byte-for-byte; changed directives are
+/// rewritten in place, removed ones dropped, and new ones added after the last
+/// directive of the block. Unless `order` is `Preserve`, the directives are
+/// then sorted among the lines they occupy.
+fn merge_host_block(block: &[&str], entry: &SshHostEntry, order: OptionOrder) -> String {
+    let wanted = entry.options();
+    let mut used = vec![false; wanted.len()];
+    // Comment-backed fields: each is kept, rewritten, or dropped like a directive
+    let comments = [
+        (FORWARD_COMMENT, entry.forward.clone()),
+        (TAGS_COMMENT, (!entry.tags.is_empty()).then(|| entry.tags.join(" "))),
+        (NOTE_COMMENT, entry.note.clone()),
+    ];
+    let mut comment_written = [false; 3];
+
+    let header = host_line_pattern(block[0]).unwrap_or_default();
+    let mut out: Vec<String> = vec![if header == entry.pattern {
+        block[0].to_string()
+    } else {
+        format!("Host {}", entry.pattern)
+    }];
+    let mut indent = "    ".to_string();
+    let mut indent_seen = false;
+    let mut insert_at = 1;
+
+    // Lines whose option is unchanged keep it first, so dropping one of several
+    // repeated options (two `LocalForward`s) doesn't shift the rest; the other
+    // lines then take the next wanted value for their key, in order
+    let directives: Vec<Option<(&str, &str)>> = block[1..]
+        .iter()
+        .map(|line| {
+            let trimmed = line.trim();
+            (!trimmed.is_empty() && !trimmed.starts_with('#')).then(|| split_directive(trimmed))
+        })
+        .collect();
+    let mut slots = vec![None; directives.len()];
+    for exact in [true, false] {
+        for (n, (key, value)) in directives.iter().enumerate().filter_map(|(n, d)| Some((n, (*d)?))) {
+            if slots[n].is_some() {
+                continue;
+            }
+            slots[n] = (0..wanted.len()).find(|&w| !used[w] && wanted[w].0.eq_ignore_ascii_case(key) && (!exact || wanted[w].1 == value));
+            if let Some(w) = slots[n] {
+                used[w] = true;
+            }
+        }
+    }
+
+    for (n, line) in block[1..].iter().enumerate() {
+        let trimmed = line.trim();
+        let line_indent = &line[..line.len() - line.trim_start().len()];
+        if let Some(n) = comments.iter().position(|(prefix, _)| trimmed.starts_with(prefix)) {
+            let (prefix, wanted_value) = &comments[n];
+            if let (Some(v), false) = (wanted_value, comment_written[n]) {
+                let current = trimmed[prefix.len()..].trim();
+                out.push(if current == v { line.to_string() } else { format!("{}{} {}", line_indent, prefix, v) });
+                comment_written[n] = true;
+                insert_at = out.len();
+            }
+            continue;
+        }
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            out.push(line.to_string());
+            // Indented comments belong to this block; unindented ones usually
+            // introduce the next block, so new directives go before them
+            if !trimmed.is_empty() && !line_indent.is_empty() { insert_at = out.len(); }
+            continue;
+        }
+        if !indent_seen {
+            indent = line_indent.to_string();
+            indent_seen = true;
+        }
+        let (key, value) = split_directive(trimmed);
+        if let Some(w) = slots[n] {
+            let new_value = &wanted[w].1;
+            out.push(if new_value == value { line.to_string() } else { format!("{}{} {}", line_indent, key, new_value) });
+            insert_at = out.len();
+        }
+    }
+
+    let mut added: Vec<String> = wanted
+        .iter()
+        .zip(&used)
+        .filter(|(_, used)| !**used)
+        .map(|((k, v), _)| format!("{}{} {}", indent, k, v))
+        .collect();
+    for ((prefix, value), written) in comments.iter().zip(comment_written) {
+        if let (Some(v), false) = (value, written) {
+            added.push(format!("{}{} {}", indent, prefix, v));
+        }
+    }
+    out.splice(insert_at..insert_at, added);
+    if order != OptionOrder::Preserve {
+        let is_directive = |line: &str| !line.trim().is_empty() && !line.trim().starts_with('#');
+        let slots: Vec<usize> = (1..out.len()).filter(|&n| is_directive(&out[n])).collect();
+        let mut directives: Vec<String> = slots.iter().map(|&n| out[n].clone()).collect();
+        directives.sort_by_cached_key(|line| order.rank(split_directive(line).0));
+        for (n, line) in slots.into_iter().zip(directives) {
+            out[n] = line;
+        }
+    }
+
+    let mut text = out.join("\n");
+    text.push('\n');
+    text
+}
+
+/// Comment prefix marking a host's default port-forward spec.
+const FORWARD_COMMENT: &str = "# forward:";
+
+/// Comment prefix listing a host's tags, separated by spaces or commas.
+const TAGS_COMMENT: &str = "# tags:";
+
+/// Comment prefix holding a host's one-line note.
+const NOTE_COMMENT: &str = "# note:";
+
+/// Tags from the text after `# tags:`; a leading `#` on a tag is dropped.
+fn parse_tags(text: &str) -> Vec<String> {
+    text.split(|c: char| c.is_whitespace() || c == ',')
+        .map(|t| t.trim_start_matches('#'))
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Check a `Host` value. Several whitespace-separated aliases are only accepted
+/// when `allow_aliases` is set.
+pub fn validate_pattern(pattern: &str, allow_aliases: bool) -> Result<()> {
+    if pattern.trim().is_empty() {
+        return Err(anyhow::anyhow!("Host pattern cannot be empty"));
+    }
+    if pattern.contains('\n') || pattern.contains('\r') {
+        return Err(anyhow::anyhow!("Host pattern cannot contain newlines"));
+    }
+    if pattern.contains(';') || pattern.contains('#') {
+        return Err(anyhow::anyhow!("Host pattern cannot contain semicolons or hash symbols"));
+    }
+    if let Some(c) = pattern.chars().find(|c| (c.is_control() && !c.is_whitespace()) || matches!(c, '"' | '\'' | '=')) {
+        return Err(anyhow::anyhow!("Host pattern cannot contain {:?}", c));
+    }
+    if !allow_aliases && pattern.trim().contains(char::is_whitespace) {
+        return Err(anyhow::anyhow!("Host pattern cannot contain spaces"));
+    }
+    Ok(())
+}
+
+/// The `:`-separated parts of a forward spec, with brackets taken off IPv6
+/// literals (`8080:[::1]:80` gives `8080`, `::1`, `80`).
+fn forward_parts(spec: &str) -> Result<Vec<&str>> {
+    let mut parts = Vec::new();
+    let mut rest = spec.trim();
+    while !rest.is_empty() {
+        let (part, tail) = if let Some(inner) = rest.strip_prefix('[') {
+            let end = inner.find(']').ok_or_else(|| anyhow::anyhow!("unclosed '[' in forward spec"))?;
+            (&inner[..end], inner[end + 1..].strip_prefix(':').unwrap_or(&inner[end + 1..]))
+        } else {
+            match rest.split_once(':') {
+                Some((part, tail)) => (part, tail),
+                None => (rest, ""),
+            }
+        };
+        parts.push(part);
+        rest = tail;
+    }
+    Ok(parts)
+}
+
+/// Check a `-L` spec of the form `[bind:]localport:remotehost:remoteport`.
+/// Hosts may be bracketed IPv6 literals, e.g. `8080:[::1]:80`.
+pub fn validate_forward_spec(spec: &str) -> Result<()> {
+    let parts = forward_parts(spec)?;
+    let (local_port, host, remote_port) = match parts.as_slice() {
+        [lp, h, rp] | [_, lp, h, rp] => (*lp, *h, *rp),
+        _ => return Err(anyhow::anyhow!("forward spec must be localport:remotehost:remoteport")),
+    };
+    for port in [local_port, remote_port] {
+        match port.parse::<u16>() {
+            Ok(p) if p > 0 => {}
+            _ => return Err(anyhow::anyhow!("invalid port '{}' in forward spec", port)),
+        }
+    }
+    if host.is_empty() || host.contains(char::is_whitespace) {
+        return Err(anyhow::anyhow!("invalid host '{}' in forward spec", host));
+    }
+    Ok(())
+}
+
+/// The value of a `LocalForward`, `RemoteForward`, or `DynamicForward` line as
+/// ssh's config spells it (`[bind:]port host:hostport`, or just `[bind:]port`
+/// for a dynamic forward), from that or the `-L` style `[bind:]port:host:hostport`.
+/// Checked loosely: ports must be numbers and the host non-empty. Specs with a
+/// Unix socket path are kept as typed.
+pub fn normalize_forward(option: &str, spec: &str) -> Result<String> {
+    let words: Vec<&str> = spec.split_whitespace().collect();
+    if words.iter().any(|w| w.contains('/')) {
+        return Ok(words.join(" "));
+    }
+    let joined = words.join(":");
+    let parts = forward_parts(&joined)?;
+    let bracketed = |host: &str| if host.contains(':') { format!("[{}]", host) } else { host.to_string() };
+    let port = |p: &str| match p.parse::<u16>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(anyhow::anyhow!("invalid port '{}' in {}", p, option)),
+    };
+    let listen = |bind: Option<&str>, p: &str| -> Result<String> {
+        let p = port(p)?;
+        Ok(bind.map_or_else(|| p.to_string(), |b| format!("{}:{}", bracketed(b), p)))
+    };
+    let dynamic_only = option.eq_ignore_ascii_case("DynamicForward");
+    // RemoteForward with no target is ssh's remote dynamic (SOCKS) forward
+    let may_be_dynamic = dynamic_only || option.eq_ignore_ascii_case("RemoteForward");
+    match parts.as_slice() {
+        [p] if may_be_dynamic => listen(None, p),
+        [bind, p] if may_be_dynamic => listen(Some(bind), p),
+        [p, host, hp] if !dynamic_only && !host.is_empty() => Ok(format!("{} {}:{}", listen(None, p)?, bracketed(host), port(hp)?)),
+        [bind, p, host, hp] if !dynamic_only && !host.is_empty() => {
+            Ok(format!("{} {}:{}", listen(Some(bind), p)?, bracketed(host), port(hp)?))
+        }
+        _ if dynamic_only => Err(anyhow::anyhow!("{} takes [bind:]port", option)),
+        _ => Err(anyhow::anyhow!("{} takes [bind:]port host:hostport", option)),
+    }
+}
+
+/// The first alias after `name` that no host in `hosts` uses, counting up its
+/// trailing number with the same zero padding (`web09` gives `web10`); a name
+/// without one gets `-2`, `-3`, ….
+pub fn next_free_alias(name: &str, hosts: &[SshHostEntry]) -> String {
+    let stem = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    let digits = &name[stem.len()..];
+    let (stem, mut n) = match digits.parse::<u64>() {
+        Ok(n) => (stem.to_string(), n.saturating_add(1)),
+        Err(_) => (format!("{}-", name), 2),
+    };
+    loop {
+        let candidate = format!("{}{:0width$}", stem, n, width = digits.len());
+        if !hosts.iter().any(|h| h.shares_alias_with(&candidate)) {
+            return candidate;
+        }
+        n = n.saturating_add(1);
+    }
+}
+
+/// `host:port`, with an IPv6 address bracketed (`[::1]:2222`) so its colons
+/// can't be mistaken for the port separator. Without a port, `host` as is.
+pub fn join_host_port(host: &str, port: Option<u16>) -> String {
+    match port {
+        Some(port) if host.contains(':') && !host.starts_with('[') => format!("[{}]:{}", host, port),
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    }
+}
+
+/// Split a `host:port` HostName into its parts. IPv6 literals need brackets
+/// for a port (`[::1]:22`); an unbracketed value with several colons is taken
+/// as a bare IPv6 address and returned unchanged.
+pub fn split_host_port(value: &str) -> Result<(String, Option<u16>)> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok((String::new(), None));
+    }
+    let (host, port) = if let Some(inner) = value.strip_prefix('[') {
+        let (host, tail) = inner.split_once(']').ok_or_else(|| anyhow::anyhow!("unclosed '[' in HostName"))?;
+        match tail {
+            "" => (host, None),
+            _ => match tail.strip_prefix(':') {
+                Some(port) => (host, Some(port)),
+                None => return Err(anyhow::anyhow!("unexpected '{}' after ']' in HostName", tail)),
+            },
+        }
+    } else {
+        match value.split_once(':') {
+            Some((host, port)) if !port.contains(':') => (host, Some(port)),
+            _ => (value, None),
+        }
+    };
+    if host.is_empty() {
+        return Err(anyhow::anyhow!("HostName is missing a host before the port"));
+    }
+    let port = match port.map(parse_port).transpose() {
+        Ok(None) => None,
+        Ok(Some(None)) => return Err(anyhow::anyhow!("HostName has a ':' but no port after it")),
+        Ok(Some(port)) => port,
+        Err(e) => return Err(anyhow::anyhow!("{} in HostName", e)),
+    };
+    Ok((host.to_string(), port))
+}
+
+/// Parse a Port value; blank means unset.
+pub fn parse_port(text: &str) -> Result<Option<u16>> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(None);
+    }
+    if !text.chars().all(|c| c.is_ascii_digit()) {
+        return Err(anyhow::anyhow!("port must be a number"));
+    }
+    match text.parse::<u16>() {
+        Ok(0) => Err(anyhow::anyhow!("port cannot be 0")),
+        Ok(port) => Ok(Some(port)),
+        Err(_) => Err(anyhow::anyhow!("port out of range 1-65535")),
+    }
+}
+
+pub fn default_ssh_config_path() -> PathBuf {
     home_dir()
         .map(|h| h.join(".ssh").join("config"))
         .unwrap_or_else(|| PathBuf::from("~/.ssh/config"))
 }
 
-fn parse_hosts_from_text(text: &str) -> Vec<SshHostEntry> {
+fn parse_hosts_from_text(text: &str) -> (Vec<SshHostEntry>, Vec<ParseWarning>) {
     let mut hosts = Vec::new();
+    let mut warnings = Vec::new();
+    let mut warn = |line: usize, message: String| warnings.push(ParseWarning { path: None, line: line + 1, message });
     let mut current: Option<SshHostEntry> = None;
-    for line in text.lines() {
+    for (number, line) in text.lines().enumerate() {
         let trimmed = line.trim();
+        if let Some(spec) = trimmed.strip_prefix(FORWARD_COMMENT) {
+            if let Some(entry) = current.as_mut() { entry.forward = Some(spec.trim().to_string()); }
+            continue;
+        }
+        if let Some(tags) = trimmed.strip_prefix(TAGS_COMMENT) {
+            if let Some(entry) = current.as_mut() { entry.tags.extend(parse_tags(tags)); }
+            continue;
+        }
+        if let Some(note) = trimmed.strip_prefix(NOTE_COMMENT) {
+            let note = note.trim();
+            if let Some(entry) = current.as_mut().filter(|_| !note.is_empty()) { entry.note = Some(note.to_string()); }
+            continue;
+        }
         if trimmed.is_empty() || trimmed.starts_with('#') { continue; }
-        if let Some(rest) = trimmed.strip_prefix("Host ") {
+        if let Some(pattern) = host_line_pattern(trimmed) {
+            if let Some(entry) = current.take() { hosts.push(entry); }
+            current = Some(SshHostEntry { pattern, ..Default::default() });
+            continue;
+        }
+        // A Match block's options belong to no host; `parse_matches_from_text` lists them
+        if match_line_criteria(trimmed).is_some() {
             if let Some(entry) = current.take() { hosts.push(entry); }
-            let pattern = rest.trim().to_string();
-            current = Some(SshHostEntry { pattern, hostname: None, user: None, port: None, other: vec![] });
             continue;
         }
         if let Some(entry) = current.as_mut() {
-            let mut parts = trimmed.split_whitespace();
-            if let Some(key) = parts.next() {
-                let value = parts.collect::<Vec<_>>().join(" ");
-                let key_lower = key.to_lowercase();
-                match key_lower.as_str() {
-                    "hostname" => entry.hostname = Some(value),
-                    "user" => entry.user = Some(value),
-                    "port" => entry.port = value.parse::<u16>().ok(),
-                    _ => entry.other.push((key.to_string(), value)),
-                }
+            let (key, value) = split_directive(trimmed);
+            if value.is_empty() {
+                warn(number, format!("{} has no value", key));
+            }
+            if let Err(e) = entry.set_option(key, value.to_string()) {
+                warn(number, e.to_string());
             }
         }
     }
     if let Some(entry) = current.take() { hosts.push(entry); }
-    hosts
+    (hosts, warnings)
+}
+
+/// The `Match` blocks in `text`, in order.
+fn parse_matches_from_text(text: &str) -> Vec<MatchEntry> {
+    let mut matches = Vec::new();
+    let mut current: Option<MatchEntry> = None;
+    for (number, line) in text.lines().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') { continue; }
+        if let Some(criteria) = match_line_criteria(trimmed) {
+            matches.extend(current.take());
+            current = Some(MatchEntry { criteria, line: number + 1, ..Default::default() });
+        } else if host_line_pattern(trimmed).is_some() {
+            matches.extend(current.take());
+        } else if let Some(entry) = current.as_mut() {
+            let (key, value) = split_directive(trimmed);
+            entry.options.push((key.to_string(), value.to_string()));
+        }
+    }
+    matches.extend(current);
+    matches
+}
+
+fn read_if_exists(path: &Path) -> Result<String> {
+    let mut text = String::new();
+    if path.exists() {
+        std::fs::File::open(path)?.read_to_string(&mut text)?;
+    }
+    Ok(text)
+}
+
+/// `spec` with a leading `~/` replaced by the home directory.
+pub fn expand_tilde(spec: &str) -> PathBuf {
+    match spec.strip_prefix("~/") {
+        Some(rest) => home_dir().map(|h| h.join(rest)).unwrap_or_else(|| PathBuf::from(spec)),
+        None => PathBuf::from(spec),
+    }
+}
+
+/// Follow `Include` lines in `text`, appending each newly seen file (depth-first,
+/// in directive order) to `out`. Globs and `~` are expanded; files already in
+/// `visited` are skipped so include cycles terminate.
+fn expand_includes(
+    text: &str,
+    base: &Path,
+    depth: usize,
+    visited: &mut HashSet<PathBuf>,
+    out: &mut Vec<IncludedFile>,
+    report: &mut dyn FnMut(&Path),
+) -> Result<()> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(anyhow::anyhow!("Include nested more than {} levels deep", MAX_INCLUDE_DEPTH));
+    }
+    for line in text.lines() {
+        let mut parts = line.split_whitespace();
+        if !parts.next().is_some_and(|k| k.eq_ignore_ascii_case("include")) {
+            continue;
+        }
+        for spec in parts {
+            let expanded = expand_tilde(spec);
+            let full = if expanded.is_absolute() { expanded } else { base.join(expanded) };
+            let Ok(matches) = glob::glob(&full.to_string_lossy()) else { continue };
+            for path in matches.flatten().filter(|p| p.is_file()) {
+                let key = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+                if !visited.insert(key) {
+                    continue;
+                }
+                report(&path);
+                let text = read_if_exists(&path)?;
+                out.push(IncludedFile { path, text: text.clone() });
+                expand_includes(&text, base, depth + 1, visited, out, report)?;
+            }
+        }
+    }
+    Ok(())
 }
 
-fn write_file_atomic(path: &PathBuf, content: &str) -> Result<()> {
+/// Replace `path` with `content` through a temporary file and a rename, so a
+/// crash mid-write never leaves it truncated.
+pub fn write_file_atomic(path: &Path, content: &str) -> Result<()> {
     // Create parent directory if it doesn't exist
     if let Some(parent) = path.parent() { 
         fs::create_dir_all(parent)?; 
@@ -235,3 +1369,661 @@ fn write_file_atomic(path: &PathBuf, content: &str) -> Result<()> {
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host(port: Option<u16>) -> SshHostEntry {
+        SshHostEntry { pattern: "web".into(), hostname: Some("web.example.com".into()), port, ..Default::default() }
+    }
+
+    /// The first listed host whose `Host` value is `pattern`.
+    fn listed(cfg: &SshConfigFile, pattern: &str) -> SshHostEntry {
+        cfg.list_hosts().into_iter().find(|h| h.pattern == pattern).unwrap()
+    }
+
+    #[test]
+    fn display_port_only_when_set() {
+        assert_eq!(host(None).display_port(), None);
+        assert_eq!(host(Some(22)).display_port().as_deref(), Some("22"));
+        assert_eq!(host(Some(2222)).display_port().as_deref(), Some("2222"));
+    }
+
+    #[test]
+    fn launch_args_never_add_default_port() {
+        assert_eq!(host(None).ssh_args(false, None), vec!["web"]);
+        assert_eq!(host(Some(22)).ssh_args(false, None), vec!["web"]);
+        assert_eq!(host(Some(2222)).ssh_args(false, None), vec!["-p", "2222", "web"]);
+    }
+
+    #[test]
+    fn copy_args_add_explicit_default_port_only_when_verbose() {
+        assert_eq!(host(None).ssh_args(true, None), vec!["web"]);
+        assert_eq!(host(Some(22)).ssh_args(true, None), vec!["-p", "22", "web"]);
+        assert_eq!(host(Some(2222)).ssh_args(true, None), vec!["-p", "2222", "web"]);
+    }
+
+    #[test]
+    fn ssh_args_name_a_config_other_than_the_default() {
+        let host = SshHostEntry { pattern: "web".into(), port: Some(2222), ..Default::default() };
+        let config = Path::new("/home/me/.ssh/work config");
+        assert_eq!(host.ssh_args(false, Some(config)), vec!["-F", "/home/me/.ssh/work config", "-p", "2222", "web"]);
+        assert_eq!(host.ssh_command(false, Some(config)), "ssh -F '/home/me/.ssh/work config' -p 2222 web");
+    }
+
+    #[test]
+    fn upsert_replaces_existing_block_in_place() {
+        let mut cfg = SshConfigFile::in_memory("Host a\n    HostName a.com\n\nHost b\n    HostName b.com\n");
+        cfg.upsert_host(None, &SshHostEntry { pattern: "a".into(), hostname: Some("new.com".into()), ..Default::default() }).unwrap();
+        assert_eq!(cfg.text, "Host a\n    HostName new.com\n\nHost b\n    HostName b.com\n");
+    }
+
+    #[test]
+    fn exact_upsert_leaves_blocks_that_only_share_a_wildcard() {
+        let mut cfg = SshConfigFile::in_memory("Host web *
+    User u
+
+Host *
+    ServerAliveInterval 30
+");
+        let global = cfg.list_hosts().into_iter().find(|h| h.pattern == "*").unwrap();
+        let entry = global.with_options(vec![("ServerAliveInterval".into(), "60".into())]).unwrap();
+        cfg.upsert_exact_host(None, &entry).unwrap();
+        assert_eq!(cfg.text, "Host web *
+    User u
+
+Host *
+    ServerAliveInterval 60
+");
+    }
+
+    #[test]
+    fn exact_delete_leaves_blocks_that_only_share_a_wildcard() {
+        let mut cfg = SshConfigFile::in_memory("Host * !bastion
+    User u
+
+Host *
+    ServerAliveInterval 30
+");
+        let global = cfg.list_hosts().into_iter().find(|h| h.pattern == "*").unwrap();
+        cfg.delete_exact_host(&global).unwrap();
+        assert_eq!(cfg.text, "Host * !bastion
+    User u
+");
+    }
+
+    #[test]
+    fn equals_sign_directives_parse_and_survive_an_edit() {
+        let original = "Host web\n    HostName=web.example.com\n    User = root\n";
+        let mut cfg = SshConfigFile::in_memory(original);
+        let mut entry = cfg.list_hosts()[0].clone();
+        assert_eq!(entry.hostname.as_deref(), Some("web.example.com"));
+        assert_eq!(entry.user.as_deref(), Some("root"));
+        entry.port = Some(2222);
+        cfg.upsert_host(None, &entry).unwrap();
+        assert_eq!(cfg.text, "Host web\n    HostName=web.example.com\n    User = root\n    Port 2222\n");
+    }
+
+    #[test]
+    fn upsert_keeps_block_comments_and_blank_lines() {
+        let original = "# prod bastion - do not delete\nHost a\n  # uses jump host\n  HostName a.com\n\n  Port 22\n  ProxyJump   bastion\n\nHost b\n";
+        let mut cfg = SshConfigFile::in_memory(original);
+        let mut entry = cfg.list_hosts()[0].clone();
+        entry.port = Some(2222);
+        cfg.upsert_host(None, &entry).unwrap();
+        assert_eq!(cfg.text, original.replace("Port 22\n", "Port 2222\n"));
+    }
+
+    #[test]
+    fn upsert_adds_and_removes_directives_within_block() {
+        let mut cfg = SshConfigFile::in_memory("Host a\n\tUser x\n\t# keep me\n\nHost b\n");
+        let entry = SshHostEntry { pattern: "a".into(), hostname: Some("a.com".into()), ..Default::default() };
+        cfg.upsert_host(None, &entry).unwrap();
+        assert_eq!(cfg.text, "Host a\n\t# keep me\n\tHostName a.com\n\nHost b\n");
+    }
+
+    #[test]
+    fn clearing_other_options_leaves_clean_block() {
+        let mut cfg = SshConfigFile::in_memory("Host a\n    HostName a.com\n    Compression yes\n    ForwardAgent yes\n\nHost z\n");
+        let mut entry = cfg.list_hosts()[0].clone();
+        entry.other.clear();
+        cfg.upsert_host(None, &entry).unwrap();
+        assert_eq!(cfg.text, "Host a\n    HostName a.com\n\nHost z\n");
+    }
+
+    #[test]
+    fn host_keyword_matches_any_case_and_spacing() {
+        for header in ["Host   web-prod", "host web-prod", "HOST\tweb-prod", "\tHost web-prod  ", "Host=web-prod", "Host = web-prod"] {
+            let original = format!("{}\n    User x\n\nHost db\n", header);
+            let mut cfg = SshConfigFile::in_memory(&original);
+            assert_eq!(cfg.list_hosts()[0].pattern, "web-prod", "{:?}", header);
+            let entry = SshHostEntry { pattern: "web-prod".into(), user: Some("y".into()), ..Default::default() };
+            cfg.upsert_host(None, &entry).unwrap();
+            assert_eq!(cfg.text, original.replace("User x", "User y"), "{:?}", header);
+            cfg.delete_exact_host(&listed(&cfg, "web-prod")).unwrap();
+            assert_eq!(cfg.text, "Host db\n", "{:?}", header);
+        }
+    }
+
+    #[test]
+    fn multi_alias_patterns_compare_with_collapsed_whitespace() {
+        let original = "Host  web1 \t w1\n    User x\n\nHost web1 w1\n";
+        let cfg = SshConfigFile::in_memory(original);
+        assert_eq!(cfg.list_hosts()[0].pattern, "web1 w1");
+        assert_eq!(cfg.validate_config().len(), 1);
+        let mut cfg = SshConfigFile::in_memory(original);
+        let entry = SshHostEntry { pattern: "web1 w1".into(), user: Some("y".into()), ..Default::default() };
+        cfg.upsert_host(None, &entry).unwrap();
+        assert_eq!(cfg.text, original.replace("User x", "User y"));
+    }
+
+    #[test]
+    fn a_block_sharing_one_alias_survives_a_delete_and_an_edit() {
+        let original = "Host w1 other\n    User o\n\nHost web1 w1\n    User x\n";
+        let mut cfg = SshConfigFile::in_memory(original);
+        let mut entry = listed(&cfg, "web1 w1");
+        entry.user = Some("y".into());
+        cfg.upsert_exact_host(Some("web1 w1"), &entry).unwrap();
+        assert_eq!(cfg.text, "Host w1 other\n    User o\n\nHost web1 w1\n    User y\n");
+        cfg.delete_exact_host(&listed(&cfg, "web1 w1")).unwrap();
+        assert_eq!(cfg.text, "Host w1 other\n    User o\n");
+
+        // A value of several aliases only names its own block, even for upsert_host
+        let mut cfg = SshConfigFile::in_memory(original);
+        cfg.upsert_host(None, &entry).unwrap();
+        assert_eq!(cfg.text, "Host w1 other\n    User o\n\nHost web1 w1\n    User y\n");
+    }
+
+    #[test]
+    fn any_alias_locates_multi_pattern_block() {
+        let mut cfg = SshConfigFile::in_memory("Host web1 web1.internal w1\n    User x\n\nHost db\n");
+        let entry = SshHostEntry { pattern: "web1 web1.internal w1".into(), user: Some("y".into()), ..Default::default() };
+        cfg.upsert_host(None, &entry).unwrap();
+        assert_eq!(cfg.text, "Host web1 web1.internal w1\n    User y\n\nHost db\n");
+        assert_eq!(cfg.list_hosts()[0].primary_alias(), "web1");
+        cfg.delete_exact_host(&listed(&cfg, "web1 web1.internal w1")).unwrap();
+        assert_eq!(cfg.text, "Host db\n");
+    }
+
+    #[test]
+    fn upsert_appends_new_block() {
+        let mut cfg = SshConfigFile::in_memory("Host a\n    HostName a.com\n");
+        cfg.upsert_host(None, &SshHostEntry { pattern: "b".into(), port: Some(2222), ..Default::default() }).unwrap();
+        assert_eq!(cfg.text, "Host a\n    HostName a.com\n\nHost b\n    Port 2222\n");
+        assert_eq!(cfg.list_hosts().len(), 2);
+    }
+
+    #[test]
+    fn duplicate_blocks_are_reported_and_merged_into_the_first() {
+        let mut cfg = SshConfigFile::in_memory("Host staging\n    User a\n\nHost b\n\nHost staging\n    User c\n");
+        assert_eq!(cfg.validate_config(), vec!["'staging' has 2 Host blocks; edits change the first".to_string()]);
+        assert_eq!(cfg.merge_duplicates().unwrap(), 1);
+        assert_eq!(cfg.text, "Host staging\n    User a\n\nHost b\n");
+        assert!(cfg.validate_config().is_empty());
+        assert_eq!(cfg.merge_duplicates().unwrap(), 0);
+    }
+
+    #[test]
+    fn repeated_edits_keep_block_spacing_stable() {
+        let mut cfg = SshConfigFile::in_memory("Host a\n    User x\n\nHost b\n    User y\n");
+        cfg.upsert_host(None, &SshHostEntry { pattern: "c".into(), user: Some("z".into()), ..Default::default() }).unwrap();
+        let expected = "Host a\n    User x\n\nHost b\n    User y\n\nHost c\n    User z\n";
+        assert_eq!(cfg.text, expected);
+        for pattern in ["a", "b", "c"] {
+            for _ in 0..3 {
+                let mut entry = cfg.list_hosts().into_iter().find(|h| h.pattern == pattern).unwrap();
+                entry.port = Some(2222);
+                cfg.upsert_host(None, &entry).unwrap();
+                entry.port = None;
+                cfg.upsert_host(None, &entry).unwrap();
+            }
+        }
+        assert_eq!(cfg.text, expected);
+        cfg.delete_exact_host(&listed(&cfg, "c")).unwrap();
+        assert_eq!(cfg.text, "Host a\n    User x\n\nHost b\n    User y\n");
+    }
+
+    #[test]
+    fn add_hosts_appends_blocks_in_one_undo_step() {
+        let mut cfg = SshConfigFile::in_memory("Host a\n    User x\n\n");
+        let new = |name: &str, port| SshHostEntry { pattern: name.into(), hostname: Some(name.into()), port, ..Default::default() };
+        assert_eq!(cfg.add_hosts(&[new("b.com", None), new("c.com", Some(2222))]).unwrap(), 2);
+        assert_eq!(
+            cfg.text,
+            "Host a\n    User x\n\nHost b.com\n    HostName b.com\n\nHost c.com\n    HostName c.com\n    Port 2222\n"
+        );
+        assert_eq!(cfg.undo().unwrap().as_deref(), Some("import of 2 hosts"));
+        assert_eq!(cfg.text, "Host a\n    User x\n\n");
+    }
+
+    #[test]
+    fn delete_removes_only_matching_block() {
+        let mut cfg = SshConfigFile::in_memory("# top\nHost a\n    User x\n\nHost b\n    User y\n");
+        cfg.delete_exact_host(&listed(&cfg, "a")).unwrap();
+        assert_eq!(cfg.text, "# top\nHost b\n    User y\n");
+    }
+
+    #[test]
+    fn batch_delete_writes_once_and_undoes_together() {
+        let original = "Host a\n    User x\n\nHost b\n    User y\n\nHost c\n    User z\n";
+        let mut cfg = SshConfigFile::in_memory(original);
+        cfg.delete_exact_hosts(&[listed(&cfg, "a"), listed(&cfg, "c")]).unwrap();
+        assert_eq!(cfg.text, "Host b\n    User y\n");
+        assert_eq!(cfg.undo().unwrap().as_deref(), Some("delete of 2 hosts"));
+        assert_eq!(cfg.text, original);
+    }
+
+    #[test]
+    fn undo_restores_text_before_each_change() {
+        let original = "Host a\n    User x\n\nHost b\n";
+        let mut cfg = SshConfigFile::in_memory(original);
+        cfg.upsert_host(None, &SshHostEntry { pattern: "a".into(), user: Some("y".into()), ..Default::default() }).unwrap();
+        cfg.delete_exact_host(&listed(&cfg, "b")).unwrap();
+        assert_eq!(cfg.undo().unwrap().as_deref(), Some("delete of 'b'"));
+        assert_eq!(cfg.text, "Host a\n    User y\n\nHost b\n");
+        assert_eq!(cfg.undo().unwrap().as_deref(), Some("edit of 'a'"));
+        assert_eq!(cfg.text, original);
+        assert_eq!(cfg.undo().unwrap(), None);
+    }
+
+    #[test]
+    fn proxy_jump_round_trips_and_flags_unknown_hops() {
+        let mut cfg = SshConfigFile::in_memory("Host bastion\n    HostName b.com\n\nHost web\n    ProxyJump bastion\n");
+        let hosts = cfg.list_hosts();
+        assert_eq!(hosts[1].proxy_jump.as_deref(), Some("bastion"));
+        assert!(hosts[1].other.is_empty());
+        assert!(hosts[1].unknown_jump_hosts(&hosts).is_empty());
+
+        let mut entry = hosts[1].clone();
+        entry.proxy_jump = Some("me@bastion:2222,[::1]:22,gw.example.com".into());
+        assert_eq!(entry.unknown_jump_hosts(&hosts), vec!["::1", "gw.example.com"]);
+        cfg.upsert_host(None, &entry).unwrap();
+        assert_eq!(cfg.text, "Host bastion\n    HostName b.com\n\nHost web\n    ProxyJump me@bastion:2222,[::1]:22,gw.example.com\n");
+    }
+
+    #[test]
+    fn tags_comment_round_trips_through_upsert() {
+        let mut cfg = SshConfigFile::in_memory("Host db\n    # tags: prod, #db\n    User pg\n");
+        let mut entry = cfg.list_hosts().remove(0);
+        assert_eq!(entry.tags, vec!["prod", "db"]);
+        assert!(entry.has_tag("PROD") && entry.other.is_empty());
+
+        entry.user = Some("admin".into());
+        cfg.upsert_host(None, &entry).unwrap();
+        assert_eq!(cfg.text, "Host db\n    # tags: prod db\n    User admin\n");
+        entry.tags.clear();
+        cfg.upsert_host(None, &entry).unwrap();
+        assert_eq!(cfg.text, "Host db\n    User admin\n");
+        entry.tags = vec!["staging".into()];
+        cfg.upsert_host(None, &entry).unwrap();
+        assert_eq!(cfg.text, "Host db\n    User admin\n    # tags: staging\n");
+    }
+
+    #[test]
+    fn rename_changes_only_the_host_line() {
+        let text = "Host a\n    User x\n\n  Host  old\n    # tags: prod\n    Port 2222\n\nHost c\n";
+        let mut cfg = SshConfigFile::in_memory(text);
+        let entry = cfg.list_hosts().remove(1);
+        assert_eq!(entry.pattern, "old");
+        cfg.rename_host(&entry, "new").unwrap();
+        assert_eq!(cfg.text, text.replace("  Host  old", "  Host new"));
+        assert!(cfg.rename_host(&entry, "other").is_err());
+    }
+
+    #[test]
+    fn edit_that_renames_keeps_the_block_in_place() {
+        let mut cfg = SshConfigFile::in_memory("Host a\n\nHost web-1\n    User x\n\nHost c\n");
+        let mut entry = cfg.list_hosts().remove(1);
+        entry.pattern = "web-01".into();
+        entry.user = Some("y".into());
+        cfg.upsert_host(Some("web-1"), &entry).unwrap();
+        assert_eq!(cfg.text, "Host a\n\nHost web-01\n    User y\n\nHost c\n");
+        let patterns: Vec<String> = cfg.list_hosts().into_iter().map(|h| h.pattern).collect();
+        assert_eq!(patterns, vec!["a", "web-01", "c"]);
+    }
+
+    #[test]
+    fn yes_no_options_round_trip_through_upsert() {
+        let mut cfg = SshConfigFile::in_memory("Host a\n    ForwardAgent yes\n    User x\n");
+        let mut entry = cfg.list_hosts().remove(0);
+        entry.other = vec![("AddKeysToAgent".into(), "no".into()), ("Compression".into(), "yes".into())];
+        cfg.upsert_host(None, &entry).unwrap();
+        assert_eq!(cfg.text, "Host a\n    User x\n    AddKeysToAgent no\n    Compression yes\n");
+        assert_eq!(cfg.list_hosts()[0].other, entry.other);
+    }
+
+    #[test]
+    fn canonical_order_sorts_directives_around_comments() {
+        let mut cfg = SshConfigFile::in_memory(
+            "Host web\n    ServerAliveInterval 30\n    # tags: prod\n    User deploy\n    IdentityFile ~/.ssh/a\n    Compression yes\n    HostName web.example.com\n    IdentityFile ~/.ssh/b\n",
+        );
+        cfg.option_order = OptionOrder::Canonical;
+        let mut entry = cfg.list_hosts().remove(0);
+        entry.port = Some(2222);
+        cfg.upsert_host(None, &entry).unwrap();
+        assert_eq!(
+            cfg.text,
+            "Host web\n    HostName web.example.com\n    # tags: prod\n    User deploy\n    Port 2222\n    IdentityFile ~/.ssh/a\n    IdentityFile ~/.ssh/b\n    Compression yes\n    ServerAliveInterval 30\n"
+        );
+
+        cfg.option_order = OptionOrder::Alphabetical;
+        let new = SshHostEntry { pattern: "db".into(), user: Some("pg".into()), hostname: Some("db1".into()), ..Default::default() };
+        cfg.upsert_host(None, &new).unwrap();
+        assert!(cfg.text.ends_with("\nHost db\n    HostName db1\n    User pg\n"));
+    }
+
+    #[test]
+    fn upsert_refuses_options_that_would_not_read_back() {
+        let mut cfg = SshConfigFile::in_memory("Host a\n    User x\n");
+        let before = cfg.text.clone();
+        let entry = cfg.list_hosts().remove(0);
+        let with = |key: &str, value: &str| SshHostEntry { other: vec![(key.into(), value.into())], ..entry.clone() };
+
+        let err = cfg.upsert_host(None, &with("Compression", "  ")).unwrap_err();
+        assert_eq!(err.to_string(), "Compression has no value");
+        let err = cfg.upsert_host(None, &with("Local Forward", "8080 db:80")).unwrap_err();
+        assert!(err.to_string().contains("cannot contain spaces"), "{}", err);
+        let err = cfg.upsert_host(None, &with("", "yes")).unwrap_err();
+        assert!(err.to_string().contains("has no name"), "{}", err);
+        let err = cfg.upsert_host(None, &with("ProxyCommand", "nc %h\nHost evil")).unwrap_err();
+        assert_eq!(err.to_string(), "ProxyCommand cannot contain newlines");
+        // A comma splits a tag in two when read back
+        let tagged = SshHostEntry { tags: vec!["a,b".into()], ..entry.clone() };
+        let err = cfg.upsert_host(None, &tagged).unwrap_err();
+        assert!(err.to_string().contains("would not read back"), "{}", err);
+        assert_eq!(cfg.text, before);
+
+        assert!(SshConfigFile::validate_write("Host a\n    user x\n", [&entry]).is_ok());
+    }
+
+    #[test]
+    fn match_blocks_are_listed_apart_and_survive_edits() {
+        let text = "Host a\n    User x\nMatch host *.internal user deploy\n    IdentityFile ~/.ssh/deploy\n    ForwardAgent no\nHost b\n";
+        let mut cfg = SshConfigFile::in_memory(text);
+        let hosts = cfg.list_hosts();
+        assert_eq!(hosts.len(), 2);
+        assert!(hosts[0].other.is_empty() && hosts[0].identity_file.is_none());
+        let matches = cfg.list_matches();
+        assert_eq!(matches.len(), 1);
+        assert_eq!((matches[0].criteria.as_str(), matches[0].line), ("host *.internal user deploy", 3));
+        assert_eq!(matches[0].options[1], ("ForwardAgent".to_string(), "no".to_string()));
+
+        let mut entry = hosts[0].clone();
+        entry.port = Some(2222);
+        cfg.upsert_host(None, &entry).unwrap();
+        assert_eq!(cfg.text, text.replace("User x\n", "User x\n    Port 2222\n"));
+        cfg.delete_exact_host(&listed(&cfg, "a")).unwrap();
+        assert!(cfg.text.starts_with("Match host *.internal user deploy\n    IdentityFile ~/.ssh/deploy\n"));
+    }
+
+    #[test]
+    fn repeated_options_survive_an_edit_in_order() {
+        let text = "Host tunnel\n    LocalForward 8080 db:80\n    User x\n    LocalForward 5432 db:5432\n    IdentityFile ~/.ssh/a\n    IdentityFile ~/.ssh/b\n";
+        let mut cfg = SshConfigFile::in_memory(text);
+        let mut entry = cfg.list_hosts().remove(0);
+        let forwards: Vec<&str> = entry.other.iter().filter(|(k, _)| k == "LocalForward").map(|(_, v)| v.as_str()).collect();
+        assert_eq!(forwards, ["8080 db:80", "5432 db:5432"]);
+
+        entry.user = Some("y".into());
+        cfg.upsert_host(None, &entry).unwrap();
+        assert_eq!(cfg.text, text.replace("User x", "User y"));
+
+        // Dropping the first of two leaves the second where it was
+        entry.other.retain(|(_, v)| v != "8080 db:80");
+        cfg.upsert_host(None, &entry).unwrap();
+        assert_eq!(cfg.text, "Host tunnel\n    User y\n    LocalForward 5432 db:5432\n    IdentityFile ~/.ssh/a\n    IdentityFile ~/.ssh/b\n");
+
+        entry.other.push(("LocalForward".into(), "9000 cache:6379".into()));
+        cfg.upsert_host(None, &entry).unwrap();
+        assert!(cfg.text.ends_with("IdentityFile ~/.ssh/b\n    LocalForward 9000 cache:6379\n"));
+        assert_eq!(cfg.list_hosts()[0].other, entry.other);
+    }
+
+    #[test]
+    fn forwards_normalize_to_config_syntax() {
+        assert_eq!(normalize_forward("LocalForward", "8080 db:80").unwrap(), "8080 db:80");
+        assert_eq!(normalize_forward("LocalForward", "127.0.0.1:8080:db:80").unwrap(), "127.0.0.1:8080 db:80");
+        assert_eq!(normalize_forward("LocalForward", "8080:[::1]:80").unwrap(), "8080 [::1]:80");
+        assert_eq!(normalize_forward("RemoteForward", "9000 localhost:3000").unwrap(), "9000 localhost:3000");
+        assert_eq!(normalize_forward("RemoteForward", "1080").unwrap(), "1080");
+        assert_eq!(normalize_forward("DynamicForward", "localhost:1080").unwrap(), "localhost:1080");
+        assert_eq!(normalize_forward("LocalForward", "/tmp/sock /run/db.sock").unwrap(), "/tmp/sock /run/db.sock");
+        assert!(normalize_forward("LocalForward", "8080").is_err());
+        assert!(normalize_forward("LocalForward", "http db:80").is_err());
+        assert!(normalize_forward("LocalForward", "8080 :80").is_err());
+        assert!(normalize_forward("DynamicForward", "1080 db:80").is_err());
+    }
+
+    #[test]
+    fn with_options_replaces_options_and_keeps_comments() {
+        let cfg = SshConfigFile::in_memory("Host *\n    # note: defaults\n    User x\n    Compression yes\n");
+        let block = cfg.list_hosts().remove(0);
+        let options = vec![("Port".to_string(), "2222".to_string()), ("ServerAliveInterval".to_string(), "30".to_string())];
+        let entry = block.with_options(options.clone()).unwrap();
+        assert_eq!((entry.user, entry.port, entry.note.as_deref()), (None, Some(2222), Some("defaults")));
+        assert_eq!(entry.other, vec![("ServerAliveInterval".to_string(), "30".to_string())]);
+        assert!(block.with_options(vec![("Port".to_string(), "x".to_string())]).is_err());
+    }
+
+    #[test]
+    fn note_comment_round_trips_through_upsert() {
+        let mut cfg = SshConfigFile::in_memory("Host db\n    # note:  rebooted weekly, Sundays\n    User pg\n");
+        let mut entry = cfg.list_hosts().remove(0);
+        assert_eq!(entry.note.as_deref(), Some("rebooted weekly, Sundays"));
+        assert!(entry.other.is_empty());
+
+        entry.note = Some("owned by payments".into());
+        cfg.upsert_host(None, &entry).unwrap();
+        assert_eq!(cfg.text, "Host db\n    # note: owned by payments\n    User pg\n");
+        entry.note = None;
+        cfg.upsert_host(None, &entry).unwrap();
+        assert_eq!(cfg.text, "Host db\n    User pg\n");
+
+        entry.pattern = "cache".into();
+        entry.note = Some("new".into());
+        cfg.upsert_host(None, &entry).unwrap();
+        assert!(cfg.text.ends_with("Host cache\n    User pg\n    # note: new\n"));
+    }
+
+    #[test]
+    fn wide_search_includes_jump_host_and_other_options() {
+        let entry = SshHostEntry {
+            pattern: "web".into(),
+            proxy_jump: Some("bastion".into()),
+            other: vec![("SetEnv".into(), "TEAM=payments".into())],
+            ..Default::default()
+        };
+        assert_eq!(entry.match_score("bastion", false), None);
+        assert!(entry.match_score("bastion", true).is_some());
+        assert!(entry.match_score("payments", true).is_some());
+        assert!(entry.match_score("web", false).is_some());
+    }
+
+    #[test]
+    fn listed_hosts_carry_a_lowercased_search_key() {
+        let cfg = SshConfigFile::in_memory("Host Web-Prod\n    HostName DB.Example.com\n    ProxyJump Bastion\n");
+        let entry = cfg.list_hosts().remove(0);
+        assert!(entry.search_key.is_some());
+        assert!(entry.match_score("WEBP", false).is_some());
+        assert!(entry.match_score("db.ex", false).is_some());
+        assert!(entry.match_score("bastion", true).is_some());
+        assert_eq!(entry.match_score("web", false), SshHostEntry { search_key: None, ..entry.clone() }.match_score("web", false));
+    }
+
+    #[test]
+    fn proxy_command_spacing_survives_an_edit() {
+        let original = "Host legacy\n    ProxyCommand ssh -q -W %h:%p  gw  -o 'SetEnv=A=b  c'\n    User old\n";
+        let mut cfg = SshConfigFile::in_memory(original);
+        let mut entry = cfg.list_hosts().remove(0);
+        assert_eq!(
+            entry.other,
+            vec![("ProxyCommand".to_string(), "ssh -q -W %h:%p  gw  -o 'SetEnv=A=b  c'".to_string())]
+        );
+        entry.user = Some("new".into());
+        cfg.upsert_host(None, &entry).unwrap();
+        assert_eq!(cfg.text, original.replace("User old", "User new"));
+        // Saving again without changes leaves the file as it is
+        let entry = cfg.list_hosts().remove(0);
+        cfg.upsert_host(None, &entry).unwrap();
+        assert_eq!(cfg.text, original.replace("User old", "User new"));
+    }
+
+    #[test]
+    fn malformed_lines_are_reported_and_skipped() {
+        let cfg = SshConfigFile::in_memory("Host web\n  HostName web.example\n  Port notanumber\n\nHost db\n  User\n");
+        let warnings: Vec<String> = cfg.parse_warnings().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            warnings,
+            vec!["line 3: invalid Port value 'notanumber' (port must be a number)", "line 6: User has no value"]
+        );
+        let hosts = cfg.list_hosts();
+        assert_eq!(hosts.len(), 2);
+        assert_eq!(hosts[0].hostname.as_deref(), Some("web.example"));
+        assert_eq!(hosts[0].port, None);
+    }
+
+    #[test]
+    fn wildcard_patterns_are_detected() {
+        let host = |pattern: &str| SshHostEntry { pattern: pattern.into(), ..Default::default() };
+        assert!(host("*").is_wildcard());
+        assert!(host("web-? db").is_wildcard());
+        assert!(host("* !bastion").is_wildcard());
+        assert!(!host("web-prod web").is_wildcard());
+        assert_eq!(host("*.internal !bastion.internal  !db").negated_aliases().collect::<Vec<_>>(), ["bastion.internal", "db"]);
+        assert_eq!(host("web-prod web").negated_aliases().count(), 0);
+    }
+
+    #[test]
+    fn next_free_alias_counts_up_past_taken_names() {
+        let hosts: Vec<SshHostEntry> = ["prod-web-2", "prod-web-3 pw3", "web09", "bastion"]
+            .iter()
+            .map(|p| SshHostEntry { pattern: p.to_string(), ..Default::default() })
+            .collect();
+        assert_eq!(next_free_alias("prod-web-2", &hosts), "prod-web-4");
+        assert_eq!(next_free_alias("web09", &hosts), "web10");
+        assert_eq!(next_free_alias("bastion", &hosts), "bastion-2");
+    }
+
+    #[test]
+    fn port_errors_say_what_is_wrong() {
+        let error = |text: &str| parse_port(text).unwrap_err().to_string();
+        assert_eq!(error("0"), "port cannot be 0");
+        assert_eq!(error("70000"), "port out of range 1-65535");
+        assert_eq!(error("abc"), "port must be a number");
+        assert_eq!(parse_port(" 22 ").unwrap(), Some(22));
+        assert_eq!(parse_port("").unwrap(), None);
+    }
+
+    #[test]
+    fn host_port_shorthand_splits_and_respects_ipv6() {
+        let split = |v: &str| split_host_port(v).ok();
+        assert_eq!(split("example.com:2222"), Some(("example.com".into(), Some(2222))));
+        assert_eq!(split("example.com"), Some(("example.com".into(), None)));
+        assert_eq!(split("  "), Some((String::new(), None)));
+        assert_eq!(split("[::1]:22"), Some(("::1".into(), Some(22))));
+        assert_eq!(split("[fe80::1]"), Some(("fe80::1".into(), None)));
+        assert_eq!(split("fe80::1"), Some(("fe80::1".into(), None)));
+        assert_eq!(split("example.com:0"), None);
+        assert_eq!(split("example.com:ssh"), None);
+        assert_eq!(split(":22"), None);
+        assert_eq!(split("[::1"), None);
+    }
+
+    #[test]
+    fn ipv6_hostnames_round_trip_and_join_with_brackets() {
+        let mut cfg = SshConfigFile::in_memory("Host lo\n    HostName ::1\n\nHost ll\n    HostName fe80::1%eth0\n");
+        let hosts = cfg.list_hosts();
+        assert_eq!(hosts[0].hostname.as_deref(), Some("::1"));
+        assert_eq!(hosts[1].connect_host(), "fe80::1%eth0");
+
+        let (hostname, port) = split_host_port("[2001:db8::1]:2222").unwrap();
+        let entry = SshHostEntry { pattern: "v6".into(), hostname: Some(hostname), port, ..Default::default() };
+        cfg.upsert_host(None, &entry).unwrap();
+        assert!(cfg.text.ends_with("Host v6\n    HostName 2001:db8::1\n    Port 2222\n"));
+        let saved = cfg.list_hosts().remove(2);
+        assert_eq!(saved.hostname.as_deref(), Some("2001:db8::1"));
+        assert_eq!(join_host_port(saved.connect_host(), saved.port), "[2001:db8::1]:2222");
+
+        cfg.delete_exact_host(&listed(&cfg, "lo")).unwrap();
+        assert_eq!(cfg.text, "Host ll\n    HostName fe80::1%eth0\n\nHost v6\n    HostName 2001:db8::1\n    Port 2222\n");
+
+        let bracketed = SshHostEntry { pattern: "b".into(), hostname: Some("[::1]".into()), ..Default::default() };
+        assert_eq!(bracketed.connect_host(), "::1");
+        assert_eq!(join_host_port("example.com", Some(22)), "example.com:22");
+        assert_eq!(join_host_port("fe80::1%eth0", None), "fe80::1%eth0");
+    }
+
+    #[test]
+    fn render_hosts_writes_a_standalone_fragment() {
+        let cfg = SshConfigFile::in_memory("Host a\n    User x\n    # tags: prod\n\nHost b\n    Port 2222\n    ForwardAgent yes\n");
+        let text = render_hosts(&cfg.list_hosts());
+        assert_eq!(text, "Host a\n    User x\n    # tags: prod\n\nHost b\n    Port 2222\n    ForwardAgent yes\n");
+        assert_eq!(SshConfigFile::in_memory(&text).list_hosts(), cfg.list_hosts());
+    }
+
+    #[test]
+    fn effective_port_defaults_to_22() {
+        assert_eq!(host(None).effective_port(), 22);
+        assert_eq!(host(Some(2222)).effective_port(), 2222);
+    }
+
+    /// An empty `.ssh` directory under the system temp dir, unique to this test run.
+    fn scratch_ssh_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ssh-picker-include-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join(".ssh")).unwrap();
+        dir.join(".ssh")
+    }
+
+    fn include_names(cfg: &SshConfigFile, dir: &Path) -> Vec<String> {
+        cfg.includes.iter().map(|inc| inc.path.strip_prefix(dir).unwrap().display().to_string()).collect()
+    }
+
+    #[test]
+    fn includes_expand_globs_relative_to_the_config_directory() {
+        let dir = scratch_ssh_dir("glob");
+        fs::create_dir(dir.join("config.d")).unwrap();
+        fs::write(dir.join("config"), "Include config.d/*.conf\nHost main\n").unwrap();
+        fs::write(dir.join("config.d/b.conf"), "Host b\n").unwrap();
+        fs::write(dir.join("config.d/a.conf"), "Host a\n").unwrap();
+        fs::write(dir.join("config.d/notes.txt"), "Host skipped\n").unwrap();
+        let mut reported = Vec::new();
+        let cfg = SshConfigFile::load_reporting(dir.join("config"), &mut |p| reported.push(p.to_path_buf())).unwrap();
+        assert_eq!(include_names(&cfg, &dir), ["config.d/a.conf", "config.d/b.conf"]);
+        assert_eq!(reported, [dir.join("config.d/a.conf"), dir.join("config.d/b.conf")]);
+        let names: Vec<String> = cfg.list_hosts().into_iter().map(|h| h.pattern).collect();
+        assert_eq!(names, ["main", "a", "b"]);
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn include_cycles_read_each_file_once() {
+        let dir = scratch_ssh_dir("cycle");
+        fs::write(dir.join("config"), "Include other\nHost main\n").unwrap();
+        fs::write(dir.join("other"), "Include config\nInclude other\nHost o\n").unwrap();
+        let cfg = SshConfigFile::load(dir.join("config")).unwrap();
+        assert_eq!(include_names(&cfg, &dir), ["other"]);
+        assert_eq!(cfg.list_hosts().len(), 2);
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn include_nesting_stops_at_the_depth_limit() {
+        let dir = scratch_ssh_dir("depth");
+        // `config` includes f1, which includes f2, and so on down to f{levels}
+        let chain = |levels: usize| {
+            for n in 0..=levels {
+                let name = if n == 0 { "config".to_string() } else { format!("f{}", n) };
+                let text = if n < levels { format!("Include f{}\n", n + 1) } else { String::new() };
+                fs::write(dir.join(name), text).unwrap();
+            }
+        };
+        chain(MAX_INCLUDE_DEPTH - 1);
+        assert_eq!(SshConfigFile::load(dir.join("config")).unwrap().includes.len(), MAX_INCLUDE_DEPTH - 1);
+        chain(MAX_INCLUDE_DEPTH);
+        let err = SshConfigFile::load(dir.join("config")).err().unwrap();
+        assert_eq!(err.to_string(), format!("Include nested more than {} levels deep", MAX_INCLUDE_DEPTH));
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+}
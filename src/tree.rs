@@ -0,0 +1,116 @@
+use crate::ssh_config::SshHostEntry;
+use std::collections::HashSet;
+
+/// One visible line of the host list.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListRow {
+    /// Hosts whose first alias starts with `prefix-`, shown as a collapsible header.
+    Group { prefix: String, depth: usize, count: usize, collapsed: bool },
+    /// A host, by index into `AppState.hosts`.
+    Host { index: usize, depth: usize },
+}
+
+/// Rows for the flat list: every host in `order`, unindented.
+pub fn flat(order: &[usize]) -> Vec<ListRow> {
+    order.iter().map(|&index| ListRow::Host { index, depth: 0 }).collect()
+}
+
+/// Rows for the tree view: hosts in `order` grouped by the `-`-separated
+/// segments of their first alias. A group needs at least two hosts, chains
+/// of single-child groups are merged into one (`prod-web`, not `prod` then
+/// `web`), and groups appear where their first host would. The children of
+/// prefixes in `collapsed` are left out.
+pub fn build(hosts: &[SshHostEntry], order: &[usize], collapsed: &HashSet<String>) -> Vec<ListRow> {
+    let segments: Vec<Vec<&str>> = hosts.iter().map(|h| h.primary_alias().split('-').collect()).collect();
+    let mut rows = Vec::new();
+    add_level(&segments, order, 0, 0, collapsed, &mut rows);
+    rows
+}
+
+/// Add rows for `members`, which share their first `shared` segments.
+fn add_level(
+    segments: &[Vec<&str>],
+    members: &[usize],
+    shared: usize,
+    depth: usize,
+    collapsed: &HashSet<String>,
+    rows: &mut Vec<ListRow>,
+) {
+    // Bucket by the next segment, in order of first appearance; a host with no
+    // more segments is its own bucket
+    let mut buckets: Vec<(Option<&str>, Vec<usize>)> = Vec::new();
+    for &index in members {
+        let next = segments[index].get(shared).copied();
+        match buckets.iter_mut().find(|(seg, _)| next.is_some() && *seg == next) {
+            Some((_, bucket)) => bucket.push(index),
+            None => buckets.push((next, vec![index])),
+        }
+    }
+    for (_, bucket) in buckets {
+        if let [index] = bucket[..] {
+            rows.push(ListRow::Host { index, depth });
+            continue;
+        }
+        // Extend the prefix while every member continues with the same segment
+        let first = &segments[bucket[0]];
+        let mut len = shared + 1;
+        while first.len() > len && bucket.iter().all(|&i| segments[i].len() > len && segments[i][len] == first[len]) {
+            len += 1;
+        }
+        let prefix = first[..len].join("-");
+        let is_collapsed = collapsed.contains(&prefix);
+        rows.push(ListRow::Group { prefix, depth, count: bucket.len(), collapsed: is_collapsed });
+        if !is_collapsed {
+            add_level(segments, &bucket, len, depth + 1, collapsed, rows);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hosts(names: &[&str]) -> Vec<SshHostEntry> {
+        names.iter().map(|n| SshHostEntry { pattern: n.to_string(), ..Default::default() }).collect()
+    }
+
+    fn labels(rows: &[ListRow], hosts: &[SshHostEntry]) -> Vec<String> {
+        rows.iter()
+            .map(|row| match row {
+                ListRow::Group { prefix, depth, count, collapsed } => {
+                    format!("{}{} {}{}", "  ".repeat(*depth), prefix, count, if *collapsed { "+" } else { "" })
+                }
+                ListRow::Host { index, depth } => format!("{}{}", "  ".repeat(*depth), hosts[*index].pattern),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn groups_by_shared_prefix_and_merges_single_child_chains() {
+        let hosts = hosts(&["prod-web-1", "bastion", "prod-web-2", "prod-db-1", "stage-web-1", "stage-web-2"]);
+        let order: Vec<usize> = (0..hosts.len()).collect();
+        let rows = build(&hosts, &order, &HashSet::new());
+        assert_eq!(
+            labels(&rows, &hosts),
+            vec![
+                "prod 3",
+                "  prod-web 2",
+                "    prod-web-1",
+                "    prod-web-2",
+                "  prod-db-1",
+                "bastion",
+                "stage-web 2",
+                "  stage-web-1",
+                "  stage-web-2",
+            ]
+        );
+    }
+
+    #[test]
+    fn collapsed_groups_hide_their_hosts() {
+        let hosts = hosts(&["prod-web-1", "prod-web-2", "prod-db-1"]);
+        let collapsed = HashSet::from(["prod-web".to_string()]);
+        let rows = build(&hosts, &[0, 1, 2], &collapsed);
+        assert_eq!(labels(&rows, &hosts), vec!["prod 3", "  prod-web 2+", "  prod-db-1"]);
+    }
+}
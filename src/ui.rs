@@ -1,17 +1,22 @@
-use crate::app::{AppState, ConfirmContext, Mode};
-use crate::ssh_config::SshHostEntry;
+use crate::tree::ListRow;
+use crate::app::{AppState, ConfirmContext, FormData, GlobalOptions, ListView, Mode, SettingsForm, StatusKind, FORM_BASIC_FIELDS, FORM_FORWARDS_START, FORM_TOGGLES, SETTINGS_ROWS};
+use crate::probe::ProbeResult;
+use crate::ssh_config::{join_host_port, SshHostEntry};
 use anyhow::Result;
-use crossterm::event::{self, Event as CEvent, KeyCode, KeyEvent, KeyModifiers};
-use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use crate::keymap::Keymap;
+use crossterm::event::{self, Event as CEvent, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEventKind};
+use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
 use ratatui::style::{Color, Modifier, Style};
 use ratatui::text::{Line, Span};
 use ratatui::widgets::{Block, Borders, Clear, List, ListItem, Paragraph, Wrap};
 use ratatui::Frame;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(Debug)]
 pub enum Event {
     Action(UiAction),
+    /// The terminal changed size; the next frame is laid out from scratch.
+    Resize,
     Tick,
 }
 
@@ -20,153 +25,1020 @@ pub enum UiAction {
     MoveUp,
     MoveDown,
     PageUp,
+    /// Select the first row; the key has to be pressed twice, like vim's `gg`.
+    JumpFirst,
+    JumpLast,
     PageDown,
     BeginFilter,
     InputChar(char),
+    /// Left mouse button pressed at this terminal cell.
+    Click { column: u16, row: u16 },
     BackspaceFilter,
     ClearFilter,
     EditSelected,
     NewHost,
     DeleteSelected,
+    MergeDuplicates,
+    Undo,
+    CopySelectedAs,
+    /// Write the marked (or all filtered) hosts to a file of the user's choice.
+    ExportHosts,
+    /// Prompt for a new name and rewrite only the host's `Host` line.
+    RenameSelected,
+    /// Open the add form prefilled from the selected host.
+    CloneSelected,
+    CopyCommand,
+    /// Copy the selected host's HostName (or its name, without one).
+    CopyHostname,
+    /// Resolve the selected host's HostName in the background and copy the address.
+    CopyAddress,
     LaunchSelected,
+    LaunchWithForward,
+    LaunchWithArgs,
+    /// Prompt for a command and run it on the selected host with ssh.
+    RunCommand,
+    LaunchSftp,
+    /// ssh with `-vvv`, keeping its log on screen until a key is pressed.
+    LaunchVerbose,
+    /// Switch between the flat list and hosts grouped by name prefix.
+    ToggleTree,
+    /// Show (or close) the options `ssh -G` resolves for the selected host.
+    ShowResolved,
+    /// Show (or close) the list of config lines the parser skipped.
+    ToggleDiagnostics,
+    /// List the config's `Match` blocks, which aren't hosts.
+    ShowMatchBlocks,
+    /// Edit the options of the `Host *` block, which apply to every host.
+    ShowGlobalOptions,
+    /// Open the settings screen.
+    ShowSettings,
+    /// Open the ssh config in `$VISUAL` / `$EDITOR` and reload it afterwards.
+    EditConfigFile,
+    /// Offer to add hosts from `~/.ssh/known_hosts` that the config lacks.
+    ImportKnownHosts,
+    /// Mark or unmark the selected host for a batch delete.
+    ToggleMark,
+    ProbeHosts,
+    /// Time a TCP connect to the selected host and report it in the footer.
+    TestConnection,
+    ToggleLatency,
+    ToggleSearchAll,
+    CycleSort,
+    TogglePreview,
+    ScrollPreviewDown,
+    ScrollPreviewUp,
+    ToggleHelp,
     FormNextField,
     FormPrevField,
     FormSubmit,
     FormCancel,
+    /// Enter in a confirmation: the same as answering `y`.
+    ConfirmAccept,
+    PromptSubmit,
+    PromptCancel,
     Quit,
     Noop,
 }
 
+impl UiAction {
+    /// Actions that start a change to the ssh config.
+    pub fn edits_config(self) -> bool {
+        matches!(
+            self,
+            UiAction::EditSelected
+                | UiAction::NewHost
+                | UiAction::DeleteSelected
+                | UiAction::MergeDuplicates
+                | UiAction::Undo
+                | UiAction::CopySelectedAs
+                | UiAction::RenameSelected
+                | UiAction::CloneSelected
+                | UiAction::ToggleMark
+                | UiAction::ImportKnownHosts
+                | UiAction::ShowGlobalOptions
+                | UiAction::EditConfigFile
+        )
+    }
+}
+
+/// Braille frames for the loading and probing spinners.
+const SPINNER: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+/// Time each spinner frame is shown.
+pub const SPINNER_STEP: Duration = Duration::from_millis(100);
+
+/// The spinner frame for now, so every redraw advances it without a counter.
+fn spinner_frame() -> char {
+    let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    SPINNER[(elapsed.as_millis() / SPINNER_STEP.as_millis()) as usize % SPINNER.len()]
+}
+
+/// Screen shown while the ssh config is read in the background; `detail` says
+/// what is being read once the load has taken a while.
+pub fn draw_loading(f: &mut Frame<'_>, detail: Option<&str>) {
+    let area = f.area();
+    let dim = Style::default().fg(Color::DarkGray);
+    let mut lines = vec![Line::from(""); usize::from(area.height.saturating_sub(3) / 2)];
+    lines.push(Line::from(format!("{} Loading hosts…", spinner_frame())));
+    lines.push(Line::from(Span::styled(detail.unwrap_or_default().to_string(), dim)));
+    lines.push(Line::from(Span::styled("[q] quit", dim)));
+    f.render_widget(Paragraph::new(lines).alignment(Alignment::Center), area);
+}
+
+/// Keys that quit before the keymap is loaded: q, Esc and Ctrl-C.
+pub fn is_quit_key(key: KeyEvent) -> bool {
+    match key.code {
+        KeyCode::Esc | KeyCode::Char('q') => true,
+        KeyCode::Char('c') => key.modifiers.contains(KeyModifiers::CONTROL),
+        _ => false,
+    }
+}
+
 pub fn draw_ui(f: &mut Frame<'_>, state: &AppState) {
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints([
             Constraint::Length(1),
             Constraint::Min(3),
-            Constraint::Length(2),
+            Constraint::Length(1),
+            Constraint::Length(3),
         ])
         .split(f.area());
 
     // Header
     let header = Paragraph::new(Line::from(vec![
-        Span::styled("ssh-picker", Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)),
-        Span::raw("  [j/k] move  [Enter] ssh  [/] filter  [e] edit  [a] add  [d] delete  [q] quit"),
+        Span::styled("ssh-picker", Style::default().fg(state.accent).add_modifier(Modifier::BOLD)),
+        Span::raw("  "),
+        Span::raw(mode_hints(&state.mode, &state.keymap, state.read_only)),
     ]));
     f.render_widget(header, chunks[0]);
 
     // List of hosts
-    let items: Vec<ListItem> = state
-        .filtered_hosts
-        .iter()
-        .map(|&idx| host_to_item(&state.hosts[idx]))
-        .collect();
+    let list_area = if state.show_preview {
+        let panes = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(55), Constraint::Percentage(45)])
+            .split(chunks[1]);
+        draw_preview(f, panes[1], state);
+        panes[0]
+    } else {
+        chunks[1]
+    };
+    let columns = column_widths(state, list_area);
+    // Only the rows on screen become items, so drawing stays cheap for long lists
+    let ls = build_list_state(state, list_area);
+    let visible = &state.rows[ls.offset()..];
+    let visible = &visible[..visible.len().min(usize::from(list_area.height))];
+    let items: Vec<ListItem> = visible.iter().map(|row| row_to_item(row, columns, state)).collect();
+    let list_title = match state.marked.len() {
+        0 => "Hosts".to_string(),
+        n => format!("Hosts ({} marked, [Esc] clears)", n),
+    };
     let list = List::new(items)
-        .block(Block::default().borders(Borders::ALL).title("Hosts"))
-        .highlight_style(Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD))
-        .highlight_symbol("› ");
-    let mut ls = build_list_state(state);
-    f.render_stateful_widget(list, chunks[1], &mut ls);
-
-    // Footer / filter
-    let filter = match state.mode {
-        Mode::Filter => format!("/{}", state.filter_text),
-        _ => String::new(),
+        .block(Block::default().borders(Borders::ALL).title(list_title))
+        .highlight_style(Style::default().fg(state.accent).add_modifier(Modifier::BOLD))
+        .highlight_symbol(HIGHLIGHT_SYMBOL);
+    if state.rows.is_empty() {
+        draw_empty_list(f, list_area, state);
+    } else {
+        let mut shown = ratatui::widgets::ListState::default().with_selected(ls.selected().map(|i| i.saturating_sub(ls.offset())));
+        f.render_stateful_widget(list, list_area, &mut shown);
+        state.list_view.set(ListView { area: list_area, offset: ls.offset() });
+    }
+
+    f.render_widget(Paragraph::new(status_line(state)), chunks[2]);
+
+    // Footer: filter, prompt input, or the last status message
+    let (title, content, style) = match &state.mode {
+        Mode::Filter => {
+            let title = if state.filter_regex.is_some() {
+                "Filter (regex)"
+            } else if state.filter_glob.is_some() {
+                "Filter (glob)"
+            } else if state.search_all_fields {
+                "Filter (all fields)"
+            } else {
+                "Filter"
+            };
+            let mut spans = vec![Span::raw(format!("/{}", state.filter_text))];
+            // A regex or glob that doesn't parse yet is reported beside the text
+            if let (StatusKind::Failure, Some(message)) = (state.status_kind, &state.status_message) {
+                spans.push(Span::styled(format!("  {}", message), Style::default().fg(Color::Red)));
+            }
+            (title.to_string(), Line::from(spans), Style::default())
+        }
+        Mode::Prompt(prompt) => (prompt.kind.label(), Line::from(prompt.input.clone()), Style::default()),
+        Mode::Normal if !state.jump_buffer.is_empty() => (
+            "Go to".to_string(),
+            Line::from(format!("#{}  [Enter] connect  [Esc] cancel", state.jump_buffer)),
+            Style::default(),
+        ),
+        _ => {
+            let style = match state.status_kind {
+                StatusKind::Info => Style::default(),
+                StatusKind::Success => Style::default().fg(Color::Green),
+                StatusKind::Failure => Style::default().fg(Color::Red),
+            };
+            let message = match &state.status_message {
+                Some(message) => message.clone(),
+                None if state.read_only => "read-only mode".to_string(),
+                None => String::new(),
+            };
+            ("Status".to_string(), Line::from(message), style)
+        }
     };
-    let footer = Paragraph::new(filter)
-        .block(Block::default().borders(Borders::ALL).title("Filter"))
+    let footer = Paragraph::new(content)
+        .style(style)
+        .block(Block::default().borders(Borders::ALL).title(title))
         .wrap(Wrap { trim: true });
-    f.render_widget(footer, chunks[2]);
+    f.render_widget(footer, chunks[3]);
 
     // Modal overlay(s)
     if let Mode::Confirm(ctx) = &state.mode {
-        let area = centered_rect(60, 30, f.area());
+        // Leave room for the list of patterns in a batch delete
+        let lists_hosts = matches!(ctx, ConfirmContext::DeleteMarked { .. } | ConfirmContext::ImportKnownHosts { .. });
+        let height = if lists_hosts { 60 } else { 30 };
+        let area = centered_rect(60, height, f.area());
         let block = Block::default().borders(Borders::ALL).title("Confirm");
         let message = match ctx {
-            ConfirmContext::Delete { pattern } => format!("Delete host '{}' ?", pattern),
+            ConfirmContext::Delete { host } => format!("Delete host '{}' ?", host.pattern),
+            ConfirmContext::DeleteMarked { hosts } => {
+                let names: Vec<String> = hosts.iter().map(|h| h.pattern.clone()).collect();
+                format!("Delete {} hosts?\n{}", hosts.len(), name_list(&names))
+            }
+            ConfirmContext::ImportKnownHosts { hosts, hashed } => {
+                let names: Vec<String> = hosts.iter().map(|h| h.pattern.clone()).collect();
+                let mut message = format!("Add {} hosts from known_hosts?\n{}", hosts.len(), name_list(&names));
+                if *hashed > 0 {
+                    message.push_str(&format!("\n({} hashed entries can't be read and were skipped)", hashed));
+                }
+                message
+            }
+            ConfirmContext::MergeDuplicates => {
+                "Keep only the first block of each repeated Host and delete the rest?".to_string()
+            }
+            ConfirmContext::DiscardForm { quit: false, .. } => "Discard unsaved changes?".to_string(),
+            ConfirmContext::DiscardForm { quit: true, .. } => "Discard unsaved changes and quit?".to_string(),
+            ConfirmContext::OverwriteExport { path, hosts } => {
+                format!("{} already exists. Replace it with {} exported host(s)?", path.display(), hosts.len())
+            }
         };
-        let text = vec![
-            Line::from(Span::raw(message)),
+        let mut text: Vec<Line> = message.lines().map(|l| Line::from(l.to_string())).collect();
+        text.extend([
             Span::raw("").into(),
             Line::from(Span::styled(
-                "y: Yes    n/Esc: No",
-                Style::default().fg(Color::Yellow),
+                match ctx {
+                    ConfirmContext::ImportKnownHosts { .. } => "y/Enter: Add all    e: Review each    n/Esc: No",
+                    _ => "y/Enter: Yes    n/Esc: No",
+                },
+                Style::default().fg(state.accent),
             )),
-        ];
+        ]);
         let para = Paragraph::new(text).block(block).wrap(Wrap { trim: true });
         f.render_widget(Clear, area); // clear background
         f.render_widget(para, area);
     }
 
     if let Mode::EditForm(form) = &state.mode {
-        let area = centered_rect(80, 60, f.area());
-        let title = if form.is_editing { "Edit Host" } else { "New Host" };
-        let block = Block::default().borders(Borders::ALL).title(title);
-
-        let fields = [
-            ("Host Pattern", &form.pattern),
-            ("HostName", &form.hostname),
-            ("User", &form.user),
-            ("Port", &form.port),
-        ];
-
-        let mut text = vec![
-            Line::from(Span::raw("Use Tab/Shift+Tab to navigate, Enter to save, Esc to cancel")),
-            Span::raw("").into(),
-        ];
+        draw_form(f, form, state);
+    }
 
-        for (i, (label, value)) in fields.iter().enumerate() {
-            let style = if i == form.current_field {
-                Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)
-            } else {
-                Style::default().fg(Color::White)
-            };
-            text.push(Line::from(vec![
-                Span::styled(format!("{:12}: ", label), Style::default().fg(Color::Cyan)),
-                Span::styled(value.as_str(), style),
-            ]));
-        }
+    if let Mode::GlobalOptions(global) = &state.mode {
+        draw_global_options(f, global, state);
+    }
 
-        let para = Paragraph::new(text).block(block).wrap(Wrap { trim: true });
+    if let Mode::Settings(form) = &state.mode {
+        draw_settings(f, form, state);
+    }
+
+    if let Mode::Help = &state.mode {
+        draw_help(f, state);
+    }
+
+    if let Mode::Resolved { host, lines, scroll } = &state.mode {
+        draw_resolved(f, host, lines, *scroll);
+    }
+
+    if let Mode::Diagnostics { lines, scroll } = &state.mode {
+        let area = centered_rect(70, 60, f.area());
+        let block = Block::default().borders(Borders::ALL).title("Config problems  [Esc] close");
+        let rows: Vec<Line> = lines.iter().map(|l| Line::from(Span::styled(l.clone(), Style::default().fg(Color::Yellow)))).collect();
+        let para = Paragraph::new(rows).block(block).wrap(Wrap { trim: false }).scroll((*scroll, 0));
+        f.render_widget(Clear, area);
+        f.render_widget(para, area);
+    }
+
+    if let Mode::MatchBlocks { lines, scroll } = &state.mode {
+        let area = centered_rect(70, 60, f.area());
+        let block = Block::default().borders(Borders::ALL).title("Match blocks (kept as written, not connectable)  [Esc] close");
+        let rows: Vec<Line> = lines
+            .iter()
+            .map(|l| if l.starts_with(' ') { Line::from(l.clone()) } else { Line::from(Span::styled(l.clone(), Style::default().fg(Color::Magenta))) })
+            .collect();
+        let para = Paragraph::new(rows).block(block).wrap(Wrap { trim: false }).scroll((*scroll, 0));
         f.render_widget(Clear, area);
         f.render_widget(para, area);
     }
 }
 
-fn host_to_item(entry: &SshHostEntry) -> ListItem<'_> {
-    let line = Line::from(vec![
-        Span::styled(&entry.pattern, Style::default().fg(Color::White)),
-        Span::raw("  "),
-        Span::styled(
-            entry.hostname.as_deref().unwrap_or(""),
-            Style::default().fg(Color::Gray),
+/// One row under the list: how many hosts match out of all of them, the sort
+/// order, and whatever narrows or changes the view (mode, filter, tree, …).
+fn status_line(state: &AppState) -> Line<'static> {
+    let mut parts = Vec::new();
+    let mode = match &state.mode {
+        Mode::Normal => None,
+        Mode::Filter => Some("FILTER"),
+        Mode::Confirm(_) => Some("CONFIRM"),
+        Mode::EditForm(_) => Some("EDIT"),
+        Mode::Prompt(_) => Some("PROMPT"),
+        Mode::Help => Some("HELP"),
+        Mode::Resolved { .. } => Some("SSH -G"),
+        Mode::Diagnostics { .. } => Some("DIAGNOSTICS"),
+        Mode::MatchBlocks { .. } => Some("MATCH"),
+        Mode::GlobalOptions(_) => Some("GLOBAL"),
+        Mode::Settings(_) => Some("SETTINGS"),
+    };
+    parts.push(format!("{}/{} hosts", state.filtered_hosts.len(), state.hosts.len()));
+    parts.push(format!("sort: {}", state.sort_mode.label()));
+    if !state.filter_text.is_empty() {
+        parts.push(format!("filter: {}", state.filter_text));
+    }
+    if state.search_all_fields {
+        parts.push("all fields".to_string());
+    }
+    if state.tree_view {
+        parts.push("tree".to_string());
+    }
+    if state.read_only {
+        parts.push("read-only".to_string());
+    }
+    if !state.probing.is_empty() {
+        parts.push(format!("{} probing {} host(s)…", spinner_frame(), state.probing.len()));
+    }
+    let dim = Style::default().fg(Color::DarkGray);
+    let mut spans = Vec::new();
+    if let Some(mode) = mode {
+        spans.push(Span::styled(format!(" {} ", mode), Style::default().fg(Color::Black).bg(state.accent)));
+    }
+    for (i, part) in parts.into_iter().enumerate() {
+        spans.push(Span::styled(if i == 0 { " " } else { "  · " }, dim));
+        spans.push(Span::styled(part, dim));
+    }
+    Line::from(spans)
+}
+
+/// Guidance in place of an empty host list: how to add the first host, or that
+/// the filter matched nothing.
+fn draw_empty_list(f: &mut Frame<'_>, area: Rect, state: &AppState) {
+    let message = if state.hosts.is_empty() {
+        format!("No hosts yet — press '{}' to add one", state.keymap.label("NewHost"))
+    } else {
+        format!("No hosts match '{}'", state.filter_text)
+    };
+    let block = Block::default().borders(Borders::ALL).title("Hosts");
+    let top_padding = area.height.saturating_sub(3) / 2;
+    let mut lines = vec![Line::from(""); usize::from(top_padding)];
+    lines.push(Line::from(Span::styled(message, Style::default().fg(Color::DarkGray))));
+    let para = Paragraph::new(lines).block(block).alignment(Alignment::Center);
+    f.render_widget(para, area);
+}
+
+/// Every action grouped by mode, with the keys currently bound to it.
+fn draw_help(f: &mut Frame<'_>, state: &AppState) {
+    let area = centered_rect(70, 80, f.area());
+    let block = Block::default().borders(Borders::ALL).title("Keys  [Esc/?] close");
+    let heading = |title: &'static str| Line::from(Span::styled(title, Style::default().fg(state.accent).add_modifier(Modifier::BOLD)));
+    let row = |keys: &str, help: &str| {
+        Line::from(vec![
+            Span::styled(format!("{:>16}  ", keys), Style::default().fg(Color::Cyan)),
+            Span::raw(help.to_string()),
+        ])
+    };
+    let mut lines = vec![heading("Host list")];
+    for (help, keys) in state.keymap.describe() {
+        lines.push(row(if keys.is_empty() { "(unbound)" } else { &keys }, help));
+    }
+    lines.push(row("1-9…", "type a list number, Enter connects"));
+    lines.push(row("other letters", "jump to next host starting with it"));
+    let fixed: [(&str, &[(&str, &str)]); 4] = [
+        (
+            "Filter",
+            &[
+                ("type", "fuzzy filter; * or ? for a glob, start with / for a regex"),
+                ("Up/Down", "move"),
+                ("Enter", "ssh to selected host"),
+                ("Backspace", "delete char"),
+                ("Esc", "clear filter"),
+            ],
         ),
-        Span::raw("  "),
-        Span::styled(
-            entry.user.as_deref().unwrap_or(""),
-            Style::default().fg(Color::DarkGray),
+        ("Confirmations", &[("y/Enter", "yes"), ("n/Esc", "no")]),
+        (
+            "Host form",
+            &[
+                ("Tab/Shift+Tab", "next/previous field"),
+                ("Enter", "save"),
+                ("Esc", "cancel"),
+            ],
         ),
-    ]);
-    ListItem::new(line)
+        ("Prompts", &[("Enter", "confirm"), ("Esc", "cancel")]),
+    ];
+    for (title, rows) in fixed {
+        lines.push(Line::from(""));
+        lines.push(heading(title));
+        for (keys, help) in rows {
+            lines.push(row(keys, help));
+        }
+    }
+    let para = Paragraph::new(lines)
+        .block(block)
+        .wrap(Wrap { trim: false })
+        .scroll((state.help_scroll, 0));
+    f.render_widget(Clear, area);
+    f.render_widget(para, area);
+}
+
+/// `ssh -G` output as aligned `key  value` rows.
+fn draw_resolved(f: &mut Frame<'_>, host: &str, lines: &[String], scroll: u16) {
+    let area = centered_rect(70, 80, f.area());
+    let block = Block::default().borders(Borders::ALL).title(format!("ssh -G {}  [Esc] close", host));
+    let width = lines.iter().map(|line| line.split(' ').next().unwrap_or(line).len()).max().unwrap_or(0);
+    let rows: Vec<Line> = lines
+        .iter()
+        .map(|line| {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            Line::from(vec![
+                Span::styled(format!("{:>width$}  ", key), Style::default().fg(Color::Cyan)),
+                Span::raw(value.to_string()),
+            ])
+        })
+        .collect();
+    let para = Paragraph::new(rows).block(block).wrap(Wrap { trim: false }).scroll((scroll, 0));
+    f.render_widget(Clear, area);
+    f.render_widget(para, area);
+}
+
+/// The `Host *` editor: its options as editable lines, apart from the host list
+/// because the block is not something to connect to.
+fn draw_global_options(f: &mut Frame<'_>, global: &GlobalOptions, state: &AppState) {
+    let area = centered_rect(70, 70, f.area());
+    let block = Block::default().borders(Borders::ALL).title("Global options (Host *)");
+    let mut text = vec![
+        Line::from(Span::styled(
+            "Defaults for every host, one `Key value` per line; clear a line to remove it.",
+            Style::default().fg(Color::Cyan),
+        )),
+        Line::from(Span::styled(
+            "ssh takes the first value it finds, so a host's own options win when Host * comes after it.",
+            Style::default().fg(Color::DarkGray),
+        )),
+        Line::from(""),
+    ];
+    for (i, line) in global.lines.iter().enumerate() {
+        let selected = i == global.current;
+        let span = if line.is_empty() {
+            Span::styled("+ new option, e.g. ServerAliveInterval 30", Style::default().fg(Color::DarkGray))
+        } else if selected {
+            Span::styled(line.as_str(), Style::default().fg(state.accent).add_modifier(Modifier::BOLD))
+        } else {
+            Span::styled(line.as_str(), Style::default().fg(Color::White))
+        };
+        let marker = if selected { "› " } else { "  " };
+        text.push(Line::from(vec![Span::styled(marker, Style::default().fg(state.accent)), span]));
+    }
+    if let Some(error) = &global.error {
+        text.push(Line::from(""));
+        text.push(Line::from(Span::styled(error.as_str(), Style::default().fg(Color::Red))));
+    }
+    f.render_widget(Clear, area);
+    f.render_widget(Paragraph::new(text).block(block).wrap(Wrap { trim: false }), area);
+}
+
+/// The settings screen: a row per setting, toggles as checkboxes and the
+/// accent color shown in itself when it parses.
+fn draw_settings(f: &mut Frame<'_>, form: &SettingsForm, state: &AppState) {
+    let area = centered_rect(60, 50, f.area());
+    let block = Block::default().borders(Borders::ALL).title("Settings");
+    let check = |on: bool| if on { "[✓]" } else { "[ ]" };
+    let page_size = if form.page_size.trim().is_empty() || form.page_size.trim() == "0" {
+        format!("{}  (0 = list height)", form.page_size)
+    } else {
+        form.page_size.clone()
+    };
+    let values = [check(form.show_user).to_string(), check(form.show_hostname).to_string(), page_size, form.accent.clone()];
+    let mut text = vec![
+        Line::from(Span::styled(
+            "Saved to config.toml and applied right away.",
+            Style::default().fg(Color::DarkGray),
+        )),
+        Line::from(""),
+    ];
+    for (i, (label, value)) in SETTINGS_ROWS.iter().zip(values).enumerate() {
+        let selected = i == form.current;
+        let label_style = if selected { Style::default().fg(state.accent).add_modifier(Modifier::BOLD) } else { Style::default().fg(Color::Cyan) };
+        // The accent row previews the color being typed
+        let value_color = if i == 3 { parse_color(&form.accent).unwrap_or(Color::Red) } else { Color::White };
+        let marker = if selected { "› " } else { "  " };
+        text.push(Line::from(vec![
+            Span::styled(marker, Style::default().fg(state.accent)),
+            Span::styled(format!("{:<14}", label), label_style),
+            Span::styled(value, Style::default().fg(value_color).add_modifier(Modifier::BOLD)),
+        ]));
+    }
+    if let Some(error) = &form.error {
+        text.push(Line::from(""));
+        text.push(Line::from(Span::styled(error.as_str(), Style::default().fg(Color::Red))));
+    }
+    f.render_widget(Clear, area);
+    f.render_widget(Paragraph::new(text).block(block).wrap(Wrap { trim: false }), area);
+}
+
+/// Label and placeholder of each basic form field, in `current_field` order.
+const FORM_FIELDS: [(&str, &str); FORM_BASIC_FIELDS] = [
+    ("Host Pattern", "required, e.g. web-prod"),
+    ("HostName", "(optional) name or IP; defaults to the pattern"),
+    ("User", "(optional) defaults to your login name"),
+    ("Port", "(optional) 22"),
+    ("IdentityFile", "(optional) e.g. ~/.ssh/id_ed25519"),
+    ("ProxyJump", "(optional) e.g. bastion"),
+    ("Note", "(optional) e.g. owned by payments team"),
+];
+
+/// The host form: a labelled box per basic field, two per row, with the focused
+/// one outlined in the accent color, then the forward and advanced option lines.
+fn draw_form(f: &mut Frame<'_>, form: &FormData, state: &AppState) {
+    let area = centered_rect(80, 80, f.area());
+    let title = match &form.template {
+        _ if form.is_editing => "Edit Host".to_string(),
+        Some(source) => format!("New Host (from {})", source),
+        None => "New Host".to_string(),
+    };
+    let block = Block::default().borders(Borders::ALL).title(title);
+    let inner = block.inner(area);
+    f.render_widget(Clear, area);
+    f.render_widget(block, area);
+
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Length(3),
+            Constraint::Length(2),
+            Constraint::Min(0),
+        ])
+        .split(inner);
+
+    let values = form.basic_fields();
+    for (i, ((label, placeholder), value)) in FORM_FIELDS.iter().zip(values).enumerate() {
+        let cells = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(rows[i / 2]);
+        let focused = i == form.current_field;
+        // When editing, a changed field names the value it replaces
+        let title = match form.initial.get(i) {
+            Some(initial) if form.is_editing && initial != value => {
+                let was = if initial.is_empty() { "unset" } else { initial.as_str() };
+                format!("{} (was: {})", label, was)
+            }
+            _ => label.to_string(),
+        };
+        let border = if focused { Style::default().fg(state.accent) } else { Style::default().fg(Color::DarkGray) };
+        let field_block = Block::default()
+            .borders(Borders::ALL)
+            .border_style(border)
+            .title(Span::styled(title, Style::default().fg(Color::Cyan)));
+        let mut line = if value.is_empty() {
+            vec![Span::styled(*placeholder, Style::default().fg(Color::DarkGray))]
+        } else {
+            vec![Span::styled(value.as_str(), Style::default().fg(Color::White).add_modifier(Modifier::BOLD))]
+        };
+        if focused {
+            line.insert(if value.is_empty() { 0 } else { 1 }, Span::styled("▏", Style::default().fg(state.accent)));
+        }
+        // A field left alone on the last row spans it
+        let cell = if i + 1 == FORM_BASIC_FIELDS && i % 2 == 0 { rows[i / 2] } else { cells[i % 2] };
+        f.render_widget(Paragraph::new(Line::from(line)).block(field_block), cell);
+    }
+
+    // Checkboxes: ✓ writes `yes`, ✗ writes `no`, and an empty box leaves the option out
+    let mut toggles = vec![Span::raw(" ")];
+    for (i, (name, value)) in FORM_TOGGLES.iter().zip(form.toggles).enumerate() {
+        let focused = FORM_BASIC_FIELDS + i == form.current_field;
+        let mark = match value {
+            Some(true) => "[✓]",
+            Some(false) => "[✗]",
+            None => "[ ]",
+        };
+        let style = if focused {
+            Style::default().fg(state.accent).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().fg(if value.is_some() { Color::White } else { Color::DarkGray })
+        };
+        toggles.push(Span::styled(format!("{} {}", mark, name), style));
+        toggles.push(Span::raw("  "));
+    }
+    if form.current_field >= FORM_BASIC_FIELDS && form.current_field < FORM_FORWARDS_START {
+        toggles.push(Span::styled("[Space] yes / no / unset", Style::default().fg(Color::DarkGray)));
+    }
+    f.render_widget(Paragraph::new(Line::from(toggles)), rows[4]);
+
+    let option_line = |line: &str, field: usize, placeholder: &'static str| {
+        let selected = field == form.current_field;
+        let span = if line.is_empty() {
+            Span::styled(placeholder, Style::default().fg(Color::DarkGray))
+        } else if selected {
+            Span::styled(line.to_string(), Style::default().fg(state.accent).add_modifier(Modifier::BOLD))
+        } else {
+            Span::styled(line.to_string(), Style::default().fg(Color::White))
+        };
+        let marker = if selected { "› " } else { "  " };
+        Line::from(vec![Span::styled(marker, Style::default().fg(state.accent)), span])
+    };
+    let mut text = vec![Line::from(Span::styled(
+        "Forwards (L local, R remote, D dynamic; `L 8080:db:80` works too)",
+        Style::default().fg(Color::Cyan),
+    ))];
+    for (i, line) in form.forwards.iter().enumerate() {
+        text.push(option_line(line, FORM_FORWARDS_START + i, "+ new forward, e.g. L 8080 localhost:80"));
+    }
+    text.push(Line::from(Span::styled(
+        "Advanced (one `Key value` per line, clear a line to remove it)",
+        Style::default().fg(Color::Cyan),
+    )));
+    for (i, line) in form.advanced.iter().enumerate() {
+        text.push(option_line(line, form.advanced_start() + i, "+ new option, e.g. ForwardAgent yes"));
+    }
+    if let Some(notice) = &form.notice {
+        text.push(Span::raw("").into());
+        text.push(Line::from(Span::styled(notice.as_str(), Style::default().fg(Color::DarkGray))));
+    }
+    if let Some(error) = &form.error {
+        text.push(Span::raw("").into());
+        text.push(Line::from(Span::styled(error.as_str(), Style::default().fg(Color::Red))));
+    }
+    f.render_widget(Paragraph::new(text).wrap(Wrap { trim: false }), rows[5]);
 }
 
-fn build_list_state(state: &AppState) -> ratatui::widgets::ListState {
-    let mut ls = ratatui::widgets::ListState::default();
-    if !state.filtered_hosts.is_empty() {
+/// Every field of the selected host, including the options kept in `other`.
+fn draw_preview(f: &mut Frame<'_>, area: Rect, state: &AppState) {
+    let block = Block::default().borders(Borders::ALL).title("Details");
+    let Some(entry) = state.selected_host() else {
+        f.render_widget(Paragraph::new("").block(block), area);
+        return;
+    };
+    let key_style = Style::default().fg(Color::Cyan);
+    let row = |key: String, value: String| {
+        Line::from(vec![Span::styled(format!("{}: ", key), key_style), Span::raw(value)])
+    };
+    let mut lines = vec![row("Host".into(), entry.pattern.clone())];
+    let except: Vec<&str> = entry.negated_aliases().collect();
+    if !except.is_empty() {
+        lines.push(row("Except".into(), except.join(" ")));
+    }
+    let fields = [
+        ("HostName", entry.hostname.clone()),
+        ("User", entry.user.clone()),
+        ("Port", entry.display_port()),
+        ("IdentityFile", entry.identity_file.clone()),
+        ("ProxyJump", entry.proxy_jump.clone()),
+        ("Forward", entry.forward.clone()),
+        ("Tags", (!entry.tags.is_empty()).then(|| entry.tags.join(" "))),
+        ("Note", entry.note.clone()),
+    ];
+    for (key, value) in fields {
+        if let Some(value) = value {
+            lines.push(row(key.into(), value));
+        }
+    }
+    for (key, value) in &entry.other {
+        lines.push(row(key.clone(), value.clone()));
+    }
+    if let Some(result) = state.probe_results.get(&entry.pattern) {
+        let status = match result.latency() {
+            Some(rtt) => format!("reachable ({}ms)", rtt.as_millis()),
+            None => "unreachable".to_string(),
+        };
+        lines.push(row("Probe".into(), status));
+    }
+    if let (Some(name), Some(path)) = (entry.source_name(), &entry.source_path) {
+        lines.push(Line::from(""));
+        let dir = path.parent().map(|d| format!("  ({})", d.display())).unwrap_or_default();
+        lines.push(row("Defined in".into(), format!("{}{}", name, dir)));
+    }
+    let para = Paragraph::new(lines)
+        .block(block)
+        .wrap(Wrap { trim: false })
+        .scroll((state.preview_scroll, 0));
+    f.render_widget(para, area);
+}
+
+/// Key hints for the header, specific to the keys the current mode accepts.
+fn mode_hints(mode: &Mode, keymap: &Keymap, read_only: bool) -> String {
+    match mode {
+        Mode::Normal => {
+            let k = |name| keymap.label(name);
+            // (hint, changes the config)
+            let hints = [
+                (format!("[{}/{}] move", k("MoveDown"), k("MoveUp")), false),
+                (format!("[{}] ssh", k("LaunchSelected")), false),
+                (format!("[{}] filter", k("BeginFilter")), false),
+                (format!("[{}] edit", k("EditSelected")), true),
+                (format!("[{}] $EDITOR", k("EditConfigFile")), true),
+                (format!("[{}] add", k("NewHost")), true),
+                (format!("[{}] yank cmd", k("CopyCommand")), false),
+                (format!("[{}] yank host", k("CopyHostname")), false),
+                (format!("[{}] yank IP", k("CopyAddress")), false),
+                (format!("[{}] rename", k("RenameSelected")), true),
+                (format!("[{}] copy host", k("CopySelectedAs")), true),
+                (format!("[{}] clone", k("CloneSelected")), true),
+                (format!("[{}] ssh+args", k("LaunchWithArgs")), false),
+                (format!("[{}] run cmd", k("RunCommand")), false),
+                (format!("[{}] forward", k("LaunchWithForward")), false),
+                (format!("[{}] sftp", k("LaunchSftp")), false),
+                (format!("[{}] ssh -vvv", k("LaunchVerbose")), false),
+                (format!("[{}] mark", k("ToggleMark")), true),
+                (format!("[{}] export", k("ExportHosts")), false),
+                (format!("[{}] delete", k("DeleteSelected")), true),
+                (format!("[{}] undo", k("Undo")), true),
+                (format!("[{}] probe", k("ProbeHosts")), false),
+                (format!("[{}] test", k("TestConnection")), false),
+                (format!("[{}] sort", k("CycleSort")), false),
+                (format!("[{}] details", k("TogglePreview")), false),
+                (format!("[{}] ssh -G", k("ShowResolved")), false),
+                (format!("[{}] Host *", k("ShowGlobalOptions")), true),
+                (format!("[{}] Match", k("ShowMatchBlocks")), false),
+                (format!("[{}] tree", k("ToggleTree")), false),
+                (format!("[{}] settings", k("ShowSettings")), false),
+                (format!("[{}] help", k("ToggleHelp")), false),
+                (format!("[{}] quit", k("Quit")), false),
+            ];
+            let shown: Vec<String> = hints
+                .into_iter()
+                .filter(|(_, edits)| !(read_only && *edits))
+                .map(|(hint, _)| hint)
+                .collect();
+            shown.join("  ")
+        }
+        Mode::Filter => "type to filter (fuzzy, glob*, or /regex)  [Up/Down] move  [Enter] ssh  [Backspace] delete char  [Esc] clear filter".to_string(),
+        Mode::Confirm(_) => "[y/Enter] yes  [n/Esc] no".to_string(),
+        Mode::EditForm(_) => "[Tab/Shift+Tab] next/prev field  [Enter] save  [Esc] cancel".to_string(),
+        Mode::Prompt(_) => "type a value  [Enter] confirm  [Esc] cancel".to_string(),
+        Mode::Help => "[j/k] scroll  [Esc/?] close help".to_string(),
+        Mode::Resolved { .. } | Mode::Diagnostics { .. } | Mode::MatchBlocks { .. } => "[j/k] scroll  [Esc] close".to_string(),
+        Mode::GlobalOptions(_) => "[Tab/Up/Down] next/prev line  [Enter] save  [Esc] cancel".to_string(),
+        Mode::Settings(_) => "[Tab/Up/Down] next/prev setting  [Space] toggle / next color  [Enter] save  [Esc] cancel".to_string(),
+    }
+}
+
+/// Names for a confirmation, one per line; long lists end with a count of the rest.
+fn name_list(names: &[String]) -> String {
+    const SHOWN: usize = 10;
+    let mut lines: Vec<String> = names.iter().take(SHOWN).cloned().collect();
+    if names.len() > SHOWN {
+        lines.push(format!("… and {} more", names.len() - SHOWN));
+    }
+    lines.join("\n")
+}
+
+/// Marks the selected row; its width is reserved on every row.
+const HIGHLIGHT_SYMBOL: &str = "› ";
+
+/// Widths of the padded name and host columns, shared by every row.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Columns {
+    name: usize,
+    host: usize,
+}
+
+/// Whether rows start with a reachability dot.
+fn shows_reachability(state: &AppState) -> bool {
+    state.settings.auto_probe || !state.probe_results.is_empty()
+}
+
+/// `HostName[:port]` as shown in the host column; IPv6 addresses get brackets
+/// when a port follows.
+fn host_label(entry: &SshHostEntry) -> String {
+    match &entry.hostname {
+        Some(hostname) => join_host_port(hostname, entry.port),
+        None => entry.port.map(|p| format!(":{}", p)).unwrap_or_default(),
+    }
+}
+
+/// Column widths that fit the widest listed name and host, with the name capped
+/// at `settings.max_name_width` and both shrunk to leave room in `area`.
+fn column_widths(state: &AppState, area: Rect) -> Columns {
+    let rows = state.rows.iter().filter_map(|row| match row {
+        ListRow::Host { index, depth } => Some((&state.hosts[*index], *depth)),
+        ListRow::Group { .. } => None,
+    });
+    let widest_name = rows.clone().map(|(h, depth)| INDENT * depth + h.pattern.chars().count()).max().unwrap_or(0);
+    let widest_host = rows.map(|(h, _)| host_label(h).chars().count()).max().unwrap_or(0);
+    // Borders, highlight symbol, checkbox, and reachability dot
+    let mut prefix = 2 + HIGHLIGHT_SYMBOL.chars().count();
+    if !state.marked.is_empty() { prefix += 4; }
+    if shows_reachability(state) { prefix += 2; }
+    let available = usize::from(area.width).saturating_sub(prefix);
+    let name = widest_name.min(state.settings.max_name_width).min(available / 2).max(1);
+    // Leave about a third of what remains for the user and everything after it
+    let host = widest_host.min(available.saturating_sub(name + 2) * 2 / 3);
+    Columns { name, host }
+}
+
+/// `text` padded with spaces to exactly `width` characters, or cut short with
+/// an ellipsis when it is longer.
+fn fit(text: &str, width: usize) -> String {
+    let len = text.chars().count();
+    if len <= width {
+        format!("{}{}", text, " ".repeat(width - len))
+    } else if width == 0 {
+        String::new()
+    } else {
+        let mut cut: String = text.chars().take(width - 1).collect();
+        cut.push('…');
+        cut
+    }
+}
+
+/// Columns each tree level is indented by.
+const INDENT: usize = 2;
+
+fn row_to_item<'a>(row: &ListRow, columns: Columns, state: &'a AppState) -> ListItem<'a> {
+    match row {
+        ListRow::Host { index, depth } => host_to_item(&state.hosts[*index], *index, *depth, columns, state),
+        ListRow::Group { prefix, depth, count, collapsed } => {
+            // Blank space where host rows have a checkbox and reachability dot
+            let mut lead = 0;
+            if !state.marked.is_empty() { lead += 4; }
+            if shows_reachability(state) { lead += 2; }
+            let arrow = if *collapsed { "▸" } else { "▾" };
+            ListItem::new(Line::from(vec![
+                Span::raw(" ".repeat(lead + INDENT * depth)),
+                Span::styled(format!("{} {}", arrow, prefix), Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)),
+                Span::styled(format!(" ({})", count), Style::default().fg(Color::DarkGray)),
+            ]))
+        }
+    }
+}
+
+fn host_to_item<'a>(entry: &'a SshHostEntry, index: usize, depth: usize, columns: Columns, state: &AppState) -> ListItem<'a> {
+    let mut spans = Vec::new();
+    // Checkboxes only appear once something is marked
+    if !state.marked.is_empty() {
+        spans.push(if state.marked.contains(&index) {
+            Span::styled("[x] ", Style::default().fg(Color::Red))
+        } else {
+            Span::raw("[ ] ")
+        });
+    }
+    if shows_reachability(state) {
+        let color = match state.probe_results.get(&entry.pattern) {
+            Some(ProbeResult::Reachable(_)) => Color::Green,
+            Some(ProbeResult::Unreachable) => Color::Red,
+            None => Color::DarkGray,
+        };
+        spans.push(Span::styled("● ", Style::default().fg(color)));
+    }
+    // Wildcard blocks only hold defaults; dim them so they don't look connectable
+    let name_color = if entry.is_wildcard() { Color::DarkGray } else { state.host_color(entry).unwrap_or(Color::White) };
+    // The first alias is bright and any others dim, split after fitting the column
+    let indent = (INDENT * depth).min(columns.name);
+    spans.push(Span::raw(" ".repeat(indent)));
+    let name = fit(&entry.pattern, columns.name - indent);
+    let primary_end = entry.primary_alias().len();
+    // Characters the filter matched are picked out in the accent color
+    let matched = state.filter_highlights(&entry.pattern);
+    // Negated aliases (`!bastion`) are exclusions, not names: shown in red
+    let negated_at = |offset: usize| match entry.pattern.get(..offset).and_then(|before| before.rsplit(char::is_whitespace).next()) {
+        Some("") => entry.pattern[offset..].starts_with('!'),
+        Some(token) => token.starts_with('!'),
+        None => false,
+    };
+    let style_at = |offset: usize, c: char| {
+        let color = if negated_at(offset) {
+            Color::Red
+        } else if offset < primary_end {
+            name_color
+        } else {
+            Color::DarkGray
+        };
+        let base = Style::default().fg(color);
+        let is_match = matched.contains(&offset) && entry.pattern[offset..].starts_with(c);
+        if is_match { base.fg(state.accent).add_modifier(Modifier::BOLD) } else { base }
+    };
+    let mut run = String::new();
+    let mut run_style = Style::default();
+    for (offset, c) in name.char_indices() {
+        let style = style_at(offset, c);
+        if style != run_style && !run.is_empty() {
+            spans.push(Span::styled(std::mem::take(&mut run), run_style));
+        }
+        run_style = style;
+        run.push(c);
+    }
+    spans.push(Span::styled(run, run_style));
+    if state.settings.show_hostname {
+        spans.push(Span::raw("  "));
+        spans.push(Span::styled(fit(&host_label(entry), columns.host), Style::default().fg(Color::Gray)));
+    }
+    if state.settings.show_user {
+        spans.push(Span::raw("  "));
+        spans.push(Span::styled(entry.user.as_deref().unwrap_or(""), Style::default().fg(Color::DarkGray)));
+    }
+    if entry.is_wildcard() {
+        let except: Vec<&str> = entry.negated_aliases().collect();
+        let label = if except.is_empty() {
+            "  (defaults)".to_string()
+        } else {
+            format!("  (defaults, except {})", except.join(", "))
+        };
+        spans.push(Span::styled(label, Style::default().fg(Color::DarkGray).add_modifier(Modifier::ITALIC)));
+    }
+    for tag in &entry.tags {
+        spans.push(Span::raw(" "));
+        spans.push(Span::styled(format!(" {} ", tag), Style::default().fg(Color::Black).bg(state.tag_color(tag).unwrap_or_else(|| tag_color(tag)))));
+    }
+    if state.settings.show_identity {
+        if let Some(identity) = &entry.identity_file {
+            let name = identity.rsplit(['/', '\\']).next().unwrap_or(identity);
+            spans.push(Span::raw("  "));
+            spans.push(Span::styled(format!("[{}]", name), Style::default().fg(Color::Magenta)));
+        }
+    }
+    // Latency only shows once a sweep has reached this host
+    if state.show_latency {
+        if let Some(rtt) = state.probe_results.get(&entry.pattern).and_then(|r| r.latency()) {
+            spans.push(Span::raw("  "));
+            spans.push(Span::styled(
+                format!("{}ms", rtt.as_millis()),
+                Style::default().fg(Color::Green),
+            ));
+        }
+    }
+    ListItem::new(Line::from(spans))
+}
+
+/// A stable color per tag name, so a tag looks the same on every row.
+fn tag_color(tag: &str) -> Color {
+    const PALETTE: [Color; 6] = [Color::Cyan, Color::Green, Color::Yellow, Color::Magenta, Color::Blue, Color::Red];
+    let sum = tag.to_lowercase().bytes().fold(0usize, |acc, b| acc.wrapping_mul(31).wrapping_add(b as usize));
+    PALETTE[sum % PALETTE.len()]
+}
+
+/// Rows kept visible above and below the selection while scrolling.
+const SCROLL_MARGIN: usize = 2;
+
+/// First visible row for a list of `len` rows shown `height` at a time: last
+/// frame's offset, moved only as far as needed to keep `SCROLL_MARGIN` rows
+/// around `selected`.
+fn scroll_offset(previous: usize, selected: usize, height: usize, len: usize) -> usize {
+    if height == 0 {
+        return 0;
+    }
+    let margin = SCROLL_MARGIN.min((height - 1) / 2);
+    let mut offset = previous;
+    if selected < offset + margin {
+        offset = selected.saturating_sub(margin);
+    }
+    if selected + margin >= offset + height {
+        offset = selected + margin + 1 - height;
+    }
+    offset.min(len.saturating_sub(height))
+}
+
+fn build_list_state(state: &AppState, area: Rect) -> ratatui::widgets::ListState {
+    let height = usize::from(area.height.saturating_sub(2));
+    let offset = scroll_offset(state.list_view.get().offset, state.selected_index, height, state.rows.len());
+    let mut ls = ratatui::widgets::ListState::default().with_offset(offset);
+    if !state.rows.is_empty() {
         ls.select(Some(state.selected_index));
     }
     ls
 }
 
-pub fn read_event(mode: &Mode) -> Result<Event> {
+pub fn read_event(mode: &Mode, keymap: &Keymap) -> Result<Event> {
     if event::poll(Duration::from_millis(200))? {
-        if let CEvent::Key(key) = event::read()? {
-            return Ok(Event::Action(map_key(key, mode)));
+        match event::read()? {
+            CEvent::Key(key) => return Ok(Event::Action(map_key(key, mode, keymap))),
+            CEvent::Resize(_, _) => return Ok(Event::Resize),
+            CEvent::Mouse(mouse) if matches!(mode, Mode::Normal | Mode::Filter) => {
+                let action = match mouse.kind {
+                    MouseEventKind::Down(MouseButton::Left) => Some(UiAction::Click { column: mouse.column, row: mouse.row }),
+                    MouseEventKind::ScrollDown => Some(UiAction::MoveDown),
+                    MouseEventKind::ScrollUp => Some(UiAction::MoveUp),
+                    _ => None,
+                };
+                if let Some(action) = action {
+                    return Ok(Event::Action(action));
+                }
+            }
+            _ => {}
         }
     }
     Ok(Event::Tick)
 }
 
-fn map_key(key: KeyEvent, mode: &Mode) -> UiAction {
+fn map_key(key: KeyEvent, mode: &Mode, keymap: &Keymap) -> UiAction {
     match mode {
         Mode::EditForm(_) => match (key.code, key.modifiers) {
             (KeyCode::Tab, _) => UiAction::FormNextField,
@@ -174,26 +1046,124 @@ fn map_key(key: KeyEvent, mode: &Mode) -> UiAction {
             (KeyCode::Enter, _) => UiAction::FormSubmit,
             (KeyCode::Esc, _) => UiAction::FormCancel,
             (KeyCode::Backspace, _) => UiAction::BackspaceFilter,
+            // Plain letters are typed; of the Ctrl/Alt keys only a quit binding acts
+            (_, modifiers) if modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                match keymap.normal_action(key) {
+                    Some(UiAction::Quit) => UiAction::Quit,
+                    _ => UiAction::Noop,
+                }
+            }
             (KeyCode::Char(c), _) => UiAction::InputChar(c),
             _ => UiAction::Noop,
         },
-        _ => match (key.code, key.modifiers) {
-            (KeyCode::Char('q'), _) => UiAction::Quit,
-            (KeyCode::Enter, _) => UiAction::LaunchSelected,
-            (KeyCode::Char('j'), _) | (KeyCode::Down, _) => UiAction::MoveDown,
-            (KeyCode::Char('k'), _) | (KeyCode::Up, _) => UiAction::MoveUp,
-            (KeyCode::PageDown, _) | (KeyCode::Char('f'), KeyModifiers::CONTROL) => UiAction::PageDown,
-            (KeyCode::PageUp, _) | (KeyCode::Char('b'), KeyModifiers::CONTROL) => UiAction::PageUp,
-            (KeyCode::Char('/'), _) => UiAction::BeginFilter,
-            (KeyCode::Esc, _) => UiAction::ClearFilter,
+        Mode::GlobalOptions(_) | Mode::Settings(_) => match (key.code, key.modifiers) {
+            (KeyCode::Tab | KeyCode::Down, _) => UiAction::FormNextField,
+            (KeyCode::BackTab | KeyCode::Up, _) => UiAction::FormPrevField,
+            (KeyCode::Enter, _) => UiAction::FormSubmit,
+            (KeyCode::Esc, _) => UiAction::FormCancel,
             (KeyCode::Backspace, _) => UiAction::BackspaceFilter,
-            (KeyCode::Char('e'), _) => UiAction::EditSelected,
-            (KeyCode::Char('a'), _) => UiAction::NewHost,
-            (KeyCode::Char('d'), _) => UiAction::DeleteSelected,
+            (_, modifiers) if modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => UiAction::Noop,
             (KeyCode::Char(c), _) => UiAction::InputChar(c),
             _ => UiAction::Noop,
         },
+        Mode::Prompt(_) => match key.code {
+            KeyCode::Enter => UiAction::PromptSubmit,
+            KeyCode::Esc => UiAction::PromptCancel,
+            KeyCode::Backspace => UiAction::BackspaceFilter,
+            KeyCode::Char(c) => UiAction::InputChar(c),
+            _ => UiAction::Noop,
+        },
+        Mode::Help => match key.code {
+            KeyCode::Esc => UiAction::ToggleHelp,
+            _ => match keymap.normal_action(key) {
+                Some(UiAction::ToggleHelp) => UiAction::ToggleHelp,
+                Some(UiAction::MoveDown | UiAction::ScrollPreviewDown) => UiAction::ScrollPreviewDown,
+                Some(UiAction::MoveUp | UiAction::ScrollPreviewUp) => UiAction::ScrollPreviewUp,
+                _ => UiAction::Noop,
+            },
+        },
+        Mode::Resolved { .. } => match key.code {
+            KeyCode::Esc => UiAction::ShowResolved,
+            _ => match keymap.normal_action(key) {
+                Some(UiAction::ShowResolved | UiAction::Quit) => UiAction::ShowResolved,
+                Some(UiAction::MoveDown | UiAction::ScrollPreviewDown) => UiAction::ScrollPreviewDown,
+                Some(UiAction::MoveUp | UiAction::ScrollPreviewUp) => UiAction::ScrollPreviewUp,
+                _ => UiAction::Noop,
+            },
+        },
+        Mode::Diagnostics { .. } => match key.code {
+            KeyCode::Esc => UiAction::ToggleDiagnostics,
+            _ => match keymap.normal_action(key) {
+                Some(UiAction::ToggleDiagnostics | UiAction::Quit) => UiAction::ToggleDiagnostics,
+                Some(UiAction::MoveDown | UiAction::ScrollPreviewDown) => UiAction::ScrollPreviewDown,
+                Some(UiAction::MoveUp | UiAction::ScrollPreviewUp) => UiAction::ScrollPreviewUp,
+                _ => UiAction::Noop,
+            },
+        },
+        Mode::MatchBlocks { .. } => match key.code {
+            KeyCode::Esc => UiAction::ShowMatchBlocks,
+            _ => match keymap.normal_action(key) {
+                Some(UiAction::ShowMatchBlocks | UiAction::Quit) => UiAction::ShowMatchBlocks,
+                Some(UiAction::MoveDown | UiAction::ScrollPreviewDown) => UiAction::ScrollPreviewDown,
+                Some(UiAction::MoveUp | UiAction::ScrollPreviewUp) => UiAction::ScrollPreviewUp,
+                _ => UiAction::Noop,
+            },
+        },
+        Mode::Confirm(_) => match key.code {
+            KeyCode::Enter => UiAction::ConfirmAccept,
+            KeyCode::Esc => UiAction::ClearFilter,
+            KeyCode::Char(c) => UiAction::InputChar(c),
+            _ => UiAction::Noop,
+        },
+        // Letters are filter input here, so only non-character keys act
+        Mode::Filter => keymap.filter_action(key).unwrap_or(match key.code {
+            KeyCode::Enter => UiAction::LaunchSelected,
+            KeyCode::Esc => UiAction::ClearFilter,
+            KeyCode::Backspace => UiAction::BackspaceFilter,
+            KeyCode::Down => UiAction::MoveDown,
+            KeyCode::Up => UiAction::MoveUp,
+            KeyCode::PageDown => UiAction::PageDown,
+            KeyCode::PageUp => UiAction::PageUp,
+            KeyCode::Char(c) => UiAction::InputChar(c),
+            _ => UiAction::Noop,
+        }),
+        _ => keymap.normal_action(key).unwrap_or(match key.code {
+            KeyCode::Char(c) => UiAction::InputChar(c),
+            _ => UiAction::Noop,
+        }),
+    }
+}
+
+/// Parse a color name (`yellow`, `light_blue`, `dark-gray`, …) or `#rrggbb` hex.
+pub fn parse_color(s: &str) -> Option<Color> {
+    let s = s.trim();
+    if let Some(hex) = s.strip_prefix('#') {
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        return Some(Color::Rgb(channel(0)?, channel(2)?, channel(4)?));
     }
+    let name: String = s.chars().filter(|c| !matches!(c, '_' | '-' | ' ')).collect();
+    Some(match name.to_ascii_lowercase().as_str() {
+        "black" => Color::Black,
+        "red" => Color::Red,
+        "green" => Color::Green,
+        "yellow" => Color::Yellow,
+        "blue" => Color::Blue,
+        "magenta" => Color::Magenta,
+        "cyan" => Color::Cyan,
+        "gray" | "grey" => Color::Gray,
+        "darkgray" | "darkgrey" => Color::DarkGray,
+        "lightred" => Color::LightRed,
+        "lightgreen" => Color::LightGreen,
+        "lightyellow" => Color::LightYellow,
+        "lightblue" => Color::LightBlue,
+        "lightmagenta" => Color::LightMagenta,
+        "lightcyan" => Color::LightCyan,
+        "white" => Color::White,
+        _ => return None,
+    })
 }
 
 // TUI forms now handled via modal overlays and integrated event handling
@@ -220,3 +1190,29 @@ fn centered_rect(percent_x: u16, percent_y: u16, area: Rect) -> Rect {
 
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit_pads_short_text_and_truncates_long_text() {
+        assert_eq!(fit("web", 5), "web  ");
+        assert_eq!(fit("web-prod", 5), "web-…");
+        assert_eq!(fit("web", 3), "web");
+        assert_eq!(fit("web", 0), "");
+    }
+
+    #[test]
+    fn scroll_offset_keeps_a_margin_around_the_selection() {
+        // 10 rows visible out of 100: moving down scrolls once within 2 rows of the bottom
+        assert_eq!(scroll_offset(0, 7, 10, 100), 0);
+        assert_eq!(scroll_offset(0, 8, 10, 100), 1);
+        // Moving back up keeps the offset until within 2 rows of the top
+        assert_eq!(scroll_offset(20, 22, 10, 100), 20);
+        assert_eq!(scroll_offset(20, 21, 10, 100), 19);
+        // Never scrolls past the end, and short lists never scroll
+        assert_eq!(scroll_offset(0, 99, 10, 100), 90);
+        assert_eq!(scroll_offset(5, 3, 10, 4), 0);
+    }
+}